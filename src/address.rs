@@ -1,214 +1,1175 @@
-use errors::{parse_ok, Error, ErrorKind, ParseResult};
-use std::io::Write;
-use atom::{parse_atom, parse_dot_atom};
-use whitespaces::{read_cfws, read_fws, replace_fws};
-use quoted_string::{parse_quoted_string, read_quoted_string};
-use common::parse_phrase as parse_display_name;
-use common::{is_obs_no_ws_ctl, parse_word};
+use alloc::borrow::Cow;
+use core::fmt;
+use core::ops::Range;
+use core::str::FromStr;
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
-pub struct Address {
-    local_part: Vec<u8>,
-    domain: Vec<u8>,
-}
+use atom::{invalid_dot_atom_text_position, is_dot_atom_text, parse_atom, starts_atext};
+use cst::{trim_cfws, Span};
+use common::{ascii_str, display, is_obs_no_ws_ctl, parse_all, parse_phrase, parse_word, utf8_non_ascii_len, write_phrase};
+use cow::parse_cow;
+use encoded_word::decode_encoded_words;
+use errors::{Error, ErrorKind, Token};
+use idna::{domain_to_ascii, domain_to_unicode};
+use prelude::*;
+use quoted_string::write_quoted_string;
+use sink::{Counter, Sink};
+use whitespaces::{skip_cfws, skip_fws};
+use Buffer;
 
-impl Address {
-    pub fn parse(&mut self, buf: &[u8]) -> Result<Self, Error> {
-        let mut local_part = Cursor::new(&mut self.local_part);
-        let (_, read) = parse_new_local_part(buf, &mut local_part).or_else(|e| {
-            match *e.kind() {
-                ErrorKind::Parsing => {
+/// Return `true` if the byte represents a "dtext" character, i.e. a character that can appear in
+/// a domain literal without being escaped. See [RFC5322 section 3.4.1].
+///
+/// ```no_rust
+/// dtext           =   %d33-90 /          ; Printable US-ASCII
+///                     %d94-126 /         ;  characters not including
+///                     obs-dtext          ;  "[", "]", or "\"
+/// ```
+///
+/// [RFC5322 section 3.4.1]: https://tools.ietf.org/html/rfc5322#section-3.4.1
+pub fn is_dtext(c: u8) -> bool {
+    (33..=90).contains(&c) || (94..=126).contains(&c)
+}
 
-                }
-            }
+/// Build the error returned when an unexpected byte is found at index `i`.
+fn error(bytes: &[u8], pos: usize, i: usize, token: Token) -> Error {
+    if i >= bytes.len() {
+        ErrorKind::Eof.into()
+    } else {
+        ErrorKind::Token {
+            token,
+            byte: bytes[i],
+            position: pos + i,
+        }.into()
     }
 }
 
-/// Parse the local part of an address as defined in
-/// [RFC5322 section 3.4.1](https://tools.ietf.org/html/rfc5322#section-3.4.1).
-///
+/// Parse the local part of an address, and write it into the provided writer. Quoted strings are
+/// unquoted, and CFWS is removed. See [RFC5322 section 3.4.1].
 ///
 /// ```no_rust
 /// local-part      =   dot-atom / quoted-string / obs-local-part
-/// dot-atom        =   [CFWS] dot-atom-text [CFWS]
-/// dot-atom-text   =   1*atext *("." 1*atext)
-/// atext           =   ALPHA / DIGIT /    ; Printable US-ASCII
-///                     "!" / "#" /        ;  characters not including
-///                     "$" / "%" /        ;  specials.  Used for atoms.
-///                     "&" / "'" /
-///                     "*" / "+" /
-///                     "-" / "/" /
-///                     "=" / "?" /
-///                     "^" / "_" /
-///                     "`" / "{" /
-///                     "|" / "}" /
-///                     "~"
 /// obs-local-part  =   word *("." word)
-/// word            =   atom / quoted-string
-/// atom            =   [CFWS] 1*atext [CFWS]
 /// ```
 ///
-/// `obs-local-part` is more generic than `dot-atom/quoted-string`. It allows three more things:
+/// `obs-local-part` is more generic than `dot-atom / quoted-string`, it allows:
 ///
 /// - mixing atoms and quoted strings: `atom."string".atom`
 /// - having whitespaces around dots `atom . another . atom`
 /// - a combination of both: `atom . " string " . atom`
 ///
-/// This parser parses the most laxist for (the `obs-local-part`), replacing any FWS or CFWS by a
-/// single space.
-fn parse_obsolete_local_part<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
-    let (_, word) = parse_word(buf, writer)?;
-    let mut i = word.len();
-    while i < buf.len() {
-        match parse_word(&buf[i..], writer) {
-            Ok((_, word)) => i += word.len(),
-            Err(e) => match *e.kind() {
-                ErrorKind::Parsing => break,
-                _ => return Err(e),
-            },
-        }
+/// [RFC5322 section 3.4.1]: https://tools.ietf.org/html/rfc5322#section-3.4.1
+pub fn parse_local_part<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+
+    let mut i = parse_word(&buffer, writer)?;
+    while i < bytes.len() && bytes[i] == b'.' {
+        writer.write_bytes(b".")?;
+        i += 1;
+        buffer.set_position(pos + i);
+        i += parse_word(&buffer, writer)?;
     }
-    parse_ok(buf, i)
+    Ok(i)
+}
+
+/// Parse the local part of an address, and return it, borrowed from the input unless it contains
+/// CFWS or quoted-pairs. See [`parse_local_part`].
+///
+/// [`parse_local_part`]: fn.parse_local_part.html
+pub fn parse_local_part_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_local_part)
 }
 
-/// Parse the local part of an address as defined in
-/// [RFC5322 section 3.4.1](https://tools.ietf.org/html/rfc5322#section-3.4.1).
+/// Parse a domain, and write it into the provided writer. CFWS is removed, and domain literals
+/// are written with their brackets. See [RFC5322 section 3.4.1].
 ///
 /// ```no_rust
-/// local-part      =   dot-atom / quoted-string / obs-local-part
-/// dot-atom        =   [CFWS] dot-atom-text [CFWS]
-/// dot-atom-text   =   1*atext *("." 1*atext)
-/// atext           =   ALPHA / DIGIT /    ; Printable US-ASCII
-///                     "!" / "#" /        ;  characters not including
-///                     "$" / "%" /        ;  specials.  Used for atoms.
-///                     "&" / "'" /
-///                     "*" / "+" /
-///                     "-" / "/" /
-///                     "=" / "?" /
-///                     "^" / "_" /
-///                     "`" / "{" /
-///                     "|" / "}" /
-///                     "~"
-/// obs-local-part  =   word *("." word)
-/// word            =   atom / quoted-string
-/// atom            =   [CFWS] 1*atext [CFWS]
+/// domain          =   dot-atom / domain-literal / obs-domain
+/// domain-literal  =   [CFWS] "[" *([FWS] dtext) [FWS] "]" [CFWS]
+/// obs-domain      =   atom *("." atom)
+/// obs-dtext       =   obs-NO-WS-CTL / quoted-pair
 /// ```
 ///
-/// `obs-local-part` is more generic than `dot-atom/quoted-string`. It allows three more things:
+/// [RFC5322 section 3.4.1]: https://tools.ietf.org/html/rfc5322#section-3.4.1
+pub fn parse_domain<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+
+    let i = skip_cfws(&buffer).unwrap_or(0);
+    if i >= bytes.len() {
+        return Err(ErrorKind::Eof.into());
+    }
+    if bytes[i] == b'[' {
+        return parse_domain_literal(input, writer);
+    }
+    if !starts_atext(input, bytes[i]) {
+        return Err(error(bytes, pos, i, Token::Domain));
+    }
+
+    let mut i = parse_atom(&buffer, writer)?;
+    while i < bytes.len() && bytes[i] == b'.' {
+        writer.write_bytes(b".")?;
+        i += 1;
+        buffer.set_position(pos + i);
+        i += parse_atom(&buffer, writer)?;
+    }
+    Ok(i)
+}
+
+/// Parse a domain, and return it, borrowed from the input unless it contains CFWS or
+/// quoted-pairs. See [`parse_domain`].
 ///
-/// - mixing atoms and quoted strings: `atom."string".atom`
-/// - having whitespaces around dots `atom . another . atom`
-/// - a combination of both: `atom . " string " . atom`
+/// [`parse_domain`]: fn.parse_domain.html
+pub fn parse_domain_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_domain)
+}
+
+/// Parse a domain literal. See [`parse_domain`].
 ///
-/// This parser parses the most strict form (the `dot-atom/quoted-string`), replacing any FWS or CFWS by a single space.
-fn parse_new_local_part<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
-    let (_, local_part) = parse_dot_atom(buf, writer).or_else(|e| match *e.kind() {
-        ErrorKind::Parsing => parse_quoted_string(buf, writer),
-        _ => Err(e),
-    })?;
-    parse_ok(buf, local_part.len())
-}
-
-// obs-domain      =   atom *("." atom)
-// obs-dtext       =   obs-NO-WS-CTL / quoted-pair
-// domain          =   dot-atom / domain-literal / obs-domain
-// domain-literal  =   [CFWS] "[" *([FWS] dtext) [FWS] "]" [CFWS]
-// dtext           =   %d33-90 /          ; Printable US-ASCII
-//                     %d94-126 /         ;  characters not including
-//                     obs-dtext          ;  "[", "]", or "\"
-fn parse_obsolete_domain<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
-    let (_, atom) = parse_atom(buf, writer)?;
-    let mut i = atom.len();
-    while i < buf.len() {
-        let (_, atom) = parse_atom(&buf[i..], writer)?;
-        i += atom.len();
-    }
-    parse_ok(buf, i)
-}
-
-// obs-domain      =   atom *("." atom)
-// obs-dtext       =   obs-NO-WS-CTL / quoted-pair
-// domain          =   dot-atom / domain-literal / obs-domain
-// domain-literal  =   [CFWS] "[" *([FWS] dtext) [FWS] "]" [CFWS]
-// dtext           =   %d33-90 /          ; Printable US-ASCII
-//                     %d94-126 /         ;  characters not including
-//                     obs-dtext          ;  "[", "]", or "\"
-fn parse_new_domain<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
-    match parse_dot_atom(buf, writer) {
-        Ok((_, dot_atom)) => parse_ok(buf, dot_atom.len()),
-        Err(e) => match *e.kind() {
-            ErrorKind::Parsing => parse_domain_literal(buf, writer),
-            _ => Err(e),
-        },
-    }
-}
-
-// obs-dtext       =   obs-NO-WS-CTL / quoted-pair
-// domain-literal  =   [CFWS] "[" *([FWS] dtext) [FWS] "]" [CFWS]
-// dtext           =   %d33-90 /          ; Printable US-ASCII
-//                     %d94-126 /         ;  characters not including
-//                     obs-dtext          ;  "[", "]", or "\"
-fn parse_domain_literal<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
+/// [`parse_domain`]: fn.parse_domain.html
+fn parse_domain_literal<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+
+    // read [CFWS] "["
+    let mut i = skip_cfws(&buffer).unwrap_or(0);
+    if i >= bytes.len() || bytes[i] != b'[' {
+        return Err(error(bytes, pos, i, Token::Domain));
+    }
+    writer.write_bytes(b"[")?;
+    i += 1;
+
+    // read *([FWS] dtext) [FWS] "]"
+    loop {
+        buffer.set_position(pos + i);
+        i += skip_fws(&buffer).unwrap_or(0);
+        if i >= bytes.len() {
+            return Err(ErrorKind::Eof.into());
+        }
+        if let Some(len) = utf8_non_ascii_len(input, i, Token::Domain)? {
+            // dtext =/ UTF8-non-ascii
+            writer.write_bytes(&bytes[i..i + len])?;
+            i += len;
+            continue;
+        }
+        match bytes[i] {
+            b']' => break,
+            c if is_dtext(c) || is_obs_no_ws_ctl(c) => {
+                writer.write_bytes(&[c])?;
+                i += 1;
+            }
+            // obs-dtext: quoted-pair
+            b'\\' if i + 1 < bytes.len() && bytes[i + 1] < 128 => {
+                writer.write_bytes(&bytes[i + 1..i + 2])?;
+                i += 2;
+            }
+            b'\\' => return Err(error(bytes, pos, i + 1, Token::Domain)),
+            _ => return Err(error(bytes, pos, i, Token::Domain)),
+        }
+    }
+    writer.write_bytes(b"]")?;
+    i += 1;
+
     // read [CFWS]
-    let mut i = if let Ok((_, cfws)) = read_cfws(buf) {
-        cfws.len()
+    buffer.set_position(pos + i);
+    Ok(i + skip_cfws(&buffer).unwrap_or(0))
+}
+
+/// Write a local part, quoting it if it is not a valid dot-atom. Non-ASCII UTF-8 characters are
+/// `atext` (see [`is_dot_atom_text`]), so a UTF-8 local part is only quoted if it would need to be
+/// quoted in US-ASCII.
+///
+/// [`is_dot_atom_text`]: ../atom/fn.is_dot_atom_text.html
+pub fn write_local_part<W: Sink>(local_part: &[u8], writer: &mut W) -> Result<(), Error> {
+    if is_dot_atom_text(local_part) {
+        writer.write_bytes(local_part)
     } else {
-        0
-    };
+        write_quoted_string(local_part, writer)
+    }
+}
 
-    // read "["
-    if i >= buf.len() || buf[i] != b'[' {
-        return Err(ErrorKind::Parsing.into());
+/// Write a domain as stored by [`parse_domain`]. In a domain literal, the characters that were
+/// unescaped from quoted-pairs (`[`, `]`, `\\` and whitespaces) are escaped again, so that the
+/// domain parses back to the same value. CR, LF and NUL cannot be written: an `ErrorKind::Token`
+/// error is returned for them.
+///
+/// [`parse_domain`]: fn.parse_domain.html
+fn write_domain<W: Sink>(domain: &[u8], writer: &mut W) -> Result<(), Error> {
+    if domain.len() < 2 || domain[0] != b'[' {
+        return writer.write_bytes(domain);
     }
-    i += 1;
-    // read *([FWS] dtext)
-    while i < buf.len() {
-        // read [FWS]
-        match replace_fws(&buf[i..], writer) {
-            Ok((_, fws)) => i += fws.len(),
-            Err(e) => match *e.kind() {
-                ErrorKind::Parsing => {}
-                _ => return Err(e),
-            },
-        }
-        // read dtext
-        match buf[i] {
-            // regular character
-            c if is_obs_no_ws_ctl(c) || (c >= 33 && c <= 90) || (c >= 94 && c <= 126) => {
-                writer.write_all(&[c][..])?;
+    let end = domain.len() - 1;
+    if let Some(i) = domain.iter().position(|c| *c == b'\r' || *c == b'\n' || *c == 0) {
+        return Err(error(domain, 0, i, Token::Domain));
+    }
+    writer.write_bytes(b"[")?;
+    for &c in &domain[1..end] {
+        if c == b'[' || c == b']' || c == b'\\' || c == b' ' || c == b'\t' {
+            writer.write_bytes(b"\\")?;
+        }
+        writer.write_bytes(&[c])?;
+    }
+    writer.write_bytes(b"]")
+}
+
+/// An email address (`addr-spec`). See [RFC5322 section 3.4.1].
+///
+/// ```no_rust
+/// addr-spec       =   local-part "@" domain
+/// ```
+///
+/// The local part is stored unquoted, and the domain is stored as written, without CFWS. Domain
+/// literals keep their brackets.
+///
+/// [RFC5322 section 3.4.1]: https://tools.ietf.org/html/rfc5322#section-3.4.1
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Address {
+    local_part: Vec<u8>,
+    domain: Vec<u8>,
+    span: Span,
+    local_part_span: Span,
+    domain_span: Span,
+}
+
+impl Address {
+    /// Create an address, checking that it can be written in a header field. The local part can
+    /// contain any printable US-ASCII character, space, or non-ASCII UTF-8 character (it is
+    /// quoted when necessary), and the domain must be a dot-atom (`example.com`, `例子.广告`) or
+    /// a domain literal (`[192.0.2.1]`). Addresses with UTF-8 can only be written in messages
+    /// that accept UTF-8 (see [RFC6532 section 3.2]).
+    ///
+    /// [RFC6532 section 3.2]: https://tools.ietf.org/html/rfc6532#section-3.2
+    pub fn new(local_part: &[u8], domain: &[u8]) -> Result<Self, Error> {
+        if local_part.is_empty() || domain.is_empty() {
+            return Err(ErrorKind::Eof.into());
+        }
+        let input = Buffer::new(local_part).utf8(true);
+        let mut i = 0;
+        while i < local_part.len() {
+            if (32..=126).contains(&local_part[i]) {
                 i += 1;
+            } else if let Ok(Some(len)) = utf8_non_ascii_len(&input, i, Token::Address) {
+                i += len;
+            } else {
+                return Err(error(local_part, 0, i, Token::Address));
+            }
+        }
+        if domain[0] == b'[' {
+            let end = domain.len() - 1;
+            match domain.iter().skip(1).position(|c| !is_dtext(*c)) {
+                Some(i) if i + 1 == end && domain[end] == b']' => {}
+                Some(i) => return Err(error(domain, 0, i + 1, Token::Domain)),
+                None => return Err(ErrorKind::Eof.into()),
             }
-            // quoted-pair
-            b'\\' => {
-                if i + 1 < buf.len() && buf[i + 1] >= 0 && buf[i + 1] < 127 {
-                    writer.write_all(&buf[i + 1..i + 2])?;
-                    i += 2;
-                } else {
-                    return Err(ErrorKind::Parsing.into());
+        } else if let Some(i) = invalid_dot_atom_text_position(domain) {
+            return Err(error(domain, 0, i, Token::Domain));
+        }
+        Ok(Address {
+            local_part: local_part.to_vec(),
+            domain: domain.to_vec(),
+            span: Span::default(),
+            local_part_span: Span::default(),
+            domain_span: Span::default(),
+        })
+    }
+
+    /// Parse an address. On success, the address and the number of bytes read are returned. The
+    /// obsolete syntax is accepted.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+
+        let mut local_part = Vec::new();
+        let mut i = parse_local_part(&buffer, &mut local_part)?;
+        if i >= bytes.len() || bytes[i] != b'@' {
+            return Err(error(bytes, pos, i, Token::Address));
+        }
+        let local_part_span = trim_cfws(&buffer, i);
+        i += 1;
+
+        let mut domain = Vec::new();
+        buffer.set_position(pos + i);
+        let len = parse_domain(&buffer, &mut domain)?;
+        let domain_span = trim_cfws(&buffer, len);
+        i += len;
+        let address = Address {
+            local_part,
+            domain,
+            span: Span::new(local_part_span.start..domain_span.end),
+            local_part_span: Span::new(local_part_span),
+            domain_span: Span::new(domain_span),
+        };
+        Ok((address, i))
+    }
+
+    /// Return the local part, unquoted.
+    pub fn local_part(&self) -> &[u8] {
+        &self.local_part
+    }
+
+    pub fn domain(&self) -> &[u8] {
+        &self.domain
+    }
+
+    /// Return the local part, unquoted, as a string slice: local parts are US-ASCII, or UTF-8 if
+    /// the address was parsed from a buffer that accepts UTF-8.
+    pub fn local_part_str(&self) -> &str {
+        ascii_str(&self.local_part)
+    }
+
+    /// Return the domain as a string slice: domains are US-ASCII, or UTF-8 if the address was
+    /// parsed from a buffer that accepts UTF-8.
+    pub fn domain_str(&self) -> &str {
+        ascii_str(&self.domain)
+    }
+
+    /// Return the domain with its internationalized labels written as A-labels (`xn--`), to
+    /// compare addresses or to write them for relays that do not support internationalized
+    /// messages. Domain literals are returned as is. See [`domain_to_ascii`].
+    ///
+    /// ```rust
+    /// use imf::address::Address;
+    /// use imf::Buffer;
+    ///
+    /// let (address, _) = Address::parse(&Buffer::new("用户@例子.广告".as_bytes()).utf8(true)).unwrap();
+    /// assert_eq!(address.domain_ascii().unwrap(), "xn--fsqu00a.xn--4rr70v");
+    /// ```
+    ///
+    /// [`domain_to_ascii`]: ../idna/fn.domain_to_ascii.html
+    pub fn domain_ascii(&self) -> Result<String, Error> {
+        if self.domain.starts_with(b"[") {
+            return Ok(self.domain_str().to_string());
+        }
+        domain_to_ascii(&self.domain)
+    }
+
+    /// Return the domain with its A-labels (`xn--`) decoded into U-labels. Domain literals are
+    /// returned as is. See [`domain_to_unicode`].
+    ///
+    /// [`domain_to_unicode`]: ../idna/fn.domain_to_unicode.html
+    pub fn domain_unicode(&self) -> Result<String, Error> {
+        if self.domain.starts_with(b"[") {
+            return Ok(self.domain_str().to_string());
+        }
+        domain_to_unicode(&self.domain)
+    }
+
+    /// Return the position of the address in the buffer it was parsed from, without the
+    /// surrounding CFWS. Addresses created with `new` have no span.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.get()
+    }
+
+    /// Return the position of the local part in the buffer it was parsed from, without the
+    /// surrounding CFWS, and including quotes if any.
+    pub fn local_part_span(&self) -> Option<Range<usize>> {
+        self.local_part_span.get()
+    }
+
+    /// Return the position of the domain in the buffer it was parsed from, without the
+    /// surrounding CFWS.
+    pub fn domain_span(&self) -> Option<Range<usize>> {
+        self.domain_span.get()
+    }
+
+    /// Write the address, quoting the local part if necessary, and escaping the characters of a
+    /// domain literal that were quoted-pairs. An address that was parsed from quoted-pairs of CR,
+    /// LF or NUL cannot be written: an `ErrorKind::Token` error is returned instead.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        write_local_part(&self.local_part, writer)?;
+        writer.write_bytes(b"@")?;
+        write_domain(&self.domain, writer)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::Address, Address::parse)
+    }
+}
+
+/// Skip an obsolete route. See [RFC5322 section 4.4].
+///
+/// ```no_rust
+/// obs-route       =   obs-domain-list ":"
+/// obs-domain-list =   *(CFWS / ",") "@" domain
+///                     *("," [CFWS] ["@" domain])
+/// ```
+///
+/// [RFC5322 section 4.4]: https://tools.ietf.org/html/rfc5322#section-4.4
+fn skip_obs_route(input: &Buffer) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+    let mut i: usize = 0;
+    let mut domains: usize = 0;
+    loop {
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+        if i >= bytes.len() {
+            return Err(ErrorKind::Eof.into());
+        }
+        match bytes[i] {
+            b',' => i += 1,
+            b'@' => {
+                i += 1;
+                buffer.set_position(pos + i);
+                i += parse_domain(&buffer, &mut Counter::new())?;
+                domains += 1;
+            }
+            b':' if domains > 0 => return Ok(i + 1),
+            _ => return Err(error(bytes, pos, i, Token::Address)),
+        }
+    }
+}
+
+/// Parse an angle address. See [RFC5322 section 3.4].
+///
+/// ```no_rust
+/// angle-addr      =   [CFWS] "<" addr-spec ">" [CFWS] /
+///                     obs-angle-addr
+/// obs-angle-addr  =   [CFWS] "<" obs-route addr-spec ">" [CFWS]
+/// ```
+///
+/// [RFC5322 section 3.4]: https://tools.ietf.org/html/rfc5322#section-3.4
+fn parse_angle_addr(input: &Buffer) -> Result<(Address, usize), Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+
+    let mut i = skip_cfws(&buffer).unwrap_or(0);
+    if i >= bytes.len() || bytes[i] != b'<' {
+        return Err(error(bytes, pos, i, Token::Address));
+    }
+    i += 1;
+
+    buffer.set_position(pos + i);
+    let j = i + skip_cfws(&buffer).unwrap_or(0);
+    if j < bytes.len() && (bytes[j] == b'@' || bytes[j] == b',') {
+        i += skip_obs_route(&buffer)?;
+        buffer.set_position(pos + i);
+    }
+    let (address, len) = Address::parse(&buffer)?;
+    i += len;
+
+    if i >= bytes.len() || bytes[i] != b'>' {
+        return Err(error(bytes, pos, i, Token::Address));
+    }
+    i += 1;
+    buffer.set_position(pos + i);
+    Ok((address, i + skip_cfws(&buffer).unwrap_or(0)))
+}
+
+/// Parse a display name, and decode the encoded-words it contains.
+fn parse_display_name(input: &Buffer) -> Result<(Vec<u8>, usize), Error> {
+    let mut display_name = Vec::new();
+    let len = parse_phrase(input, &mut display_name)?;
+    Ok((decode_encoded_words(&display_name), len))
+}
+
+/// A mailbox: an address, optionally with a display name. See [RFC5322 section 3.4].
+///
+/// ```no_rust
+/// mailbox         =   name-addr / addr-spec
+/// name-addr       =   [display-name] angle-addr
+/// display-name    =   phrase
+/// ```
+///
+/// The display name is stored unquoted, and its encoded-words are decoded (see
+/// [`decode_encoded_words`]).
+///
+/// [RFC5322 section 3.4]: https://tools.ietf.org/html/rfc5322#section-3.4
+/// [`decode_encoded_words`]: ../encoded_word/fn.decode_encoded_words.html
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Mailbox {
+    display_name: Option<Vec<u8>>,
+    address: Address,
+    span: Span,
+    display_name_span: Span,
+}
+
+impl Mailbox {
+    /// Create a mailbox. The display name can be any text: it is quoted or encoded when the
+    /// mailbox is written. An empty display name is ignored.
+    pub fn new(display_name: Option<&[u8]>, address: Address) -> Self {
+        Mailbox {
+            display_name: display_name.filter(|name| !name.is_empty()).map(|name| name.to_vec()),
+            address,
+            span: Span::default(),
+            display_name_span: Span::default(),
+        }
+    }
+
+    /// Parse a mailbox. On success, the mailbox and the number of bytes read are returned. The
+    /// obsolete syntax is accepted.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+
+        // name-addr without display name
+        let i = skip_cfws(&buffer).unwrap_or(0);
+        if i < bytes.len() && bytes[i] == b'<' {
+            let (address, len) = parse_angle_addr(&buffer)?;
+            let mut mailbox = Mailbox::new(None, address);
+            mailbox.span = Span::new(trim_cfws(input, len));
+            return Ok((mailbox, len));
+        }
+
+        // name-addr with a display name
+        if let Ok((display_name, len)) = parse_display_name(&buffer) {
+            if len < bytes.len() && bytes[len] == b'<' {
+                buffer.set_position(pos + len);
+                let (address, address_len) = parse_angle_addr(&buffer)?;
+                let mut mailbox = Mailbox::new(Some(&display_name), address);
+                mailbox.span = Span::new(trim_cfws(input, len + address_len));
+                mailbox.display_name_span = Span::new(trim_cfws(input, len));
+                return Ok((mailbox, len + address_len));
+            }
+        }
+
+        // addr-spec
+        buffer.set_position(pos);
+        let (address, len) = Address::parse(&buffer)?;
+        let mut mailbox = Mailbox::new(None, address);
+        mailbox.span = mailbox.address.span.clone();
+        Ok((mailbox, len))
+    }
+
+    pub fn display_name(&self) -> Option<&[u8]> {
+        self.display_name.as_ref().map(|name| &name[..])
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Return the position of the mailbox in the buffer it was parsed from, without the
+    /// surrounding CFWS. Mailboxes created with `new` have no span.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.get()
+    }
+
+    /// Return the position of the display name in the buffer it was parsed from, without the
+    /// surrounding CFWS.
+    pub fn display_name_span(&self) -> Option<Range<usize>> {
+        self.display_name_span.get()
+    }
+
+    /// Write the mailbox. If there is a display name, it is written as a phrase (see
+    /// [`write_phrase`]), followed by the address in angle brackets.
+    ///
+    /// [`write_phrase`]: ../common/fn.write_phrase.html
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        match self.display_name {
+            Some(ref display_name) => {
+                write_phrase(display_name, writer)?;
+                writer.write_bytes(b" <")?;
+                self.address.write_to(writer)?;
+                writer.write_bytes(b">")?;
+            }
+            None => self.address.write_to(writer)?,
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Mailbox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for Mailbox {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::Address, Mailbox::parse)
+    }
+}
+
+/// A named group of mailboxes. See [RFC5322 section 3.4].
+///
+/// ```no_rust
+/// group           =   display-name ":" [group-list] ";" [CFWS]
+/// group-list      =   mailbox-list / CFWS / obs-group-list
+/// obs-group-list  =   1*([CFWS] ",") [CFWS]
+/// ```
+///
+/// [RFC5322 section 3.4]: https://tools.ietf.org/html/rfc5322#section-3.4
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Group {
+    display_name: Vec<u8>,
+    mailboxes: Vec<Mailbox>,
+    span: Span,
+    display_name_span: Span,
+}
+
+impl Group {
+    pub fn new(display_name: &[u8], mailboxes: Vec<Mailbox>) -> Self {
+        Group {
+            display_name: display_name.to_vec(),
+            mailboxes,
+            span: Span::default(),
+            display_name_span: Span::default(),
+        }
+    }
+
+    /// Parse a group. On success, the group and the number of bytes read are returned.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+
+        let (display_name, mut i) = parse_display_name(&buffer)?;
+        if i >= bytes.len() || bytes[i] != b':' {
+            return Err(error(bytes, pos, i, Token::Address));
+        }
+        let display_name_span = Span::new(trim_cfws(input, i));
+        i += 1;
+
+        buffer.set_position(pos + i);
+        let (mailboxes, len) = parse_list(&buffer, Mailbox::parse)?;
+        i += len;
+        if i >= bytes.len() || bytes[i] != b';' {
+            return Err(error(bytes, pos, i, Token::Address));
+        }
+        i += 1;
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+
+        let group = Group {
+            display_name,
+            mailboxes,
+            span: Span::new(trim_cfws(input, i)),
+            display_name_span,
+        };
+        Ok((group, i))
+    }
+
+    pub fn display_name(&self) -> &[u8] {
+        &self.display_name
+    }
+
+    pub fn mailboxes(&self) -> &[Mailbox] {
+        &self.mailboxes
+    }
+
+    /// Return the position of the group in the buffer it was parsed from, without the
+    /// surrounding CFWS. Groups created with `new` have no span.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.get()
+    }
+
+    /// Return the position of the display name in the buffer it was parsed from, without the
+    /// surrounding CFWS.
+    pub fn display_name_span(&self) -> Option<Range<usize>> {
+        self.display_name_span.get()
+    }
+
+    /// Write the group: its display name, followed by its mailboxes separated by commas.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        write_phrase(&self.display_name, writer)?;
+        writer.write_bytes(b":")?;
+        for (i, mailbox) in self.mailboxes.iter().enumerate() {
+            writer.write_bytes(if i == 0 { b" " } else { b", " })?;
+            mailbox.write_to(writer)?;
+        }
+        writer.write_bytes(b";")?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for Group {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::Address, Group::parse)
+    }
+}
+
+/// An element of an address list: a mailbox or a group. See [RFC5322 section 3.4].
+///
+/// ```no_rust
+/// address         =   mailbox / group
+/// ```
+///
+/// [RFC5322 section 3.4]: https://tools.ietf.org/html/rfc5322#section-3.4
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum MailboxOrGroup {
+    Mailbox(Mailbox),
+    Group(Group),
+}
+
+impl MailboxOrGroup {
+    /// Parse a mailbox or a group. On success, the element and the number of bytes read are
+    /// returned.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        if let Ok((_, len)) = parse_display_name(input) {
+            if len < bytes.len() && bytes[len] == b':' {
+                let (group, len) = Group::parse(input)?;
+                return Ok((MailboxOrGroup::Group(group), len));
+            }
+        }
+        let (mailbox, len) = Mailbox::parse(input)?;
+        Ok((MailboxOrGroup::Mailbox(mailbox), len))
+    }
+
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        match *self {
+            MailboxOrGroup::Mailbox(ref mailbox) => mailbox.write_to(writer),
+            MailboxOrGroup::Group(ref group) => group.write_to(writer),
+        }
+    }
+
+    /// Return the position of the mailbox or group in the buffer it was parsed from.
+    pub fn span(&self) -> Option<Range<usize>> {
+        match *self {
+            MailboxOrGroup::Mailbox(ref mailbox) => mailbox.span(),
+            MailboxOrGroup::Group(ref group) => group.span(),
+        }
+    }
+}
+
+impl fmt::Display for MailboxOrGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for MailboxOrGroup {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::Address, MailboxOrGroup::parse)
+    }
+}
+
+/// Parse a comma separated list of addresses. Empty elements are accepted, as in the obsolete
+/// syntax. Parsing stops before the first byte that is neither a comma nor the start of an element,
+/// and the list may be empty. Errors are given the context of the element where they occured.
+fn parse_list<T, F>(input: &Buffer, parse: F) -> Result<(Vec<T>, usize), Error>
+where
+    F: Fn(&Buffer) -> Result<(T, usize), Error>,
+{
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+    let mut elements = Vec::new();
+    let mut i: usize = 0;
+
+    loop {
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+        if i >= bytes.len() {
+            break;
+        }
+        match bytes[i] {
+            b',' => i += 1,
+            b';' | b'>' | b':' => break,
+            _ => {
+                buffer.set_position(pos + i);
+                let (element, len) = parse(&buffer).map_err(|e| e.context(Token::Address, pos + i))?;
+                elements.push(element);
+                i += len;
+                if i < bytes.len() && bytes[i] != b',' {
+                    break;
                 }
             }
-            // if we can't parse dtext, break out of the loop
-            _ => break,
         }
     }
-    // read [CFWS]
-    if let Ok((_, cfws)) = read_cfws(&buf[i..]) {
-        i += cfws.len();
+    Ok((elements, i))
+}
+
+/// Parse a mailbox list, as found in the `From` field. On success, the mailboxes and the number
+/// of bytes read are returned. See [RFC5322 section 3.4].
+///
+/// ```no_rust
+/// mailbox-list    =   (mailbox *("," mailbox)) / obs-mbox-list
+/// obs-mbox-list   =   *([CFWS] ",") mailbox *("," [mailbox / CFWS])
+/// ```
+///
+/// [RFC5322 section 3.4]: https://tools.ietf.org/html/rfc5322#section-3.4
+pub fn parse_mailbox_list(input: &Buffer) -> Result<(Vec<Mailbox>, usize), Error> {
+    let (mailboxes, len) = parse_list(input, Mailbox::parse)?;
+    if mailboxes.is_empty() {
+        let bytes = input.remaining();
+        return Err(error(bytes, input.position(), len, Token::Address));
+    }
+    Ok((mailboxes, len))
+}
+
+/// Parse an address list, as found in the `To` and `Cc` fields. On success, the mailboxes and
+/// groups, and the number of bytes read are returned. See [RFC5322 section 3.4].
+///
+/// ```no_rust
+/// address-list    =   (address *("," address)) / obs-addr-list
+/// obs-addr-list   =   *([CFWS] ",") address *("," [address / CFWS])
+/// ```
+///
+/// [RFC5322 section 3.4]: https://tools.ietf.org/html/rfc5322#section-3.4
+pub fn parse_address_list(input: &Buffer) -> Result<(Vec<MailboxOrGroup>, usize), Error> {
+    let (addresses, len) = parse_list(input, MailboxOrGroup::parse)?;
+    if addresses.is_empty() {
+        let bytes = input.remaining();
+        return Err(error(bytes, input.position(), len, Token::Address));
     }
+    Ok((addresses, len))
+}
 
-    // read "]" [CFWS]
-    if i < buf.len() && buf[i] == b']' {
-        if let Ok((_, cfws)) = read_cfws(&buf[i..]) {
-            i += cfws.len();
+/// Write a list of elements separated by commas.
+fn write_list<T, W, F>(elements: &[T], writer: &mut W, write: F) -> Result<(), Error>
+where
+    W: Sink,
+    F: Fn(&T, &mut W) -> Result<(), Error>,
+{
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 {
+            writer.write_bytes(b", ")?;
         }
-        parse_ok(buf, i)
-    } else {
-        Err(ErrorKind::Parsing.into())
+        write(element, writer)?;
     }
+    Ok(())
 }
 
-mod test {
-    fn test_parse_domain
+/// Write a mailbox list, as found in the `From` field: the mailboxes separated by commas.
+pub fn write_mailbox_list<W: Sink>(mailboxes: &[Mailbox], writer: &mut W) -> Result<(), Error> {
+    write_list(mailboxes, writer, Mailbox::write_to)
 }
 
+/// Write an address list, as found in the `To` and `Cc` fields: the mailboxes and groups separated
+/// by commas.
+pub fn write_address_list<W: Sink>(addresses: &[MailboxOrGroup], writer: &mut W) -> Result<(), Error> {
+    write_list(addresses, writer, MailboxOrGroup::write_to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::error::Error as StdError;
+
+    fn address(input: &[u8]) -> (Vec<u8>, Vec<u8>, usize) {
+        let (address, len) = Address::parse(&Buffer::new(input)).unwrap();
+        (address.local_part().to_vec(), address.domain().to_vec(), len)
+    }
+
+    fn address_len(input: &Buffer) -> Result<usize, Error> {
+        Address::parse(input).map(|(_, len)| len)
+    }
+
+    fn mailbox(input: &[u8]) -> (Option<Vec<u8>>, Vec<u8>, usize) {
+        let (mailbox, len) = Mailbox::parse(&Buffer::new(input)).unwrap();
+        let mut address = Vec::new();
+        mailbox.address().write_to(&mut address).unwrap();
+        (mailbox.display_name().map(|name| name.to_vec()), address, len)
+    }
+
+    fn mailbox_len(input: &Buffer) -> Result<usize, Error> {
+        Mailbox::parse(input).map(|(_, len)| len)
+    }
+
+    fn written<F: Fn(&mut Vec<u8>) -> Result<(), Error>>(write: F) -> Vec<u8> {
+        let mut output = Vec::new();
+        write(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(address(b"john@example.com"), (b"john".to_vec(), b"example.com".to_vec(), 16));
+        assert_eq!(address(b"john.q.public@example.com>"), (b"john.q.public".to_vec(), b"example.com".to_vec(), 25));
+        assert_eq!(address(b"\"john smith\"@example.com"), (b"john smith".to_vec(), b"example.com".to_vec(), 24));
+        assert_eq!(address(b"\"a\\\"b\"@[192.0.2.1]"), (b"a\"b".to_vec(), b"[192.0.2.1]".to_vec(), 18));
+        // obsolete syntax
+        assert_eq!(
+            address(b" john (comment) . \"q\" . public @ example . com (comment)"),
+            (b"john.q.public".to_vec(), b"example.com".to_vec(), 56)
+        );
+        assert_eq!(address(b"a@[ 192.0.2.1 ]"), (b"a".to_vec(), b"[192.0.2.1]".to_vec(), 15));
+        eof!(address_len, b"john");
+        eof!(address_len, b"john@");
+        eof!(address_len, b"john@[192.0.2.1");
+        tok!(address_len, b"john smith@example.com", Token::Address, b's', 5);
+        tok!(address_len, b"john.@example.com", Token::Word, b'@', 5);
+        tok!(address_len, b"john@.com", Token::Domain, b'.', 5);
+        tok!(address_len, b"john@[a[b]", Token::Domain, b'[', 7);
+    }
+
+    #[test]
+    fn test_new_address() {
+        let address = Address::new(b"john smith", b"example.com").unwrap();
+        assert_eq!((address.local_part_str(), address.domain_str()), ("john smith", "example.com"));
+        assert_eq!(written(|w| address.write_to(w)), b"\"john smith\"@example.com");
+        let address = Address::new(b"a\"b\\c", b"[192.0.2.1]").unwrap();
+        assert_eq!(written(|w| address.write_to(w)), b"\"a\\\"b\\\\c\"@[192.0.2.1]");
+        assert_eq!(Address::parse(b!(written(|w| address.write_to(w)))).unwrap().0, address);
+
+        assert!(Address::new(b"", b"example.com").unwrap_err().is_eof());
+        assert!(Address::new(b"john", b"[192.0.2.1").unwrap_err().is_eof());
+        assert!(Address::new(b"john", b"[a]b]").unwrap_err().is_token());
+        let e = Address::new(b"john\r\n", b"example.com").unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::Address, b'\r', 4));
+        }
+        for &(domain, byte, position) in &[(&b"example..com"[..], b'.', 8), (b".com", b'.', 0), (b"com.", b'.', 3), (b"a b", b' ', 1)] {
+            let e = Address::new(b"john", domain).unwrap_err();
+            if let ErrorKind::Token { token, byte: b, position: p } = *e.kind() {
+                assert_eq!((token, b, p), (Token::Domain, byte, position));
+            } else {
+                panic!("unexpected error {:?}", e);
+            }
+        }
+    }
 
+    #[test]
+    fn test_parse_mailbox() {
+        assert_eq!(mailbox(b"john@example.com"), (None, b"john@example.com".to_vec(), 16));
+        assert_eq!(mailbox(b"<john@example.com>"), (None, b"john@example.com".to_vec(), 18));
+        assert_eq!(
+            mailbox(b"John Smith <john@example.com>, other"),
+            (Some(b"John Smith".to_vec()), b"john@example.com".to_vec(), 29)
+        );
+        assert_eq!(
+            mailbox(b"\"Smith, John\" (comment) <john@example.com> (comment)"),
+            (Some(b"Smith, John".to_vec()), b"john@example.com".to_vec(), 52)
+        );
+        assert_eq!(
+            mailbox(b"=?utf-8?q?Andr=C3=A9?= Pirard <andre@example.com>"),
+            (Some("André Pirard".as_bytes().to_vec()), b"andre@example.com".to_vec(), 49)
+        );
+        // obsolete route
+        assert_eq!(
+            mailbox(b"Joe <@route.example, @other.example:joe@example.com>"),
+            (Some(b"Joe".to_vec()), b"joe@example.com".to_vec(), 52)
+        );
+        eof!(mailbox_len, b"John Smith <john@example.com");
+        tok!(mailbox_len, b"John Smith", Token::Address, b'S', 5);
+        tok!(mailbox_len, b"John <john@example.com)", Token::Address, b')', 22);
+    }
+
+    #[test]
+    fn test_write_mailbox() {
+        let address = Address::new(b"john", b"example.com").unwrap();
+        for &(name, expected) in &[
+            (None, &b"john@example.com"[..]),
+            (Some(&b"John Smith"[..]), b"John Smith <john@example.com>"),
+            (Some(b"Smith, John"), b"\"Smith, John\" <john@example.com>"),
+            (Some("André".as_bytes()), b"=?utf-8?b?QW5kcsOp?= <john@example.com>"),
+        ] {
+            let mailbox = Mailbox::new(name, address.clone());
+            let output = written(|w| mailbox.write_to(w));
+            assert_eq!(output, expected);
+            assert_eq!(Mailbox::parse(&Buffer::new(&output)).unwrap(), (mailbox, output.len()));
+        }
+        assert_eq!(Mailbox::new(Some(b""), address).display_name(), None);
+    }
+
+    #[test]
+    fn test_parse_address_list() {
+        let input = b"Mary Smith <mary@x.test>, jdoe@example.org, Who? <one@y.test>";
+        let (addresses, len) = parse_address_list(b!(input)).unwrap();
+        assert_eq!(len, input.len());
+        assert_eq!(addresses.len(), 3);
+
+        let input = b"A Group:Ed Jones <c@a.test>,joe@where.test,John <jdoe@one.test>;, Undisclosed recipients:;";
+        let (addresses, len) = parse_address_list(b!(input)).unwrap();
+        assert_eq!(len, input.len());
+        match addresses[0] {
+            MailboxOrGroup::Group(ref group) => {
+                assert_eq!(group.display_name(), b"A Group");
+                assert_eq!(group.mailboxes().len(), 3);
+                assert_eq!(
+                    written(|w| group.write_to(w)),
+                    &b"A Group: Ed Jones <c@a.test>, joe@where.test, John <jdoe@one.test>;"[..]
+                );
+            }
+            _ => panic!("expected a group"),
+        }
+        match addresses[1] {
+            MailboxOrGroup::Group(ref group) => {
+                assert_eq!(group.display_name(), b"Undisclosed recipients");
+                assert!(group.mailboxes().is_empty());
+                assert_eq!(written(|w| group.write_to(w)), b"Undisclosed recipients:;");
+            }
+            _ => panic!("expected a group"),
+        }
+
+        // obsolete list, with empty elements
+        let (mailboxes, len) = parse_mailbox_list(b!(b", a@b.test,, (comment) ,c@d.test,")).unwrap();
+        assert_eq!(mailboxes.len(), 2);
+        assert_eq!(len, 33);
+
+        let e = parse_mailbox_list(b!(b" , ;")).unwrap_err();
+        assert!(e.is_token());
+        assert!(parse_address_list(b!(b"")).unwrap_err().is_eof());
+        assert!(parse_address_list(b!(b"a@b.test, c@")).unwrap_err().is_eof());
+    }
+
+    #[test]
+    fn test_list_error_context() {
+        let input = b"a@b.test, Team: c@d.test, \"e\\\xff\"@f.test;, g@h.test";
+        let e = parse_address_list(b!(input)).unwrap_err();
+        let chain: Vec<String> = e.chain().map(|e| e.to_string()).collect();
+        assert_eq!(
+            chain,
+            vec![
+                "failed to parse address at position 10",
+                "failed to parse address at position 26",
+                "unexpected byte at position 29 while parsing quoted-pair",
+            ]
+        );
+        assert_eq!(e.root_cause().position(), Some(29));
+
+        // the chain is also available through the standard trait
+        let source = StdError::source(&e).unwrap();
+        assert_eq!(source.to_string(), "failed to parse address at position 26");
+        assert!(source.source().unwrap().source().is_none());
+    }
+
+    #[test]
+    fn test_display_address() {
+        let address: Address = " \"john\" (comment) @ example.com ".parse().unwrap();
+        assert_eq!(address.to_string(), "john@example.com");
+        let address: Address = "\"john smith\"@[192.0.2.1]".parse().unwrap();
+        assert_eq!(address.to_string(), "\"john smith\"@[192.0.2.1]");
+        let e = "john@example.com>".parse::<Address>().unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::Address, b'>', 16));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+
+        let mailbox: Mailbox = "John  (the man)  Smith <@route.example:john@example.com>".parse().unwrap();
+        assert_eq!(mailbox.to_string(), "John Smith <john@example.com>");
+        let mailbox: Mailbox = "\"Smith, John\" <john@example.com>".parse().unwrap();
+        assert_eq!(mailbox.to_string().parse::<Mailbox>().unwrap(), mailbox);
+
+        let group: Group = "Team : a@example.com , , b@example.com ; (end)".parse().unwrap();
+        assert_eq!(group.to_string(), "Team: a@example.com, b@example.com;");
+        let element: MailboxOrGroup = "Undisclosed recipients:;".parse().unwrap();
+        assert_eq!(element.to_string(), "Undisclosed recipients:;");
+
+        let (addresses, _) = parse_address_list(b!(b"a@example.com,,Team:b@example.com;")).unwrap();
+        let mut output = Vec::new();
+        write_address_list(&addresses, &mut output).unwrap();
+        assert_eq!(output, b"a@example.com, Team: b@example.com;");
+        let mailboxes = vec![mailbox.clone(), mailbox];
+        let mut output = Vec::new();
+        write_mailbox_list(&mailboxes, &mut output).unwrap();
+        assert_eq!(parse_mailbox_list(b!(output)).unwrap().0, mailboxes);
+    }
+
+    #[test]
+    fn test_utf8_address() {
+        let (address, len) = Address::parse(u!(" 用户@例子.广告 (x)")).unwrap();
+        assert_eq!((address.local_part_str(), address.domain_str(), len), ("用户", "例子.广告", 25));
+        assert_eq!(address.span(), Some(1..21));
+        assert_eq!(address.domain_span(), Some(8..21));
+        assert_eq!(address.to_string(), "用户@例子.广告");
+        assert_eq!(Address::parse(u!(address.to_string())).unwrap().0, address);
+        let (address, _) = Address::parse(u!("\"José Pérez\"@[例子]")).unwrap();
+        assert_eq!((address.local_part_str(), address.domain_str()), ("José Pérez", "[例子]"));
+        assert_eq!(address.to_string(), "\"José Pérez\"@[例子]");
+
+        let address = Address::new("用户".as_bytes(), "例子.广告".as_bytes()).unwrap();
+        assert_eq!(address.to_string(), "用户@例子.广告");
+        let e = Address::new(b"caf\xe9", b"example.com").unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::Address, 0xe9, 3));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+        assert!(Address::new(b"john", b"caf\xe9.example").unwrap_err().is_token());
+        tok!(address_len, "用户@例子.广告", Token::Word, 0xe7, 0);
+        enc!(address_len, b"a@b\xff", Token::Atext, 3);
+        enc!(address_len, b"a@[\xff]", Token::Domain, 3);
+    }
+
+    #[test]
+    fn test_idna_domain() {
+        let (address, _) = Address::parse(u!("用户@例子.广告")).unwrap();
+        assert_eq!(address.domain_ascii().unwrap(), "xn--fsqu00a.xn--4rr70v");
+        assert_eq!(address.domain_unicode().unwrap(), "例子.广告");
+        let address = Address::new(b"john", b"xn--bcher-kva.example").unwrap();
+        assert_eq!(address.domain_unicode().unwrap(), "bücher.example");
+        assert_eq!(address.domain_ascii().unwrap(), "xn--bcher-kva.example");
+        let address = Address::new(b"john", b"[192.0.2.1]").unwrap();
+        assert_eq!(address.domain_ascii().unwrap(), "[192.0.2.1]");
+        assert_eq!(address.domain_unicode().unwrap(), "[192.0.2.1]");
+        let address = Address::new(b"john", b"xn--bcher-kv.example").unwrap();
+        assert_eq!(address.domain_unicode().unwrap_err().position(), Some(12));
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = b"To: (x) John \"Q\" Public <@route:john.public @ (y) example.com>, Team: \"a b\"@[1.2.3.4];";
+        let buffer = Buffer::with_offset(input, 3);
+        let (addresses, _) = parse_address_list(&buffer).unwrap();
+        let text = |span: Option<Range<usize>>| &input[span.unwrap()];
+
+        let mailbox = match addresses[0] {
+            MailboxOrGroup::Mailbox(ref mailbox) => mailbox,
+            _ => panic!("expected a mailbox"),
+        };
+        assert_eq!(text(mailbox.span()), &b"John \"Q\" Public <@route:john.public @ (y) example.com>"[..]);
+        assert_eq!(text(mailbox.display_name_span()), &b"John \"Q\" Public"[..]);
+        assert_eq!(text(mailbox.address().span()), &b"john.public @ (y) example.com"[..]);
+        assert_eq!(text(mailbox.address().local_part_span()), b"john.public");
+        assert_eq!(text(mailbox.address().domain_span()), b"example.com");
+
+        assert_eq!(text(addresses[1].span()), &b"Team: \"a b\"@[1.2.3.4];"[..]);
+        if let MailboxOrGroup::Group(ref group) = addresses[1] {
+            assert_eq!(text(group.display_name_span()), b"Team");
+            let address = group.mailboxes()[0].address();
+            assert_eq!(text(address.local_part_span()), b"\"a b\"");
+            assert_eq!(text(address.domain_span()), b"[1.2.3.4]");
+            assert_eq!(text(group.mailboxes()[0].span()), &b"\"a b\"@[1.2.3.4]"[..]);
+        } else {
+            panic!("expected a group");
+        }
+
+        // spans are ignored when comparing
+        let address = Address::new(b"john.public", b"example.com").unwrap();
+        assert_eq!(address.span(), None);
+        assert_eq!(mailbox.address(), &address);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        for input in &["\"a\\\"b\\\\c\"@example.com", "\"a\\ b\\x\"@example.com", "a@[a\\]b]", "a@[\\[1.2.3.4\\] \\ x\\\\]"] {
+            let address = input.parse::<Address>().unwrap();
+            let written = address.to_string();
+            assert_eq!(written.parse::<Address>().unwrap(), address, "{}", written);
+        }
+        assert_eq!("a@[a\\]b]".parse::<Address>().unwrap().to_string(), "a@[a\\]b]");
+        assert_eq!("\"a\\x\"@example.com".parse::<Address>().unwrap().to_string(), "ax@example.com");
+
+        // CR, LF and NUL can be parsed from quoted-pairs, but not written
+        let address = "\"a\\\rb\"@example.com".parse::<Address>().unwrap();
+        let e = address.write_to(&mut Vec::new()).unwrap_err();
+        assert!(matches!(*e.kind(), ErrorKind::Token { token: Token::QuotedString, byte: b'\r', position: 1 }));
+        let address = "a@[a\\\nb]".parse::<Address>().unwrap();
+        let e = address.write_to(&mut Vec::new()).unwrap_err();
+        assert!(matches!(*e.kind(), ErrorKind::Token { token: Token::Domain, byte: b'\n', position: 2 }));
+    }
+}
@@ -1,19 +1,11 @@
-use std::io::Write;
+use alloc::borrow::Cow;
 
-use errors::{parse_ok, ErrorKind, ParseResult};
-use whitespaces::read_cfws;
-
-/// Return true if the byte represents an alphabetical character (`a-zA-Z`)
-fn is_alphabetical(c: u8) -> bool {
-    // (c >= 65 && c <= 90) || (c >= 97 && c <= 122)
-    (c >= b'A' && c <= b'Z') || (c >= b'a' && c <= b'z')
-}
-
-/// Return true if the byte represents a digit (`0-9`)
-fn is_digit(c: u8) -> bool {
-    // c >= 48 && c <= 57
-    c >= b'0' && c <= b'9'
-}
+use common::utf8_non_ascii_len;
+use cow::parse_cow;
+use errors::{Error, ErrorKind, Token};
+use sink::{Counter, Sink};
+use whitespaces::skip_cfws;
+use Buffer;
 
 /// Return true if the byte represents an "atext" token.
 ///
@@ -33,9 +25,9 @@ fn is_digit(c: u8) -> bool {
 ///
 /// See [RFC5322 section 3.2.3].
 ///
-/// [RFC2822 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
-fn is_atext(c: u8) -> bool {
-    is_alphabetical(c) || is_digit(c) ||
+/// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
+pub fn is_atext(c: u8) -> bool {
+    c.is_ascii_alphanumeric() ||
         c == b'!' || c == b'#' ||
         c == b'$' || c == b'%' ||
         c == b'&' || c == b'\'' ||
@@ -48,151 +40,276 @@ fn is_atext(c: u8) -> bool {
         c == b'~'
 }
 
-fn is_dot_atom(c: u8) -> bool {
-    is_atext(c) || c == b'.'
+/// Return `true` if the byte can start an `atext` character of the given buffer: an ASCII
+/// `atext`, or any non-ASCII byte if the buffer accepts UTF-8 (see [RFC6532 section 3.2]).
+///
+/// ```no_rust
+/// atext           =/  UTF8-non-ascii
+/// ```
+///
+/// [RFC6532 section 3.2]: https://tools.ietf.org/html/rfc6532#section-3.2
+pub fn starts_atext(input: &Buffer, c: u8) -> bool {
+    is_atext(c) || (c >= 0x80 && input.accepts_utf8())
 }
 
-pub fn read_atom(buf: &[u8]) -> ParseResult {
-    let mut i: usize = 0;
-    if let Ok((_, cfws)) = read_cfws(buf) {
-        i += cfws.len();
+/// Return the length of the `atext` character at index `i` of the remaining bytes, or `None` if
+/// there is none.
+fn atext_len(input: &Buffer, i: usize) -> Result<Option<usize>, Error> {
+    match input.remaining().get(i) {
+        Some(c) if is_atext(*c) => Ok(Some(1)),
+        Some(_) => utf8_non_ascii_len(input, i, Token::Atext),
+        None => Ok(None),
     }
-
-    let (_, atom) = read_atom_text(&buf[i..])?;
-    i += atom.len();
-
-    if let Ok((_, cfws)) = read_cfws(&buf[i..]) {
-        i += cfws.len();
-    }
-    parse_ok(buf, i)
 }
 
-pub fn parse_atom<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
-    let mut i: usize = 0;
-    if let Ok((_, cfws)) = read_cfws(buf) {
-        i += cfws.len();
-    }
-
-    let (_, atom) = read_atom_text(&buf[i..])?;
-    writer.write_all(atom)?;
-    i += atom.len();
-
-    if let Ok((_, cfws)) = read_cfws(&buf[i..]) {
-        i += cfws.len();
-    }
-    parse_ok(buf, i)
+/// Return `true` if the given bytes form a valid `dot-atom-text`, i.e. they can be written as is
+/// in a structured field, without quoting. Non-ASCII UTF-8 characters are `atext`, as in a buffer
+/// that accepts UTF-8 (see [RFC6532 section 3.2]).
+///
+/// ```no_rust
+/// dot-atom-text   =   1*atext *("." 1*atext)
+/// ```
+///
+/// [RFC6532 section 3.2]: https://tools.ietf.org/html/rfc6532#section-3.2
+pub fn is_dot_atom_text(bytes: &[u8]) -> bool {
+    invalid_dot_atom_text_position(bytes).is_none()
 }
 
-fn read_dot_atom(buf: &[u8]) -> ParseResult {
-    let mut i: usize = 0;
-    if let Ok((_, cfws)) = read_cfws(buf) {
-        i += cfws.len();
-    }
-
-    let (_, atom) = read_dot_atom_text(&buf[i..])?;
-    i += atom.len();
-
-    if let Ok((_, cfws)) = read_cfws(&buf[i..]) {
-        i += cfws.len();
+/// Return the index of the first byte that prevents the given bytes from being a valid
+/// `dot-atom-text`, or `None` if they are valid. The index is the length of the bytes if they are
+/// empty. Non-ASCII UTF-8 characters are `atext`, but invalid UTF-8 is not.
+pub fn invalid_dot_atom_text_position(bytes: &[u8]) -> Option<usize> {
+    let input = Buffer::new(bytes).utf8(true);
+    let mut i = 0;
+    loop {
+        // read 1*atext
+        let start = i;
+        while let Ok(Some(len)) = atext_len(&input, i) {
+            i += len;
+        }
+        if i == start {
+            // an empty atom is reported at its leading dot if the bytes end with a dot
+            return Some(if i > 0 && i == bytes.len() { i - 1 } else { i });
+        }
+        match bytes.get(i) {
+            None => return None,
+            Some(b'.') => i += 1,
+            Some(_) => return Some(i),
+        }
     }
-    parse_ok(buf, i)
 }
 
-pub fn parse_dot_atom<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
+/// Read `1*atext`.
+pub fn skip_atom_text(input: &Buffer) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    if bytes.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
     let mut i: usize = 0;
-    if let Ok((_, cfws)) = read_cfws(buf) {
-        i += cfws.len();
+    while let Some(len) = atext_len(input, i)? {
+        i += len;
     }
-
-    let (_, atom) = read_dot_atom_text(&buf[i..])?;
-    i += atom.len();
-    writer.write_all(atom)?;
-
-    if let Ok((_, cfws)) = read_cfws(&buf[i..]) {
-        i += cfws.len();
+    if i == 0 {
+        return Err(ErrorKind::Token {
+            token: Token::Atext,
+            byte: bytes[0],
+            position: input.position(),
+        }.into());
     }
-    parse_ok(buf, i)
+    Ok(i)
 }
 
-fn read_dot_atom_text(buf: &[u8]) -> ParseResult {
+/// Read `dot-atom-text`. A trailing dot, or two consecutive dots, end the `dot-atom-text`.
+///
+/// ```no_rust
+/// dot-atom-text   =   1*atext *("." 1*atext)
+/// ```
+pub fn skip_dot_atom_text(input: &Buffer) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    if bytes.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
     let mut i: usize = 0;
-    while i < buf.len() {
-        if is_atext(buf[i]) {
+    while i < bytes.len() {
+        if let Some(len) = atext_len(input, i)? {
+            i += len;
+        } else if bytes[i] == b'.' && i > 0 && i + 1 < bytes.len() && starts_atext(input, bytes[i + 1]) {
             i += 1;
-        } else if buf[i] == b'.' {
-            if i + 1 < buf.len() && is_atext(buf[i + 1]) {
-                i += 2;
-            } else {
-                break;
-            }
         } else {
             break;
         }
     }
     if i == 0 {
-        return Err(ErrorKind::Parsing.into());
+        return Err(ErrorKind::Token {
+            token: Token::DotAtom,
+            byte: bytes[0],
+            position: input.position(),
+        }.into());
     }
-    parse_ok(buf, i)
+    Ok(i)
 }
 
-fn read_atom_text(buf: &[u8]) -> ParseResult {
-    let mut i: usize = 0;
-    while i < buf.len() && is_atext(buf[i]) {
-        i += 1;
-    }
-    if i == 0 {
-        return Err(ErrorKind::Parsing.into());
-    }
-    parse_ok(buf, i)
+/// Parse `[CFWS] text [CFWS]`, where `text` is read by the given function, and write the text
+/// (without the CFWS) into the provided writer.
+fn parse_with_cfws<W: Sink, F>(input: &Buffer, writer: &mut W, skip_text: F) -> Result<usize, Error>
+where
+    F: Fn(&Buffer) -> Result<usize, Error>,
+{
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+
+    // read [CFWS]
+    let mut i = skip_cfws(&buffer).unwrap_or(0);
+    buffer.set_position(pos + i);
+
+    // read the text
+    let len = skip_text(&buffer)?;
+    writer.write_bytes(&bytes[i..i + len])?;
+    i += len;
+    buffer.set_position(pos + i);
+
+    // read [CFWS]
+    Ok(i + skip_cfws(&buffer).unwrap_or(0))
+}
+
+/// Parse an atom, and write it (without the surrounding CFWS) into the provided writer. See
+/// [RFC5322 section 3.2.3].
+///
+/// ```no_rust
+/// atom            =   [CFWS] 1*atext [CFWS]
+/// ```
+///
+/// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
+pub fn parse_atom<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    parse_with_cfws(input, writer, skip_atom_text)
+}
+
+/// Parse an atom, and return it, borrowed from the input. See [`parse_atom`].
+///
+/// [`parse_atom`]: fn.parse_atom.html
+pub fn parse_atom_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_atom)
 }
 
-mod test {
+/// Read an atom. See [RFC5322 section 3.2.3].
+///
+/// ```no_rust
+/// atom            =   [CFWS] 1*atext [CFWS]
+/// ```
+///
+/// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
+pub fn skip_atom(input: &Buffer) -> Result<usize, Error> {
+    parse_atom(input, &mut Counter::new())
+}
+
+/// Parse a dot-atom, and write it (without the surrounding CFWS) into the provided writer. See
+/// [RFC5322 section 3.2.3].
+///
+/// ```no_rust
+/// dot-atom        =   [CFWS] dot-atom-text [CFWS]
+/// dot-atom-text   =   1*atext *("." 1*atext)
+/// ```
+///
+/// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
+pub fn parse_dot_atom<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    parse_with_cfws(input, writer, skip_dot_atom_text)
+}
+
+/// Parse a dot-atom, and return it, borrowed from the input. See [`parse_dot_atom`].
+///
+/// [`parse_dot_atom`]: fn.parse_dot_atom.html
+pub fn parse_dot_atom_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_dot_atom)
+}
+
+/// Read a dot-atom. See [RFC5322 section 3.2.3].
+///
+/// ```no_rust
+/// dot-atom        =   [CFWS] dot-atom-text [CFWS]
+/// ```
+///
+/// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
+pub fn skip_dot_atom(input: &Buffer) -> Result<usize, Error> {
+    parse_dot_atom(input, &mut Counter::new())
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
+    use prelude::*;
+
+    fn atom(input: &Buffer) -> Result<(Vec<u8>, usize), Error> {
+        let mut atom = Vec::new();
+        let len = parse_atom(input, &mut atom)?;
+        Ok((atom, len))
+    }
 
-    fn test_read<F>(f: F, input: &[u8], exp_left: &[u8], exp_read: &[u8])
-    where
-        F: Fn(&[u8]) -> ParseResult,
-    {
-        let (left, read) = f(input).unwrap();
-        assert_eq!(read, exp_read);
-        assert_eq!(left, exp_left);
+    fn dot_atom(input: &Buffer) -> Result<(Vec<u8>, usize), Error> {
+        let mut atom = Vec::new();
+        let len = parse_dot_atom(input, &mut atom)?;
+        Ok((atom, len))
     }
 
     #[test]
-    fn test_read_atom() {
-        let f = read_atom;
-        test_read(f, &b"a"[..], &b""[..], &b"a"[..]);
-        test_read(f, &b"abc"[..], &b""[..], &b"abc"[..]);
-        test_read(f, &b"\r\n\tabc "[..], &b""[..], &b"\r\n\tabc "[..]);
-        test_read(
-            f,
-            &b"!#$%&'*+-/=?^_`{}|~."[..],
-            &b"."[..],
-            &b"!#$%&'*+-/=?^_`{}|~"[..],
+    fn test_atom() {
+        assert_eq!(atom(b!(b"a")).unwrap(), (b"a".to_vec(), 1));
+        assert_eq!(atom(b!(b"abc")).unwrap(), (b"abc".to_vec(), 3));
+        assert_eq!(atom(b!(b"\r\n\tabc ")).unwrap(), (b"abc".to_vec(), 7));
+        assert_eq!(atom(b!(b" (comment) abc (comment)")).unwrap(), (b"abc".to_vec(), 24));
+        assert_eq!(
+            atom(b!(b"!#$%&'*+-/=?^_`{}|~.")).unwrap(),
+            (b"!#$%&'*+-/=?^_`{}|~".to_vec(), 19)
         );
+        ok!(skip_atom, b"abc.def", 3);
+        eof!(skip_atom, b"");
+        eof!(skip_atom, b" ");
+        tok!(skip_atom, b"@abc", Token::Atext, b'@', 0);
+        tok!(skip_atom, b" (comment) @abc", Token::Atext, b'@', 11);
     }
 
     #[test]
-    fn test_read_dot_atom() {
-        let f = read_dot_atom;
-        test_read(f, &b"a"[..], &b""[..], &b"a"[..]);
-        test_read(f, &b"abc"[..], &b""[..], &b"abc"[..]);
-        test_read(f, &b"\r\n\tabc "[..], &b""[..], &b"\r\n\tabc "[..]);
-        test_read(
-            f,
-            &b"!#$%&'*+-/=?^_`{}|~."[..],
-            &b"."[..],
-            &b"!#$%&'*+-/=?^_`{}|~"[..],
+    fn test_dot_atom() {
+        assert_eq!(dot_atom(b!(b"a.b")).unwrap(), (b"a.b".to_vec(), 3));
+        assert_eq!(dot_atom(b!(b"\r\n\tabc.abc ")).unwrap(), (b"abc.abc".to_vec(), 11));
+        assert_eq!(
+            dot_atom(b!(b"!#$%&'*+-/=?^_`{}|~.abc")).unwrap(),
+            (b"!#$%&'*+-/=?^_`{}|~.abc".to_vec(), 23)
         );
+        ok!(skip_dot_atom, b"abc.", 3);
+        ok!(skip_dot_atom, b"abc..def", 3);
+        ok!(skip_dot_atom, b"abc@def", 3);
+        tok!(skip_dot_atom, b".abc", Token::DotAtom, b'.', 0);
+    }
 
-        test_read(f, &b"a.b"[..], &b""[..], &b"a.b"[..]);
-        test_read(f, &b"abc.abc"[..], &b""[..], &b"abc.abc"[..]);
-        test_read(f, &b"\r\n\tabc.abc "[..], &b""[..], &b"\r\n\tabc.abc "[..]);
-        test_read(
-            f,
-            &b"!#$%&'*+-/=?^_`{}|~.abc"[..],
-            &b""[..],
-            &b"!#$%&'*+-/=?^_`{}|~.abc"[..],
-        );
+    #[test]
+    fn test_utf8_atom() {
+        assert_eq!(atom(u!("用户 ")).unwrap(), ("用户".as_bytes().to_vec(), 7));
+        assert_eq!(dot_atom(u!("例子.广告")).unwrap(), ("例子.广告".as_bytes().to_vec(), 13));
+        tok!(skip_atom, "é", Token::Atext, 0xc3, 0);
+        // a lone continuation byte, a truncated sequence, an overlong form and a surrogate
+        enc!(skip_atom, b"a\x80", Token::Atext, 1);
+        enc!(skip_atom, b"a\xc3", Token::Atext, 1);
+        enc!(skip_atom, b"\xc0\xaf", Token::Atext, 0);
+        enc!(skip_dot_atom, b"a.\xed\xa0\x80", Token::Atext, 2);
+    }
+
+    #[test]
+    fn test_is_dot_atom_text() {
+        assert!(is_dot_atom_text(b"john.smith"));
+        assert!(is_dot_atom_text(b"a+b"));
+        assert!(!is_dot_atom_text(b""));
+        assert!(!is_dot_atom_text(b"john..smith"));
+        assert!(!is_dot_atom_text(b".john"));
+        assert!(!is_dot_atom_text(b"john smith"));
+        assert!(is_dot_atom_text("用户.例子".as_bytes()));
+        assert!(!is_dot_atom_text(b"caf\xe9"));
+
+        assert_eq!(invalid_dot_atom_text_position(b"john.smith"), None);
+        assert_eq!(invalid_dot_atom_text_position(b""), Some(0));
+        assert_eq!(invalid_dot_atom_text_position(b"john..smith"), Some(5));
+        assert_eq!(invalid_dot_atom_text_position(b"john."), Some(4));
+        assert_eq!(invalid_dot_atom_text_position(b"john smith"), Some(4));
+        assert_eq!(invalid_dot_atom_text_position(b"caf\xc3\xa9 \xff"), Some(5));
     }
 }
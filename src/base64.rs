@@ -0,0 +1,378 @@
+#[cfg(feature = "std")]
+use std::io;
+
+use errors::Error;
+use sink::Sink;
+use whitespaces::CRLF;
+use Buffer;
+
+/// Padding character
+pub static PAD: u8 = b'=';
+
+/// Maximum length of an encoded line, line break excluded. See [RFC2045 section 6.8].
+///
+/// [RFC2045 section 6.8]: https://tools.ietf.org/html/rfc2045#section-6.8
+pub static MAX_LINE_LENGTH: usize = 76;
+
+static ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Return the 6 bits value represented by the given byte, or `None` if the byte is not part of the
+/// base64 alphabet. See [RFC2045 section 6.8].
+///
+/// ```no_rust
+/// Value Encoding  Value Encoding  Value Encoding  Value Encoding
+///     0 A            17 R            34 i            51 z
+///     1 B            18 S            35 j            52 0
+///   ...             ...             ...             ...
+///    15 P            32 g            49 x
+///    16 Q            33 h            50 y            (pad) =
+/// ```
+///
+/// [RFC2045 section 6.8]: https://tools.ietf.org/html/rfc2045#section-6.8
+fn decode_char(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// A streaming base64 decoder. Encoded bytes written into the decoder are decoded and written
+/// into the underlying writer as soon as a full quantum (4 encoded characters) is available.
+///
+/// The decoder is lenient:
+///
+/// - any byte that is not part of the base64 alphabet (whitespaces, line breaks, garbage) is
+///   ignored, as required by [RFC2045 section 6.8].
+/// - padding terminates the current quantum, but decoding resumes after it, so that
+///   concatenated base64 blobs are decoded correctly.
+/// - an incomplete quantum at the end of the data is decoded as if it were padded.
+///
+/// [`finish`](#method.finish) must be called once all the data has been written, in order to
+/// flush the last incomplete quantum.
+///
+/// [RFC2045 section 6.8]: https://tools.ietf.org/html/rfc2045#section-6.8
+pub struct Base64Decoder<W: Sink> {
+    writer: W,
+    quantum: [u8; 4],
+    len: usize,
+}
+
+impl<W: Sink> Base64Decoder<W> {
+    pub fn new(writer: W) -> Self {
+        Base64Decoder {
+            writer,
+            quantum: [0; 4],
+            len: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Decode the last incomplete quantum, and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.flush_quantum()?;
+        Ok(self.writer)
+    }
+
+    /// Decode the bytes available in the current quantum. A quantum of a single character only
+    /// carries 6 bits, which is not enough for a byte, so it is dropped.
+    fn flush_quantum(&mut self) -> Result<(), Error> {
+        let q = &self.quantum;
+        let decoded = [
+            q[0] << 2 | q[1] >> 4,
+            q[1] << 4 | q[2] >> 2,
+            q[2] << 6 | q[3],
+        ];
+        let len = match self.len {
+            0 | 1 => 0,
+            n => n - 1,
+        };
+        self.len = 0;
+        self.quantum = [0; 4];
+        self.writer.write_bytes(&decoded[..len])
+    }
+
+    fn decode(&mut self, buf: &[u8]) -> Result<(), Error> {
+        for &c in buf {
+            if c == PAD {
+                self.flush_quantum()?;
+                continue;
+            }
+            if let Some(value) = decode_char(c) {
+                self.quantum[self.len] = value;
+                self.len += 1;
+                if self.len == 4 {
+                    self.flush_quantum()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Sink> io::Write for Base64Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.decode(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.writer.flush_sink()?)
+    }
+}
+
+impl<W: Sink> Sink for Base64Decoder<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.decode(bytes)
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        self.writer.flush_sink()
+    }
+}
+
+/// Decode base64 encoded content and write the decoded bytes into the provided writer. The whole
+/// input is consumed, and the number of bytes read is returned. See [`Base64Decoder`] for the
+/// details about how invalid content is handled.
+///
+/// [`Base64Decoder`]: struct.Base64Decoder.html
+pub fn decode_base64<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let mut decoder = Base64Decoder::new(writer);
+    decoder.decode(bytes)?;
+    decoder.finish()?;
+    Ok(bytes.len())
+}
+
+/// A streaming base64 encoder. Bytes written into the encoder are encoded and written into the
+/// underlying writer, with a CRLF line break inserted every [`MAX_LINE_LENGTH`] characters by
+/// default. No line break is written after the last line.
+///
+/// [`finish`](#method.finish) must be called once all the data has been written, in order to
+/// encode and pad the last incomplete quantum.
+///
+/// [`MAX_LINE_LENGTH`]: static.MAX_LINE_LENGTH.html
+pub struct Base64Encoder<W: Sink> {
+    writer: W,
+    quantum: [u8; 3],
+    len: usize,
+    line_length: usize,
+    max_line_length: usize,
+}
+
+impl<W: Sink> Base64Encoder<W> {
+    pub fn new(writer: W) -> Self {
+        Base64Encoder {
+            writer,
+            quantum: [0; 3],
+            len: 0,
+            line_length: 0,
+            max_line_length: MAX_LINE_LENGTH,
+        }
+    }
+
+    /// Set the maximum length of the encoded lines. It is rounded down to a multiple of 4, so that
+    /// lines always contain full quanta, and it is at least 4. A length of 0 disables line breaks.
+    pub fn line_length(mut self, length: usize) -> Self {
+        self.max_line_length = match length {
+            0 => 0,
+            1..=3 => 4,
+            _ => length - length % 4,
+        };
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Encode and pad the last incomplete quantum, and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.len > 0 {
+            self.flush_quantum()?;
+        }
+        Ok(self.writer)
+    }
+
+    fn flush_quantum(&mut self) -> Result<(), Error> {
+        if self.max_line_length > 0 && self.line_length >= self.max_line_length {
+            self.writer.write_bytes(&CRLF)?;
+            self.line_length = 0;
+        }
+        let q = &self.quantum;
+        let mut encoded = [
+            ALPHABET[(q[0] >> 2) as usize],
+            ALPHABET[((q[0] & 0x03) << 4 | q[1] >> 4) as usize],
+            ALPHABET[((q[1] & 0x0f) << 2 | q[2] >> 6) as usize],
+            ALPHABET[(q[2] & 0x3f) as usize],
+        ];
+        for c in encoded.iter_mut().skip(self.len + 1) {
+            *c = PAD;
+        }
+        self.len = 0;
+        self.quantum = [0; 3];
+        self.line_length += 4;
+        self.writer.write_bytes(&encoded)
+    }
+
+    fn encode(&mut self, buf: &[u8]) -> Result<(), Error> {
+        for &c in buf {
+            self.quantum[self.len] = c;
+            self.len += 1;
+            if self.len == 3 {
+                self.flush_quantum()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Sink> io::Write for Base64Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encode(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.writer.flush_sink()?)
+    }
+}
+
+impl<W: Sink> Sink for Base64Encoder<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.encode(bytes)
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        self.writer.flush_sink()
+    }
+}
+
+/// Encode the given content in base64, and write the encoded bytes into the provided writer,
+/// folding lines at [`MAX_LINE_LENGTH`] characters. The whole input is consumed, and the number of
+/// bytes read is returned.
+///
+/// [`MAX_LINE_LENGTH`]: static.MAX_LINE_LENGTH.html
+pub fn encode_base64<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let mut encoder = Base64Encoder::new(writer);
+    encoder.encode(bytes)?;
+    encoder.finish()?;
+    Ok(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prelude::*;
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        assert_eq!(decode_base64(b!(input), &mut output).unwrap(), input.len());
+        output
+    }
+
+    fn encode(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        assert_eq!(encode_base64(b!(input), &mut output).unwrap(), input.len());
+        output
+    }
+
+    #[test]
+    fn test_encode_base64() {
+        assert_eq!(encode(b""), b"");
+        assert_eq!(encode(b"f"), b"Zg==");
+        assert_eq!(encode(b"fo"), b"Zm8=");
+        assert_eq!(encode(b"foo"), b"Zm9v");
+        assert_eq!(encode(b"foob"), b"Zm9vYg==");
+        assert_eq!(encode(b"fooba"), b"Zm9vYmE=");
+        assert_eq!(encode(b"foobar"), b"Zm9vYmFy");
+        assert_eq!(encode(b"\xff\xfe\xfd\xfc"), b"//79/A==");
+    }
+
+    #[test]
+    fn test_encode_base64_line_length() {
+        let input = [0u8; 114];
+        let output = encode(&input[..]);
+        let lines: Vec<&[u8]> = output.split(|c| *c == b'\n').collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), 77);
+        assert!(lines[0].ends_with(b"\r"));
+        assert_eq!(lines[1].len(), 76);
+
+        let mut encoder = Base64Encoder::new(Vec::new()).line_length(10);
+        encoder.write_bytes(b"foobarfoobar").unwrap();
+        assert_eq!(encoder.finish().unwrap(), b"Zm9vYmFy\r\nZm9vYmFy");
+
+        let mut encoder = Base64Encoder::new(Vec::new()).line_length(3);
+        encoder.write_bytes(b"foobar").unwrap();
+        assert_eq!(encoder.finish().unwrap(), b"Zm9v\r\nYmFy");
+
+        let mut encoder = Base64Encoder::new(Vec::new()).line_length(0);
+        encoder.write_bytes(&input[..]).unwrap();
+        assert_eq!(encoder.finish().unwrap().len(), 152);
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        let input: Vec<u8> = (0..1000).map(|i| (i * 7 % 256) as u8).collect();
+        assert_eq!(decode(&encode(&input)), input);
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode(b""), b"");
+        assert_eq!(decode(b"Zg=="), b"f");
+        assert_eq!(decode(b"Zm8="), b"fo");
+        assert_eq!(decode(b"Zm9v"), b"foo");
+        assert_eq!(decode(b"Zm9vYg=="), b"foob");
+        assert_eq!(decode(b"Zm9vYmE="), b"fooba");
+        assert_eq!(decode(b"Zm9vYmFy"), b"foobar");
+        assert_eq!(decode(b"//79/A=="), b"\xff\xfe\xfd\xfc");
+    }
+
+    #[test]
+    fn test_decode_base64_lenient() {
+        // line breaks and whitespaces
+        assert_eq!(decode(b"Zm9v\r\nYmFy\r\n"), b"foobar");
+        assert_eq!(decode(b" Zm 9v\tYm\nFy "), b"foobar");
+        // garbage
+        assert_eq!(decode(b"Zm9v!YmFy*"), b"foobar");
+        // missing padding
+        assert_eq!(decode(b"Zm9vYg"), b"foob");
+        assert_eq!(decode(b"Zm9vYmE"), b"fooba");
+        // a lone trailing character does not carry enough bits
+        assert_eq!(decode(b"Zm9vY"), b"foo");
+        // concatenated blobs
+        assert_eq!(decode(b"Zg==Zm8="), b"ffo");
+    }
+
+    #[test]
+    fn test_decode_base64_streaming() {
+        let mut decoder = Base64Decoder::new(Vec::new());
+        for chunk in [&b"Zm"[..], b"9", b"vYm", b"F", b"y"].iter() {
+            decoder.write_bytes(chunk).unwrap();
+        }
+        assert_eq!(decoder.get_ref(), b"foobar");
+        let mut decoder = Base64Decoder::new(Vec::new());
+        decoder.write_bytes(b"Zm9vYg").unwrap();
+        assert_eq!(decoder.get_ref(), b"foo");
+        assert_eq!(decoder.finish().unwrap(), b"foob");
+    }
+}
@@ -1,6 +1,8 @@
 pub struct Buffer<'buf> {
     inner: &'buf [u8],
     position: usize,
+    /// Whether non-ASCII UTF-8 text is accepted, see `utf8`
+    utf8: bool,
 }
 
 impl<'buf> Clone for Buffer<'buf> {
@@ -8,6 +10,7 @@ impl<'buf> Clone for Buffer<'buf> {
         Buffer {
             inner: self.inner,
             position: self.position,
+            utf8: self.utf8,
         }
     }
 }
@@ -17,6 +20,7 @@ impl<'buf> Buffer<'buf> {
         Buffer {
             inner: buf,
             position: 0,
+            utf8: false,
         }
     }
 
@@ -24,10 +28,36 @@ impl<'buf> Buffer<'buf> {
         Buffer {
             inner: buf,
             position: offset,
+            utf8: false,
         }
     }
 
-    pub fn remaining(&self) -> &[u8] {
+    /// Accept non-ASCII UTF-8 characters in atoms, quoted strings, comments, domain literals and
+    /// unstructured field values, as internationalized messages do (see [RFC6532 section 3.2]).
+    /// Malformed UTF-8 then results in an `Encoding` error. Buffers only accept US-ASCII by
+    /// default, and buffers created by cloning this one accept the same text.
+    ///
+    /// ```rust
+    /// use imf::address::Address;
+    /// use imf::Buffer;
+    ///
+    /// let input = "用户@例子.广告".as_bytes();
+    /// assert!(Address::parse(&Buffer::new(input)).is_err());
+    /// let (address, _) = Address::parse(&Buffer::new(input).utf8(true)).unwrap();
+    /// assert_eq!(address.domain_str(), "例子.广告");
+    /// ```
+    ///
+    /// [RFC6532 section 3.2]: https://tools.ietf.org/html/rfc6532#section-3.2
+    pub fn utf8(mut self, accept: bool) -> Self {
+        self.utf8 = accept;
+        self
+    }
+
+    pub fn accepts_utf8(&self) -> bool {
+        self.utf8
+    }
+
+    pub fn remaining(&self) -> &'buf [u8] {
         &self.inner[self.position..]
     }
 
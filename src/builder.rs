@@ -0,0 +1,930 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use address::{write_address_list, write_mailbox_list, Group, Mailbox, MailboxOrGroup};
+use date_time::DateTime;
+use errors::Error;
+use folding::{write_structured_field, write_unstructured_field};
+use message::check_field_name;
+use message_id::{write_message_ids, MessageId};
+use mime::{ContentDisposition, ContentType, DispositionKind};
+use sink::Sink;
+use transfer_encoding::{choose_encoding, encode, TransferEncoding};
+use Buffer;
+
+impl From<Mailbox> for MailboxOrGroup {
+    fn from(mailbox: Mailbox) -> Self {
+        MailboxOrGroup::Mailbox(mailbox)
+    }
+}
+
+impl From<Group> for MailboxOrGroup {
+    fn from(group: Group) -> Self {
+        MailboxOrGroup::Group(group)
+    }
+}
+
+/// Convert line breaks into CRLF, as required for text in canonical form. See [RFC2046 section
+/// 4.1.1].
+///
+/// [RFC2046 section 4.1.1]: https://tools.ietf.org/html/rfc2046#section-4.1.1
+fn normalize_line_breaks(text: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(text.len());
+    for (i, &c) in text.iter().enumerate() {
+        if c == b'\n' && (i == 0 || text[i - 1] != b'\r') {
+            normalized.push(b'\r');
+        }
+        normalized.push(c);
+    }
+    normalized
+}
+
+/// Return `true` if `needle` occurs in `haystack`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Generate a random multipart boundary. Boundaries start with `=_`, which can appear neither in
+/// base64 nor in quoted-printable content.
+fn generate_boundary() -> Vec<u8> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("=_{:016x}", hasher.finish()).into_bytes()
+}
+
+/// Return the media type of a text part, with the charset `us-ascii` or `utf-8` depending on the
+/// content.
+fn text_content_type(subtype: &[u8], text: &[u8]) -> ContentType {
+    let charset: &[u8] = if text.is_ascii() { b"us-ascii" } else { b"utf-8" };
+    ContentType::new(b"text", subtype).with_parameter(b"charset", charset)
+}
+
+/// Return the identity encoding that describes the content: `7bit` if [`choose_encoding`] allows
+/// it, `8bit` if the content has no NUL, no bare CR or LF, and no line longer than 998 bytes, and
+/// `binary` otherwise. See [RFC2045 section 2.7].
+///
+/// [`choose_encoding`]: ../transfer_encoding/fn.choose_encoding.html
+/// [RFC2045 section 2.7]: https://tools.ietf.org/html/rfc2045#section-2.7
+fn identity_encoding(content: &[u8]) -> TransferEncoding {
+    if choose_encoding(content) == TransferEncoding::SevenBit {
+        return TransferEncoding::SevenBit;
+    }
+    let is_eight_bit = content.iter().enumerate().all(|(i, &c)| match c {
+        0 => false,
+        b'\r' => content.get(i + 1) == Some(&b'\n'),
+        b'\n' => i > 0 && content[i - 1] == b'\r',
+        _ => true,
+    }) && content.split(|c| *c == b'\n').all(|line| line.len() - usize::from(line.ends_with(b"\r")) <= 998);
+    if is_eight_bit {
+        TransferEncoding::EightBit
+    } else {
+        TransferEncoding::Binary
+    }
+}
+
+/// A MIME field name, with its value.
+type MimeField = (&'static [u8], Vec<u8>);
+
+/// A part which content has been encoded, ready to be written.
+#[derive(Clone, Debug)]
+struct EncodedPart {
+    content_type: ContentType,
+    encoding: TransferEncoding,
+    /// The `Content-ID` and `Content-Disposition` fields, if any
+    fields: Vec<MimeField>,
+    body: Vec<u8>,
+}
+
+impl EncodedPart {
+    /// Encode content. Text is encoded with the transfer encoding chosen by `choose_encoding`.
+    /// Messages and multipart bodies are written as is, with an identity encoding, since they
+    /// cannot be encoded (see [RFC2046 section 5.1] and [RFC2046 section 5.2.1]). Other content is
+    /// encoded with base64, so that it is transported unchanged.
+    ///
+    /// [RFC2046 section 5.1]: https://tools.ietf.org/html/rfc2046#section-5.1
+    /// [RFC2046 section 5.2.1]: https://tools.ietf.org/html/rfc2046#section-5.2.1
+    fn new(content_type: ContentType, content: &[u8]) -> Result<Self, Error> {
+        let mime_type = content_type.mime_type();
+        let encoding = if mime_type.eq_ignore_ascii_case(b"text") {
+            choose_encoding(content)
+        } else if mime_type.eq_ignore_ascii_case(b"message") || mime_type.eq_ignore_ascii_case(b"multipart") {
+            identity_encoding(content)
+        } else {
+            TransferEncoding::Base64
+        };
+        let mut body = Vec::new();
+        encode(encoding, &Buffer::new(content), &mut body)?;
+        Ok(EncodedPart {
+            content_type,
+            encoding,
+            fields: Vec::new(),
+            body,
+        })
+    }
+
+    /// Join parts into a multipart entity. Boundaries are produced by `generate` until one of them
+    /// does not occur in any of the parts.
+    fn multipart<F>(content_type: ContentType, parts: &[EncodedPart], mut generate: F) -> Result<Self, Error>
+    where
+        F: FnMut() -> Vec<u8>,
+    {
+        let mut written = Vec::with_capacity(parts.len());
+        for part in parts {
+            let mut output = Vec::new();
+            part.write_to(&mut output)?;
+            written.push(output);
+        }
+        let boundary = loop {
+            let boundary = generate();
+            let mut dash_boundary = b"--".to_vec();
+            dash_boundary.extend_from_slice(&boundary);
+            if !written.iter().any(|part| contains(part, &dash_boundary)) {
+                break boundary;
+            }
+        };
+
+        let mut body = Vec::new();
+        for part in &written {
+            body.extend_from_slice(b"--");
+            body.extend_from_slice(&boundary);
+            body.extend_from_slice(b"\r\n");
+            body.extend_from_slice(part);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--");
+        body.extend_from_slice(&boundary);
+        body.extend_from_slice(b"--\r\n");
+        Ok(EncodedPart {
+            content_type: content_type.with_parameter(b"boundary", &boundary),
+            encoding: TransferEncoding::SevenBit,
+            fields: Vec::new(),
+            body,
+        })
+    }
+
+    /// Return `true` if the part can be sent without any MIME field.
+    fn is_plain_text(&self) -> bool {
+        self.content_type == ContentType::default()
+            && self.encoding == TransferEncoding::SevenBit
+            && self.fields.is_empty()
+    }
+
+    /// Return the MIME fields of the part, with their values.
+    fn mime_fields(&self) -> Result<Vec<MimeField>, Error> {
+        let mut content_type = Vec::new();
+        self.content_type.write_to(&mut content_type)?;
+        let mut fields = vec![(&b"Content-Type"[..], content_type)];
+        if self.encoding != TransferEncoding::SevenBit {
+            fields.push((b"Content-Transfer-Encoding", self.encoding.name().as_bytes().to_vec()));
+        }
+        fields.extend(self.fields.iter().cloned());
+        Ok(fields)
+    }
+
+    fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        for (name, value) in self.mime_fields()? {
+            write_structured_field(name, &value, writer)?;
+        }
+        writer.write_bytes(b"\r\n")?;
+        writer.write_bytes(&self.body)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PartBody {
+    Content(Vec<u8>),
+    Parts(Vec<PartBuilder>),
+}
+
+/// A builder for MIME entities: either a part with some content, or a multipart entity made of
+/// other parts. See [RFC2045 section 2.4] and [RFC2046 section 5.1].
+///
+/// Text is written with a transfer encoding chosen by [`choose_encoding`], messages and multipart
+/// content as is, other content in base64, and the boundaries of multipart entities are generated so that they do not
+/// occur in any of their parts.
+///
+/// [RFC2045 section 2.4]: https://tools.ietf.org/html/rfc2045#section-2.4
+/// [RFC2046 section 5.1]: https://tools.ietf.org/html/rfc2046#section-5.1
+/// [`choose_encoding`]: ../transfer_encoding/fn.choose_encoding.html
+#[derive(Clone, Debug)]
+pub struct PartBuilder {
+    content_type: ContentType,
+    content_id: Option<MessageId>,
+    disposition: Option<ContentDisposition>,
+    body: PartBody,
+}
+
+impl PartBuilder {
+    /// Create a part with the given media type. The content is written as is, with a transfer
+    /// encoding if necessary.
+    pub fn new<T: Into<Vec<u8>>>(content_type: ContentType, content: T) -> Self {
+        PartBuilder {
+            content_type,
+            content_id: None,
+            disposition: None,
+            body: PartBody::Content(content.into()),
+        }
+    }
+
+    /// Create a `text/plain` part. Line breaks are converted into CRLF, and the charset is
+    /// `us-ascii` or `utf-8` depending on the content.
+    pub fn text<T: AsRef<[u8]>>(text: T) -> Self {
+        let text = normalize_line_breaks(text.as_ref());
+        PartBuilder::new(text_content_type(b"plain", &text), text)
+    }
+
+    /// Create a `text/html` part. Line breaks are converted into CRLF, and the charset is
+    /// `us-ascii` or `utf-8` depending on the content.
+    pub fn html<T: AsRef<[u8]>>(html: T) -> Self {
+        let html = normalize_line_breaks(html.as_ref());
+        PartBuilder::new(text_content_type(b"html", &html), html)
+    }
+
+    /// Create a multipart entity, for instance `multipart/mixed`, made of the given parts. The
+    /// `boundary` parameter is generated when the entity is written.
+    pub fn multipart(content_type: ContentType, parts: Vec<PartBuilder>) -> Self {
+        PartBuilder {
+            content_type,
+            content_id: None,
+            disposition: None,
+            body: PartBody::Parts(parts),
+        }
+    }
+
+    /// Set the content identifier, which other parts can reference with a `cid:` URL. See
+    /// [RFC2392].
+    ///
+    /// [RFC2392]: https://tools.ietf.org/html/rfc2392
+    pub fn content_id(mut self, content_id: MessageId) -> Self {
+        self.content_id = Some(content_id);
+        self
+    }
+
+    pub fn disposition(mut self, disposition: ContentDisposition) -> Self {
+        self.disposition = Some(disposition);
+        self
+    }
+
+    /// Mark the part as an attachment, with the given suggested file name.
+    pub fn attachment<T: AsRef<[u8]>>(self, filename: T) -> Self {
+        self.disposition(ContentDisposition::new(DispositionKind::Attachment).with_filename(filename.as_ref()))
+    }
+
+    /// Mark the part as intended to be displayed with the rest of the message.
+    pub fn inline(self) -> Self {
+        self.disposition(ContentDisposition::new(DispositionKind::Inline))
+    }
+
+    fn encode(&self) -> Result<EncodedPart, Error> {
+        let mut part = match self.body {
+            PartBody::Content(ref content) => EncodedPart::new(self.content_type.clone(), content)?,
+            PartBody::Parts(ref parts) => {
+                let parts = parts.iter().map(PartBuilder::encode).collect::<Result<Vec<_>, _>>()?;
+                EncodedPart::multipart(self.content_type.clone(), &parts, generate_boundary)?
+            }
+        };
+        if let Some(ref content_id) = self.content_id {
+            let mut value = Vec::new();
+            content_id.write_to(&mut value)?;
+            part.fields.push((b"Content-ID", value));
+        }
+        if let Some(ref disposition) = self.disposition {
+            let mut value = Vec::new();
+            disposition.write_to(&mut value)?;
+            part.fields.push((b"Content-Disposition", value));
+        }
+        Ok(part)
+    }
+
+    /// Write the part: its MIME fields, followed by its encoded body.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        self.encode()?.write_to(writer)
+    }
+}
+
+/// A builder for new messages. See [RFC5322 section 3.6].
+///
+/// Values are given as typed values (mailboxes, date-times, message identifiers...) or as text,
+/// and are quoted or encoded as needed when the message is written, so that the output can be
+/// parsed back by the parsers of this crate:
+///
+/// - display names are written as phrases (see [`write_phrase`])
+/// - the subject and custom fields are written as unstructured values (see
+///   [`write_unstructured_field`])
+/// - each text part of the body is written with a transfer encoding chosen by
+///   [`choose_encoding`], messages and multipart parts as is, and the other parts in base64
+///
+/// The body is made of a main part (a text, an HTML text, or any [`PartBuilder`]), of inline parts
+/// and of attachments. MIME entities are nested as needed: a text and an HTML alternative are
+/// joined in a `multipart/alternative` entity, the main part and the inline parts it references
+/// in a `multipart/related` entity (see [RFC2387]), and attachments are added with a
+/// `multipart/mixed` entity.
+///
+/// When the message is written, the `Date` field is set to the current date-time and a
+/// `Message-ID` is generated if they were not provided. A `Sender` field is added if there are
+/// several authors. The `MIME-Version` field is only added when the body cannot be sent as
+/// US-ASCII text without MIME.
+///
+/// Header fields are folded to respect the recommended line length (see the [`folding`]
+/// module).
+///
+/// ```rust
+/// use imf::address::{Address, Mailbox};
+/// use imf::builder::MessageBuilder;
+///
+/// let from = Mailbox::new(Some(b"Notifications"), Address::new(b"noreply", b"example.com").unwrap());
+/// let to = Mailbox::new(None, Address::new(b"user", b"example.org").unwrap());
+/// let message = MessageBuilder::new(from)
+///     .to(to)
+///     .subject("Your report is ready")
+///     .text("Hello,\nyour report is ready.\n")
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// [RFC5322 section 3.6]: https://tools.ietf.org/html/rfc5322#section-3.6
+/// [RFC2387]: https://tools.ietf.org/html/rfc2387
+/// [`PartBuilder`]: struct.PartBuilder.html
+/// [`folding`]: ../folding/index.html
+/// [`write_phrase`]: ../common/fn.write_phrase.html
+/// [`write_unstructured_field`]: ../folding/fn.write_unstructured_field.html
+/// [`choose_encoding`]: ../transfer_encoding/fn.choose_encoding.html
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    from: Vec<Mailbox>,
+    sender: Option<Mailbox>,
+    reply_to: Vec<Mailbox>,
+    to: Vec<MailboxOrGroup>,
+    cc: Vec<MailboxOrGroup>,
+    subject: Option<Vec<u8>>,
+    date: Option<DateTime>,
+    message_id: Option<MessageId>,
+    in_reply_to: Vec<MessageId>,
+    references: Vec<MessageId>,
+    fields: Vec<(Vec<u8>, Vec<u8>)>,
+    text: Option<PartBuilder>,
+    html: Option<PartBuilder>,
+    body: Option<PartBuilder>,
+    inline: Vec<PartBuilder>,
+    attachments: Vec<PartBuilder>,
+}
+
+impl MessageBuilder {
+    /// Create a builder for a message which author is the given mailbox.
+    pub fn new(from: Mailbox) -> Self {
+        MessageBuilder {
+            from: vec![from],
+            sender: None,
+            reply_to: Vec::new(),
+            to: Vec::new(),
+            cc: Vec::new(),
+            subject: None,
+            date: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            fields: Vec::new(),
+            text: None,
+            html: None,
+            body: None,
+            inline: Vec::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Add an author.
+    pub fn from(mut self, mailbox: Mailbox) -> Self {
+        self.from.push(mailbox);
+        self
+    }
+
+    /// Set the mailbox of the agent responsible for the transmission of the message. It defaults
+    /// to the first author when there are several authors.
+    pub fn sender(mut self, mailbox: Mailbox) -> Self {
+        self.sender = Some(mailbox);
+        self
+    }
+
+    pub fn reply_to(mut self, mailbox: Mailbox) -> Self {
+        self.reply_to.push(mailbox);
+        self
+    }
+
+    /// Add a primary recipient: a mailbox or a group.
+    pub fn to<T: Into<MailboxOrGroup>>(mut self, recipient: T) -> Self {
+        self.to.push(recipient.into());
+        self
+    }
+
+    /// Add a secondary recipient: a mailbox or a group.
+    pub fn cc<T: Into<MailboxOrGroup>>(mut self, recipient: T) -> Self {
+        self.cc.push(recipient.into());
+        self
+    }
+
+    pub fn subject<T: AsRef<[u8]>>(mut self, subject: T) -> Self {
+        self.subject = Some(subject.as_ref().to_vec());
+        self
+    }
+
+    /// Set the date. It defaults to the date-time at which the message is written.
+    pub fn date(mut self, date: DateTime) -> Self {
+        self.date = Some(date);
+        self
+    }
+
+    /// Set the message identifier. By default, a unique identifier is generated when the message
+    /// is written, using the domain of the first author.
+    pub fn message_id(mut self, message_id: MessageId) -> Self {
+        self.message_id = Some(message_id);
+        self
+    }
+
+    /// Add the identifier of a message this message is a reply to.
+    pub fn in_reply_to(mut self, message_id: MessageId) -> Self {
+        self.in_reply_to.push(message_id);
+        self
+    }
+
+    /// Add the identifier of a message of the thread this message belongs to.
+    pub fn references(mut self, message_id: MessageId) -> Self {
+        self.references.push(message_id);
+        self
+    }
+
+    /// Add a custom field, with an unstructured value. A custom field replaces the field with the
+    /// same name that the builder would otherwise write, for instance `Date`. The name is checked
+    /// when the message is written.
+    pub fn field<N: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, name: N, value: V) -> Self {
+        self.fields.push((name.as_ref().to_vec(), value.as_ref().to_vec()));
+        self
+    }
+
+    /// Set a plain text body. Line breaks are converted into CRLF, and the charset is `us-ascii`
+    /// or `utf-8` depending on the content.
+    pub fn text<T: AsRef<[u8]>>(mut self, text: T) -> Self {
+        self.text = Some(PartBuilder::text(text));
+        self
+    }
+
+    /// Set an HTML body. If a plain text body is also set, both are sent as alternatives.
+    pub fn html<T: AsRef<[u8]>>(mut self, html: T) -> Self {
+        self.html = Some(PartBuilder::html(html));
+        self
+    }
+
+    /// Set the body, and its media type. The content is written as is, with a transfer encoding
+    /// if necessary. The body replaces the text and HTML bodies.
+    pub fn body<T: Into<Vec<u8>>>(self, content_type: ContentType, content: T) -> Self {
+        self.part(PartBuilder::new(content_type, content))
+    }
+
+    /// Set the body to the given part, which can be a multipart entity. The body replaces the
+    /// text and HTML bodies.
+    pub fn part(mut self, part: PartBuilder) -> Self {
+        self.body = Some(part);
+        self
+    }
+
+    /// Add an inline part, for instance an image, that the body references with a `cid:` URL
+    /// made of the given content identifier (`cid:left@right`).
+    pub fn inline<T: Into<Vec<u8>>>(mut self, content_id: MessageId, content_type: ContentType, content: T) -> Self {
+        self.inline.push(PartBuilder::new(content_type, content).content_id(content_id).inline());
+        self
+    }
+
+    /// Add an attachment, with its suggested file name.
+    pub fn attachment<N, T>(mut self, filename: N, content_type: ContentType, content: T) -> Self
+    where
+        N: AsRef<[u8]>,
+        T: Into<Vec<u8>>,
+    {
+        self.attachments.push(PartBuilder::new(content_type, content).attachment(filename));
+        self
+    }
+
+    /// Encode the body, nesting the parts in multipart entities as needed.
+    fn encode_body(&self) -> Result<Option<EncodedPart>, Error> {
+        let encode_all = |parts: &[PartBuilder]| parts.iter().map(PartBuilder::encode).collect::<Result<Vec<_>, _>>();
+
+        let main = match self.body {
+            Some(ref body) => Some(body.encode()?),
+            None => {
+                let mut alternatives = self.text.iter()
+                    .chain(self.html.iter())
+                    .map(PartBuilder::encode)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if alternatives.len() > 1 {
+                    let content_type = ContentType::new(b"multipart", b"alternative");
+                    Some(EncodedPart::multipart(content_type, &alternatives, generate_boundary)?)
+                } else {
+                    alternatives.pop()
+                }
+            }
+        };
+
+        let mut parts = Vec::new();
+        let inline = encode_all(&self.inline)?;
+        match main {
+            Some(main) if !inline.is_empty() => {
+                let mut root_type = main.content_type.mime_type().to_vec();
+                root_type.push(b'/');
+                root_type.extend_from_slice(main.content_type.subtype());
+                let content_type = ContentType::new(b"multipart", b"related").with_parameter(b"type", &root_type);
+                let mut related = vec![main];
+                related.extend(inline);
+                parts.push(EncodedPart::multipart(content_type, &related, generate_boundary)?);
+            }
+            Some(main) => parts.push(main),
+            None => parts.extend(inline),
+        }
+
+        let attachments = encode_all(&self.attachments)?;
+        if attachments.is_empty() && parts.len() <= 1 {
+            return Ok(parts.pop());
+        }
+        parts.extend(attachments);
+        let content_type = ContentType::new(b"multipart", b"mixed");
+        Ok(Some(EncodedPart::multipart(content_type, &parts, generate_boundary)?))
+    }
+
+    /// Return `true` if a custom field has the given name.
+    fn has_field(&self, name: &[u8]) -> bool {
+        self.fields.iter().any(|(field, _)| field.eq_ignore_ascii_case(name))
+    }
+
+    /// Write a field with a structured value, unless a custom field with the same name exists.
+    fn write_field<W: Sink, F>(&self, name: &[u8], writer: &mut W, write_value: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+    {
+        if self.has_field(name) {
+            return Ok(());
+        }
+        let mut value = Vec::new();
+        write_value(&mut value)?;
+        write_structured_field(name, &value, writer)
+    }
+
+    /// Write the message.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        for (name, _) in &self.fields {
+            check_field_name(name)?;
+        }
+
+        let date = self.date.unwrap_or_else(DateTime::now);
+        self.write_field(b"Date", writer, |w| date.write_to(w))?;
+        self.write_field(b"From", writer, |w| write_mailbox_list(&self.from, w))?;
+        let sender = self.sender.as_ref().or_else(|| if self.from.len() > 1 { self.from.first() } else { None });
+        if let Some(sender) = sender {
+            self.write_field(b"Sender", writer, |w| sender.write_to(w))?;
+        }
+        if !self.reply_to.is_empty() {
+            self.write_field(b"Reply-To", writer, |w| write_mailbox_list(&self.reply_to, w))?;
+        }
+        if !self.to.is_empty() {
+            self.write_field(b"To", writer, |w| write_address_list(&self.to, w))?;
+        }
+        if !self.cc.is_empty() {
+            self.write_field(b"Cc", writer, |w| write_address_list(&self.cc, w))?;
+        }
+        let message_id = match self.message_id {
+            Some(ref message_id) => message_id.clone(),
+            None => MessageId::generate(self.from[0].address().domain())?,
+        };
+        self.write_field(b"Message-ID", writer, |w| message_id.write_to(w))?;
+        if !self.in_reply_to.is_empty() {
+            self.write_field(b"In-Reply-To", writer, |w| write_message_ids(&self.in_reply_to, w))?;
+        }
+        if !self.references.is_empty() {
+            self.write_field(b"References", writer, |w| write_message_ids(&self.references, w))?;
+        }
+        if let Some(ref subject) = self.subject {
+            if !self.has_field(b"Subject") {
+                write_unstructured_field(b"Subject", subject, writer)?;
+            }
+        }
+        for (name, value) in &self.fields {
+            write_unstructured_field(name, value, writer)?;
+        }
+
+        if let Some(body) = self.encode_body()? {
+            if !body.is_plain_text() {
+                self.write_field(b"MIME-Version", writer, |w| w.write_bytes(b"1.0"))?;
+                for (name, value) in body.mime_fields()? {
+                    self.write_field(name, writer, |w| w.write_bytes(&value))?;
+                }
+            }
+            writer.write_bytes(b"\r\n")?;
+            writer.write_bytes(&body.body)?;
+        } else {
+            writer.write_bytes(b"\r\n")?;
+        }
+        Ok(())
+    }
+
+    /// Write the message into a new vector.
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let mut message = Vec::new();
+        self.write_to(&mut message)?;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address::{parse_address_list, parse_mailbox_list, Address};
+    use encoded_word::decode_encoded_words;
+    use errors::{ErrorKind, Token};
+    use message::Message;
+    use message_id::parse_message_ids;
+    use mime_part::{Body, MimePart};
+    use std::io::Read;
+
+    fn mailbox(display_name: Option<&str>, local_part: &str, domain: &str) -> Mailbox {
+        let address = Address::new(local_part.as_bytes(), domain.as_bytes()).unwrap();
+        Mailbox::new(display_name.map(|name| name.as_bytes()), address)
+    }
+
+    fn value(message: &Message, name: &[u8]) -> Vec<u8> {
+        message.field(name).unwrap().unfolded_value()
+    }
+
+    #[test]
+    fn test_build_message() {
+        let date = DateTime::from_timestamp(880_127_706, -360).unwrap();
+        let parent = MessageId::new(b"1234", b"local.machine.example").unwrap();
+        let root = MessageId::new(b"5678", b"local.machine.example").unwrap();
+        let message = MessageBuilder::new(mailbox(Some("Smith, John"), "john", "example.com"))
+            .to(mailbox(Some("Zoë"), "zoe", "example.org"))
+            .to(Group::new(b"Team", vec![mailbox(None, "a b", "example.org"), mailbox(None, "c", "[192.0.2.1]")]))
+            .cc(mailbox(None, "mary", "example.net"))
+            .reply_to(mailbox(None, "replies", "example.com"))
+            .subject("Réunion de l'équipe")
+            .date(date)
+            .in_reply_to(parent.clone())
+            .references(root.clone())
+            .references(parent.clone())
+            .field("X-Mailer", "imf")
+            .text("Bonjour,\nla réunion est à 10h.\n")
+            .build()
+            .unwrap();
+
+        let message = Message::parse(&Buffer::new(&message)).unwrap();
+        let names: Vec<&[u8]> = message.fields().iter().map(|field| field.name()).collect();
+        assert_eq!(names, vec![
+            &b"Date"[..], b"From", b"Reply-To", b"To", b"Cc", b"Message-ID", b"In-Reply-To", b"References",
+            b"Subject", b"X-Mailer", b"MIME-Version", b"Content-Type", b"Content-Transfer-Encoding",
+        ]);
+        assert_eq!(value(&message, b"Date"), b"Fri, 21 Nov 1997 09:55:06 -0600");
+        assert_eq!(DateTime::parse(b!(value(&message, b"Date"))).unwrap().0, date);
+
+        let (from, _) = parse_mailbox_list(b!(value(&message, b"From"))).unwrap();
+        assert_eq!(from, vec![mailbox(Some("Smith, John"), "john", "example.com")]);
+        let (to, _) = parse_address_list(b!(value(&message, b"To"))).unwrap();
+        assert_eq!(to, vec![
+            mailbox(Some("Zoë"), "zoe", "example.org").into(),
+            Group::new(b"Team", vec![mailbox(None, "a b", "example.org"), mailbox(None, "c", "[192.0.2.1]")]).into(),
+        ]);
+        assert_eq!(value(&message, b"To"), &b"=?utf-8?q?Zo=C3=AB?= <zoe@example.org>, Team: \"a b\"@example.org, c@[192.0.2.1];"[..]);
+        let (message_id, _) = MessageId::parse(b!(value(&message, b"Message-ID"))).unwrap();
+        assert_eq!(message_id.right(), b"example.com");
+        let (references, _) = parse_message_ids(b!(value(&message, b"References"))).unwrap();
+        assert_eq!(references, vec![root, parent]);
+        assert_eq!(decode_encoded_words(&value(&message, b"Subject")), "Réunion de l'équipe".as_bytes());
+        assert_eq!(value(&message, b"X-Mailer"), b"imf");
+        assert_eq!(value(&message, b"Content-Type"), b"text/plain; charset=utf-8");
+        assert_eq!(value(&message, b"Content-Transfer-Encoding"), b"quoted-printable");
+        assert_eq!(message.body(), b"Bonjour,\r\nla r=C3=A9union est =C3=A0 10h.\r\n");
+    }
+
+    #[test]
+    fn test_build_ascii_message() {
+        let message = MessageBuilder::new(mailbox(None, "john", "example.com"))
+            .from(mailbox(None, "mary", "example.com"))
+            .to(mailbox(None, "team", "example.com"))
+            .text("Hello\n")
+            .build()
+            .unwrap();
+        let part = MimePart::parse(&Buffer::new(&message)).unwrap();
+        let names: Vec<&[u8]> = part.fields().iter().map(|field| field.name()).collect();
+        assert_eq!(names, vec![&b"Date"[..], b"From", b"Sender", b"To", b"Message-ID"]);
+        assert_eq!(part.field(b"Sender").unwrap().value(), b" john@example.com");
+        assert!(DateTime::parse(b!(part.field(b"Date").unwrap().unfolded_value())).is_ok());
+        let mut body = Vec::new();
+        part.decode_body(&mut body).unwrap();
+        assert_eq!(body, b"Hello\r\n");
+
+        // two messages get different identifiers
+        let builder = MessageBuilder::new(mailbox(None, "john", "example.com"));
+        let first = Message::parse(&Buffer::new(&builder.build().unwrap())).unwrap().field(b"Message-ID").unwrap().unfolded_value();
+        let second = Message::parse(&Buffer::new(&builder.build().unwrap())).unwrap().field(b"Message-ID").unwrap().unfolded_value();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_build_binary_body() {
+        let content = vec![0u8, 159, 146, 150, 13, 10, 255];
+        let message = MessageBuilder::new(mailbox(None, "john", "example.com"))
+            .body(ContentType::new(b"application", b"octet-stream"), content.clone())
+            .build()
+            .unwrap();
+        let part = MimePart::parse(&Buffer::new(&message)).unwrap();
+        assert_eq!(part.transfer_encoding(), TransferEncoding::Base64);
+        assert_eq!(part.content_type().subtype(), b"octet-stream");
+        let mut body = Vec::new();
+        part.decode_body(&mut body).unwrap();
+        assert_eq!(body, content);
+    }
+
+    #[test]
+    fn test_custom_fields() {
+        let message = MessageBuilder::new(mailbox(None, "john", "example.com"))
+            .field("Date", "Fri, 21 Nov 1997 09:55:06 -0600")
+            .field("X-Note", "line 1\r\nline 2")
+            .build()
+            .unwrap();
+        let message = Message::parse(&Buffer::new(&message)).unwrap();
+        let names: Vec<&[u8]> = message.fields().iter().map(|field| field.name()).collect();
+        assert_eq!(names, vec![&b"From"[..], b"Message-ID", b"Date", b"X-Note"]);
+        assert_eq!(decode_encoded_words(&value(&message, b"X-Note")), b"line 1\r\nline 2");
+
+        let builder = MessageBuilder::new(mailbox(None, "john", "example.com"));
+        let e = builder.clone().field("X Note", "value").build().unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::FieldName, b' ', 1));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+        assert!(builder.field("", "value").build().unwrap_err().is_eof());
+    }
+
+    #[test]
+    fn test_header_injection() {
+        let builder = MessageBuilder::new(mailbox(None, "john", "example.com"));
+        let content_type = ContentType::new(b"text", b"plain\r\nBcc: victim@example.com");
+        let e = builder.clone().body(content_type, "text").build().unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::MimeToken, b'\r', 5));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+
+        let content_type = ContentType::default().with_parameter(b"x\r\nBcc: victim@example.com\r\nx", b"y");
+        assert!(builder.clone().body(content_type, "text").build().unwrap_err().is_token());
+        let content_type = ContentType::new(b"", b"plain");
+        assert!(builder.clone().body(content_type, "text").build().unwrap_err().is_eof());
+
+        let disposition = ContentDisposition::new(DispositionKind::Other(b"x\r\nBcc: victim@example.com".to_vec()));
+        let part = PartBuilder::text("text").disposition(disposition);
+        assert!(builder.part(part).build().unwrap_err().is_token());
+    }
+
+    fn decoded_body(part: &MimePart) -> Vec<u8> {
+        let mut body = Vec::new();
+        part.decode_body(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn test_fold_header() {
+        let subject = "Compte rendu de la réunion du comité de pilotage du projet de migration de la messagerie";
+        let mut builder = MessageBuilder::new(mailbox(None, "john", "example.com")).subject(subject);
+        for i in 0..10 {
+            builder = builder.to(mailbox(Some(&format!("Recipient {}", i)), &format!("recipient.{}", i), "example.com"));
+        }
+        let output = builder.build().unwrap();
+        let header = &output[..output.windows(4).position(|w| w == b"\r\n\r\n").unwrap()];
+        assert!(header.split(|c| *c == b'\n').all(|line| line.len() <= 79));
+
+        let message = Message::parse(&Buffer::new(&output)).unwrap();
+        assert_eq!(decode_encoded_words(&value(&message, b"Subject")), subject.as_bytes());
+        let (to, _) = parse_address_list(b!(value(&message, b"To"))).unwrap();
+        assert_eq!(to.len(), 10);
+        assert_eq!(to[9], mailbox(Some("Recipient 9"), "recipient.9", "example.com").into());
+    }
+
+    #[test]
+    fn test_build_multipart_message() {
+        let logo = MessageId::new(b"logo", b"example.com").unwrap();
+        let image = vec![0x89u8, b'P', b'N', b'G', 13, 10, 26, 10, 0, 0];
+        let message = MessageBuilder::new(mailbox(None, "john", "example.com"))
+            .text("Voir le rapport ci-joint.\n")
+            .html("<p>Voir le rapport ci-joint.</p><img src=\"cid:logo@example.com\">\n")
+            .inline(logo.clone(), ContentType::new(b"image", b"png"), image.clone())
+            .attachment("rapport été.pdf", ContentType::new(b"application", b"pdf"), b"%PDF-1.4\n--".to_vec())
+            .build()
+            .unwrap();
+
+        let part = MimePart::parse(&Buffer::new(&message)).unwrap();
+        assert_eq!(part.field(b"MIME-Version").unwrap().unfolded_value(), b"1.0");
+        assert_eq!(part.content_type().subtype(), b"mixed");
+        assert!(part.content_type().boundary().unwrap().starts_with(b"=_"));
+        assert_eq!(part.children().len(), 2);
+
+        let related = &part.children()[0];
+        assert_eq!(related.content_type().subtype(), b"related");
+        assert_eq!(related.content_type().parameter(b"type"), Some(&b"multipart/alternative"[..]));
+        let alternative = &related.children()[0];
+        assert_eq!(alternative.content_type().subtype(), b"alternative");
+        assert_eq!(alternative.children()[0].content_type().subtype(), b"plain");
+        assert_eq!(decoded_body(&alternative.children()[0]), b"Voir le rapport ci-joint.\r\n");
+        assert_eq!(alternative.children()[1].content_type().subtype(), b"html");
+        let inline = &related.children()[1];
+        assert_eq!(inline.field(b"Content-ID").unwrap().unfolded_value(), b"<logo@example.com>");
+        assert_eq!(inline.transfer_encoding(), TransferEncoding::Base64);
+        assert_eq!(decoded_body(inline), image);
+
+        let attachments: Vec<_> = part.attachments().collect();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].filename(), Some("rapport été.pdf".as_bytes()));
+        let mut content = Vec::new();
+        attachments[0].reader().read_to_end(&mut content).unwrap();
+        assert_eq!(attachments[0].part().transfer_encoding(), TransferEncoding::Base64);
+        assert_eq!(content, b"%PDF-1.4\n--");
+    }
+
+    #[test]
+    fn test_build_alternative_message() {
+        let message = MessageBuilder::new(mailbox(None, "john", "example.com"))
+            .text("Hello\n")
+            .html("<p>Héllo</p>")
+            .build()
+            .unwrap();
+        let part = MimePart::parse(&Buffer::new(&message)).unwrap();
+        assert_eq!(part.content_type().subtype(), b"alternative");
+        assert!(part.field(b"Content-Transfer-Encoding").is_none());
+        assert_eq!(part.children().len(), 2);
+        assert_eq!(part.children()[1].content_type().charset(), Some(&b"utf-8"[..]));
+        assert_eq!(decoded_body(&part.children()[1]), "<p>Héllo</p>".as_bytes());
+        assert_eq!(part.attachments().count(), 0);
+
+        // a single attachment is sent in a multipart/mixed entity
+        let message = MessageBuilder::new(mailbox(None, "john", "example.com"))
+            .attachment("a.txt", ContentType::default(), "a")
+            .build()
+            .unwrap();
+        let part = MimePart::parse(&Buffer::new(&message)).unwrap();
+        assert_eq!(part.content_type().subtype(), b"mixed");
+        assert_eq!(part.attachments().count(), 1);
+    }
+
+    #[test]
+    fn test_forward_message() {
+        let forwarded = "From: alice@example.com\r\nSubject: Réunion\r\n\r\nÀ demain.\r\n".as_bytes();
+        let message = MessageBuilder::new(mailbox(None, "john", "example.com"))
+            .text("See the forwarded message.")
+            .attachment("fwd.eml", ContentType::new(b"message", b"rfc822"), forwarded)
+            .build()
+            .unwrap();
+
+        let part = MimePart::parse(&Buffer::new(&message)).unwrap();
+        let attachment = &part.children()[1];
+        assert_eq!(attachment.transfer_encoding(), TransferEncoding::EightBit);
+        assert!(contains(&message, forwarded));
+        match *attachment.body() {
+            Body::Message(ref embedded) => {
+                assert_eq!(embedded.field(b"From").unwrap().unfolded_value(), b"alice@example.com");
+            }
+            _ => panic!("the forwarded message is not parsed as a message"),
+        }
+
+        assert_eq!(identity_encoding(b"a\r\nb"), TransferEncoding::SevenBit);
+        assert_eq!(identity_encoding(b"a\nb\xff"), TransferEncoding::Binary);
+        assert_eq!(identity_encoding(&[b'\xff'; 999]), TransferEncoding::Binary);
+        let part = PartBuilder::new(ContentType::new(b"multipart", b"mixed"), "\x00").encode().unwrap();
+        assert_eq!(part.encoding, TransferEncoding::Binary);
+    }
+
+    #[test]
+    fn test_build_part() {
+        let part = PartBuilder::multipart(
+            ContentType::new(b"multipart", b"mixed"),
+            vec![PartBuilder::text("a\n"), PartBuilder::new(ContentType::default(), "--=_\r\n").inline()],
+        );
+        let mut output = Vec::new();
+        part.write_to(&mut output).unwrap();
+        let parsed = MimePart::parse(&Buffer::new(&output)).unwrap();
+        assert_eq!(parsed.children().len(), 2);
+        assert_eq!(parsed.children()[1].field(b"Content-Disposition").unwrap().unfolded_value(), b"inline");
+        assert!(parsed.field(b"MIME-Version").is_none());
+    }
+
+    #[test]
+    fn test_boundary_collision() {
+        let part = EncodedPart::new(ContentType::default(), b"--=_a\r\n").unwrap();
+        let mut boundaries = vec![b"=_b".to_vec(), b"=_a".to_vec()];
+        let content_type = ContentType::new(b"multipart", b"mixed");
+        let multipart = EncodedPart::multipart(content_type, &[part], || boundaries.pop().unwrap()).unwrap();
+        assert_eq!(multipart.content_type.boundary(), Some(&b"=_b"[..]));
+        assert_eq!(
+            multipart.body,
+            &b"--=_b\r\nContent-Type: text/plain; charset=us-ascii\r\n\r\n--=_a\r\n\r\n--=_b--\r\n"[..]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use common::utf8_non_ascii_len;
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use Buffer;
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// An element of the content of a comment.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum CommentContent {
+    /// Text, with quoted pairs resolved and the line breaks used for folding removed
+    Text(Vec<u8>),
+    /// A nested comment
+    Comment(Comment),
+}
+
+/// A comment, with its nested comments. See [RFC5322 section 3.2.2].
+///
+/// ```no_rust
+/// ctext           =   %d33-39 /          ; Printable US-ASCII
+///                     %d42-91 /          ;  characters not including
+///                     %d93-126 /         ;  "(", ")", or "\"
+///                     obs-ctext
+/// ccontent        =   ctext / quoted-pair / comment
+/// comment         =   "(" *([FWS] ccontent) [FWS] ")"
+/// ```
+///
+/// [RFC5322 section 3.2.2]: https://tools.ietf.org/html/rfc5322#section-3.2.2
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Comment {
+    content: Vec<CommentContent>,
+}
+
+/// Append text to the content of a comment, merging it with the preceding text if any.
+fn push_text(content: &mut Vec<CommentContent>, text: &[u8]) {
+    if let Some(&mut CommentContent::Text(ref mut previous)) = content.last_mut() {
+        previous.extend_from_slice(text);
+        return;
+    }
+    content.push(CommentContent::Text(text.to_vec()));
+}
+
+impl Comment {
+    /// Parse a comment. On success, the comment and the number of bytes read are returned.
+    /// Nested comments are accepted at any depth.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        if bytes.is_empty() {
+            return Err(ErrorKind::Eof.into());
+        }
+        if bytes[0] != b'(' {
+            return Err(ErrorKind::Token {
+                token: Token::Comment,
+                byte: bytes[0],
+                position: input.position(),
+            }.into());
+        }
+
+        // the content of the comments being read, the outermost first
+        let mut open: Vec<Vec<CommentContent>> = vec![Vec::new()];
+        let mut i: usize = 1;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' if i + 1 < bytes.len() => {
+                    let len = utf8_non_ascii_len(input, i + 1, Token::Comment)?.unwrap_or(1);
+                    push_text(open.last_mut().unwrap(), &bytes[i + 1..i + 1 + len]);
+                    i += 1 + len;
+                    continue;
+                }
+                b'(' => open.push(Vec::new()),
+                b')' => {
+                    let comment = Comment { content: open.pop().unwrap() };
+                    match open.last_mut() {
+                        Some(parent) => parent.push(CommentContent::Comment(comment)),
+                        None => return Ok((comment, i + 1)),
+                    }
+                }
+                b'\r' if bytes[i + 1..].starts_with(b"\n") && bytes.get(i + 2).is_some_and(|c| is_wsp(*c)) => {
+                    i += 2;
+                    continue;
+                }
+                b'\n' if bytes.get(i + 1).is_some_and(|c| is_wsp(*c)) => {}
+                // ctext =/ UTF8-non-ascii
+                c if c > 127 && input.accepts_utf8() => {
+                    let len = utf8_non_ascii_len(input, i, Token::Comment)?.unwrap_or(1);
+                    push_text(open.last_mut().unwrap(), &bytes[i..i + len]);
+                    i += len;
+                    continue;
+                }
+                c => push_text(open.last_mut().unwrap(), &[c]),
+            }
+            i += 1;
+        }
+        // we reached the end of the buffer without seeing the closing parenthesis
+        Err(ErrorKind::Eof.into())
+    }
+
+    pub fn content(&self) -> &[CommentContent] {
+        &self.content
+    }
+
+    /// Return the text of the comment, with the text of nested comments included in
+    /// parentheses. For instance, the text of `(John \(the (real\) man))` is `John (the (real)
+    /// man)`.
+    pub fn text(&self) -> Vec<u8> {
+        let mut text = Vec::new();
+        self.write_text(&mut text);
+        text
+    }
+
+    fn write_text(&self, text: &mut Vec<u8>) {
+        for content in &self.content {
+            match *content {
+                CommentContent::Text(ref content) => text.extend_from_slice(content),
+                CommentContent::Comment(ref comment) => {
+                    text.push(b'(');
+                    comment.write_text(text);
+                    text.push(b')');
+                }
+            }
+        }
+    }
+}
+
+/// Return the comments found in the next `len` bytes of the buffer, outside of quoted strings and
+/// domain literals. Nested comments are not returned separately: they are part of the outermost
+/// comment.
+fn find_comments(input: &Buffer, len: usize) -> Result<Vec<Comment>, Error> {
+    let bytes = &input.remaining()[..len];
+    let mut buffer = input.clone();
+    let mut comments = Vec::new();
+    let mut quoted = false;
+    let mut literal = false;
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if quoted || literal => i += 1,
+            b'"' if !literal => quoted = !quoted,
+            b'[' if !quoted => literal = true,
+            b']' if !quoted => literal = false,
+            b'(' if !quoted && !literal => {
+                buffer.set_position(input.position() + i);
+                let (comment, len) = Comment::parse(&buffer)?;
+                comments.push(comment);
+                i += len;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(comments)
+}
+
+/// Parse a structured value with the given parser, and collect the comments it skipped. On
+/// success, the value, the comments, and the number of bytes read are returned.
+///
+/// Comments often carry information, for instance the name of the owner of an address, which
+/// can be used when there is no display name:
+///
+/// ```rust
+/// use imf::address::Mailbox;
+/// use imf::comment::parse_with_comments;
+/// use imf::Buffer;
+///
+/// let input = Buffer::new(b"john@example.com (John Smith)");
+/// let (mailbox, comments, _) = parse_with_comments(&input, Mailbox::parse).unwrap();
+/// let name = mailbox
+///     .display_name()
+///     .map(|name| name.to_vec())
+///     .or_else(|| comments.first().map(|comment| comment.text()));
+/// assert_eq!(name, Some(b"John Smith".to_vec()));
+/// ```
+pub fn parse_with_comments<'buf, T, F>(input: &Buffer<'buf>, parse: F) -> Result<(T, Vec<Comment>, usize), Error>
+where
+    F: FnOnce(&Buffer<'buf>) -> Result<(T, usize), Error>,
+{
+    let (value, len) = parse(input)?;
+    let comments = find_comments(input, len)?;
+    Ok((value, comments, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address::{parse_address_list, Mailbox};
+    use date_time::DateTime;
+
+    fn comment(input: &[u8]) -> (Comment, usize) {
+        Comment::parse(&Buffer::new(input)).unwrap()
+    }
+
+    fn comment_len(input: &Buffer) -> Result<usize, Error> {
+        Comment::parse(input).map(|(_, len)| len)
+    }
+
+    #[test]
+    fn test_parse_comment() {
+        let (parsed, len) = comment(b"(John \\(the (real\\) man)) x");
+        assert_eq!(len, 25);
+        assert_eq!(parsed.text(), b"John (the (real) man)");
+        assert_eq!(parsed.content().len(), 2);
+        assert_eq!(parsed.content()[0], CommentContent::Text(b"John (the ".to_vec()));
+        if let CommentContent::Comment(ref nested) = parsed.content()[1] {
+            assert_eq!(nested.content(), &[CommentContent::Text(b"real) man".to_vec())][..]);
+        } else {
+            panic!("expected a nested comment");
+        }
+
+        assert_eq!(comment(b"(a\r\n b)").0.text(), b"a b");
+        assert_eq!(comment(b"()").0.content().len(), 0);
+        eof!(comment_len, b"");
+        eof!(comment_len, b"(a (b)");
+        tok!(comment_len, b"a", Token::Comment, b'a', 0);
+    }
+
+    #[test]
+    fn test_utf8_comment() {
+        let (parsed, len) = Comment::parse(u!("(café \\ü)")).unwrap();
+        assert_eq!((parsed.text(), len), ("café ü".as_bytes().to_vec(), 11));
+        // bytes that are not UTF-8 are only accepted by default
+        assert_eq!(comment(b"(caf\xe9)").0.text(), b"caf\xe9");
+        enc!(comment_len, b"(caf\xe9)", Token::Comment, 4);
+        enc!(comment_len, b"(\\\xe9)", Token::Comment, 2);
+    }
+
+    #[test]
+    fn test_parse_with_comments() {
+        let (mailbox, comments, len) = parse_with_comments(b!(b"john@example.com (John Smith)"), Mailbox::parse).unwrap();
+        assert_eq!(mailbox.display_name(), None);
+        assert_eq!(comments, vec![comment(b"(John Smith)").0]);
+        assert_eq!(len, 29);
+
+        let input = b"\"(not a comment)\" <a@[(1)]> (one), b@example.com (two (nested))";
+        let (addresses, comments, _) = parse_with_comments(b!(input), parse_address_list).unwrap();
+        assert_eq!(addresses.len(), 2);
+        let texts: Vec<Vec<u8>> = comments.iter().map(Comment::text).collect();
+        assert_eq!(texts, vec![b"one".to_vec(), b"two (nested)".to_vec()]);
+
+        let (_, comments, _) = parse_with_comments(b!(b"Fri, 21 Nov 1997 09:55:06 -0600 (CST)"), DateTime::parse).unwrap();
+        assert_eq!(comments[0].text(), b"CST");
+    }
+}
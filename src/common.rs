@@ -1,17 +1,19 @@
-use errors::{parse_ok, Error, ErrorKind, ParseResult};
+use alloc::borrow::Cow;
+use core::fmt;
+use core::str;
+
+use atom::{is_atext, parse_atom, skip_atom_text, starts_atext};
+use cow::parse_cow;
+use encoded_word::write_encoded_words;
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use quoted_string::{parse_quoted_string, write_quoted_string, DEL};
+use sink::{Counter, Sink};
+use whitespaces::skip_cfws;
 use Buffer;
-use std::io::Write;
-use whitespaces::{skip_cfws, replace_cfws};
-use quoted_string::{parse_quoted_string, read_quoted_string, DEL};
-use atom::{parse_atom, read_atom};
 
-/// If the given byte is an upper case alphabetical character, return the same character as lowercase. Otherwise, return the byte.
-pub fn lowercase(c: u8) -> u8 {
-    match c {
-        b'A'...b'Z' => c + 32,
-        _ => c,
-    }
-}
+/// The tokens a word can be made of.
+const WORD: &[Token] = &[Token::Atom, Token::QuotedString];
 
 /// Return `true` if the byte represents a non-whitespace control character.
 /// See [RFC5322 section 4.1](https://tools.ietf.org/html/rfc5322#section-4.1)
@@ -24,27 +26,7 @@ pub fn lowercase(c: u8) -> u8 {
 ///                      %d127              ;  white space characters
 /// ```
 pub fn is_obs_no_ws_ctl(c: u8) -> bool {
-    (c >= 1 && c <= 8) || c == 11 || c == 12 || (c >= 14 && c <= 31) || c == DEL
-}
-
-/// Return `true` is the byte represents an "obs-ctext" character as defined in
-/// [RFC5322 section 4.1](https://tools.ietf.org/html/rfc5322#section-4.1)
-///
-/// ```no_rust
-///  obs-ctext       =   obs-NO-WS-CTL
-/// ```
-pub fn is_obs_ctext(c: u8) -> bool {
-    is_obs_no_ws_ctl(c)
-}
-
-/// Return `true` is the byte represents an "obs-qtext" character as defined in
-/// [RFC5322 section 4.1](https://tools.ietf.org/html/rfc5322#section-4.1)
-///
-/// ```no_rust
-///  obs-qtext       =   obs-NO-WS-CTL
-/// ```
-pub fn is_obs_qtext(c: u8) -> bool {
-    is_obs_no_ws_ctl(c)
+    (1..=8).contains(&c) || c == 11 || c == 12 || (14..=31).contains(&c) || c == DEL
 }
 
 /// Return true if the byte represents a "special" primitive token
@@ -61,7 +43,7 @@ pub fn is_obs_qtext(c: u8) -> bool {
 ///
 /// See [RFC5322 section 3.2.3].
 ///
-/// [RFC2822 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
+/// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
 pub fn is_special(c: u8) -> bool {
     c == b'(' || c == b')' ||
         c == b'<' || c == b'>' ||
@@ -82,78 +64,341 @@ pub fn is_special(c: u8) -> bool {
 ///
 /// See [RFC2234 section 6.1](https://tools.ietf.org/html/rfc2234#section6.1)
 pub fn is_vchar(c: u8) -> bool {
-    c >= 33 && c <= 126
+    (33..=126).contains(&c)
+}
+
+/// Return the length of the non-ASCII character at index `i` of the remaining bytes, if the
+/// buffer accepts UTF-8 (see [`Buffer::utf8`]). `None` is returned for an ASCII byte, and for any
+/// byte if the buffer only accepts US-ASCII. Malformed UTF-8 results in an `Encoding` error for
+/// the given token. See [RFC6532 section 3.1].
+///
+/// ```no_rust
+/// UTF8-non-ascii  =   UTF8-2 / UTF8-3 / UTF8-4
+/// UTF8-2          =   %xC2-DF UTF8-tail
+/// UTF8-3          =   %xE0 %xA0-BF UTF8-tail / %xE1-EC 2( UTF8-tail ) /
+///                     %xED %x80-9F UTF8-tail / %xEE-EF 2( UTF8-tail )
+/// UTF8-4          =   %xF0 %x90-BF 2( UTF8-tail ) / %xF1-F3 3( UTF8-tail ) /
+///                     %xF4 %x80-8F 2( UTF8-tail )
+/// UTF8-tail       =   %x80-BF
+/// ```
+///
+/// [`Buffer::utf8`]: ../struct.Buffer.html#method.utf8
+/// [RFC6532 section 3.1]: https://tools.ietf.org/html/rfc6532#section-3.1
+pub fn utf8_non_ascii_len(input: &Buffer, i: usize, token: Token) -> Result<Option<usize>, Error> {
+    let bytes = input.remaining();
+    if !input.accepts_utf8() || i >= bytes.len() || bytes[i] < 0x80 {
+        return Ok(None);
+    }
+    let len = match bytes[i] {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 0,
+    };
+    match bytes.get(i..i + len) {
+        // the lead byte gives the length, and `from_utf8` rejects overlong forms and surrogates
+        Some(character) if len > 0 && str::from_utf8(character).is_ok() => Ok(Some(len)),
+        _ => Err(ErrorKind::Encoding {
+            token,
+            position: input.position() + i,
+        }.into()),
+    }
+}
+
+/// Parse a word, and write it into the provided writer. Atoms are written as is, and quoted
+/// strings are unquoted. See [RFC5322 section 3.2.5].
+///
+/// ```no_rust
+/// word            =   atom / quoted-string
+/// ```
+///
+/// [RFC5322 section 3.2.5]: https://tools.ietf.org/html/rfc5322#section-3.2.5
+pub fn parse_word<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let i = skip_cfws(input).unwrap_or(0);
+    if i >= bytes.len() {
+        return Err(ErrorKind::Eof.into());
+    }
+    match bytes[i] {
+        b'"' => parse_quoted_string(input, writer),
+        c if starts_atext(input, c) => parse_atom(input, writer),
+        c => {
+            let error: Error = ErrorKind::Token {
+                token: Token::Word,
+                byte: c,
+                position: input.position() + i,
+            }.into();
+            Err(error.expecting(WORD))
+        }
+    }
 }
 
-pub fn read_word(buf: &[u8]) -> ParseResult {
-    read_atom(buf).or_else(|e| match *e.kind() {
-        ErrorKind::Parsing => read_quoted_string(buf),
-        _ => Err(e),
-    })
+/// Parse a word, and return it, borrowed from the input unless it is a quoted string with
+/// quoted-pairs. See [`parse_word`].
+///
+/// [`parse_word`]: fn.parse_word.html
+pub fn parse_word_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_word)
 }
 
-pub fn parse_word<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
-    parse_atom(buf, writer).or_else(|e| match *e.kind() {
-        ErrorKind::Parsing => parse_quoted_string(buf, writer),
-        _ => Err(e),
-    })
+/// Read a word. See [RFC5322 section 3.2.5].
+///
+/// ```no_rust
+/// word            =   atom / quoted-string
+/// ```
+///
+/// [RFC5322 section 3.2.5]: https://tools.ietf.org/html/rfc5322#section-3.2.5
+pub fn skip_word(input: &Buffer) -> Result<usize, Error> {
+    parse_word(input, &mut Counter::new())
 }
 
+/// Parse a phrase, and write it into the provided writer. See [RFC5322 section 3.2.5].
+///
+/// ```no_rust
+/// phrase          =   1*word / obs-phrase
 /// obs-phrase      =   word *(word / "." / CFWS)
-/// phrase = 1*word / obs-phrase
-pub fn read_phrase(buf: &[u8]) -> ParseResult {
-    let (_, word) = read_word(buf)?;
-    let mut i = word.len();
-    while i < buf.len() {
-        if let Ok((_, word)) = read_word(&buf[i..]) {
-            i += word.len();
-        } else if let Ok((_, cfws)) = skip_cfws(&buf[i..]) {
-            i += cfws.len();
-        } else if buf[i] == b'.' {
-            i += 1;
-        } else {
+/// ```
+///
+/// Quoted strings are unquoted, comments are removed, and the words are separated by a single
+/// space when CFWS separates them in the input. The trailing CFWS is read, but not written.
+///
+/// [RFC5322 section 3.2.5]: https://tools.ietf.org/html/rfc5322#section-3.2.5
+pub fn parse_phrase<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+    let mut i: usize = 0;
+    let mut words: usize = 0;
+    let mut space = false;
+
+    loop {
+        buffer.set_position(pos + i);
+        if let Ok(len) = skip_cfws(&buffer) {
+            space = true;
+            i += len;
+        }
+        if i >= bytes.len() {
             break;
         }
-    }
-    parse_ok(buf, i)
-}
-
-pub fn parse_phrase<'a, W: Write>(buf: &'a [u8], writer: &mut W) -> ParseResult<'a> {
-    let (_, word) = parse_word(buf, writer)?;
-    let mut i = word.len();
-    while i < buf.len() {
-        match parse_word(&buf[i..], writer) {
-            Ok((_, word)) => {
-                i += word.len();
-                continue;
+        let separator: &[u8] = if space && words > 0 { b" " } else { b"" };
+        buffer.set_position(pos + i);
+        match bytes[i] {
+            b'"' => {
+                writer.write_bytes(separator)?;
+                let len = parse_quoted_string(&buffer, writer)?;
+                // the quoted string also reads the CFWS that follows it
+                space = bytes[i + len - 1] != b'"';
+                i += len;
             }
-            Err(e) => match *e.kind() {
-                ErrorKind::Parsing => return Err(e),
-                _ => {}
-            },
+            c if starts_atext(&buffer, c) => {
+                writer.write_bytes(separator)?;
+                let len = skip_atom_text(&buffer)?;
+                writer.write_bytes(&bytes[i..i + len])?;
+                space = false;
+                i += len;
+            }
+            b'.' if words > 0 => {
+                writer.write_bytes(separator)?;
+                writer.write_bytes(b".")?;
+                space = false;
+                i += 1;
+            }
+            _ => break,
         }
+        words += 1;
+    }
 
-        match replace_cfws(&buf[i..], writer) {
-            Ok((_, cfws)) => {
-                i += cfws.len();
-                continue;
-            }
-            Err(e) => match *e.kind() {
-                ErrorKind::Parsing => return Err(e),
-                _ => {}
-            },
+    if words == 0 {
+        if i >= bytes.len() {
+            return Err(ErrorKind::Eof.into());
         }
+        let error: Error = ErrorKind::Token {
+            token: Token::Word,
+            byte: bytes[i],
+            position: pos + i,
+        }.into();
+        return Err(error.expecting(WORD));
+    }
+    Ok(i)
+}
 
-        if buf[i] == b'.' {
-            writer.write_all(&b"."[..])?;
-            i += 1;
+/// Parse a phrase, and return it, borrowed from the input unless words are unquoted or separated
+/// by anything but a single space. See [`parse_phrase`].
+///
+/// [`parse_phrase`]: fn.parse_phrase.html
+pub fn parse_phrase_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_phrase)
+}
+
+/// Write the given text as a phrase, so that [`parse_phrase`] returns the same text. The text is
+/// written as is if it is made of atoms separated by single spaces, as a quoted string if it is
+/// printable US-ASCII, and as encoded-words otherwise (see [RFC2047 section 5]).
+///
+/// [`parse_phrase`]: fn.parse_phrase.html
+/// [RFC2047 section 5]: https://tools.ietf.org/html/rfc2047#section-5
+pub fn write_phrase<W: Sink>(text: &[u8], writer: &mut W) -> Result<(), Error> {
+    if text.iter().all(|c| is_vchar(*c) || *c == b' ') {
+        let is_atoms = text
+            .split(|c| *c == b' ')
+            .all(|word| !word.is_empty() && word.iter().all(|c| is_atext(*c)) && !word.starts_with(b"=?"));
+        if is_atoms {
+            writer.write_bytes(text)
         } else {
-            break;
+            write_quoted_string(text, writer)
         }
+    } else {
+        write_encoded_words(text, writer)
     }
-    parse_ok(buf, i)
 }
 
-// unstructured = (*([FWS] VCHAR) *WSP) / obs-unstruct
-// obs-utext    = %d0 / obs-NO-WS-CTL / VCHAR
-// obs-unstruct = *((*LF *CR *(obs-utext *LF *CR)) / FWS)
+/// Write the given text as an unstructured field value. The text is written as is if it only
+/// contains printable US-ASCII characters and spaces, and as encoded-words otherwise (see
+/// [RFC2047 section 5]).
+///
+/// ```no_rust
+/// unstructured    =   (*([FWS] VCHAR) *WSP) / obs-unstruct
+/// ```
+///
+/// [RFC2047 section 5]: https://tools.ietf.org/html/rfc2047#section-5
+pub fn write_unstructured<W: Sink>(text: &[u8], writer: &mut W) -> Result<(), Error> {
+    let is_plain = text.iter().all(|c| is_vchar(*c) || *c == b' ' || *c == b'\t')
+        && !text.split(|c| *c == b' ' || *c == b'\t').any(|word| word.starts_with(b"=?"));
+    if is_plain {
+        writer.write_bytes(text)?;
+        Ok(())
+    } else {
+        write_encoded_words(text, writer)
+    }
+}
+
+/// Return text that the grammar guarantees to be US-ASCII, or well-formed UTF-8 when the buffer
+/// it was parsed from accepts UTF-8, as a string slice.
+pub fn ascii_str(bytes: &[u8]) -> &str {
+    str::from_utf8(bytes).expect("the grammar only allows US-ASCII or well-formed UTF-8")
+}
+
+/// Parse a value that must span the whole input, except for trailing CFWS. This is used to
+/// implement `FromStr`. If bytes remain after the value, a `Token` error is returned for the first
+/// of them.
+pub fn parse_all<T, F>(input: &[u8], token: Token, parse: F) -> Result<T, Error>
+where
+    F: FnOnce(&Buffer) -> Result<(T, usize), Error>,
+{
+    parse_remaining(&Buffer::new(input), token, parse)
+}
+
+/// Parse a value that must span the remaining bytes of the buffer, except for trailing CFWS. See
+/// [`parse_all`].
+///
+/// [`parse_all`]: fn.parse_all.html
+pub fn parse_remaining<'buf, T, F>(input: &Buffer<'buf>, token: Token, parse: F) -> Result<T, Error>
+where
+    F: FnOnce(&Buffer<'buf>) -> Result<(T, usize), Error>,
+{
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+    let (value, mut i) = parse(&buffer)?;
+    buffer.set_position(pos + i);
+    i += skip_cfws(&buffer).unwrap_or(0);
+    if i < bytes.len() {
+        return Err(ErrorKind::Token {
+            token,
+            byte: bytes[i],
+            position: pos + i,
+        }.into());
+    }
+    Ok(value)
+}
+
+/// Format a value with its `write_to` method. This is used to implement `Display`: the output of
+/// the writers is US-ASCII, except for text that was not valid UTF-8 in the parsed input, which is
+/// replaced by U+FFFD.
+pub fn display<F>(f: &mut fmt::Formatter, write_to: F) -> fmt::Result
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+{
+    let mut output = Vec::new();
+    write_to(&mut output).map_err(|_| fmt::Error)?;
+    f.write_str(&String::from_utf8_lossy(&output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phrase(input: &Buffer) -> Result<(Vec<u8>, usize), Error> {
+        let mut phrase = Vec::new();
+        let len = parse_phrase(input, &mut phrase)?;
+        Ok((phrase, len))
+    }
+
+    fn written_phrase(text: &[u8]) -> Vec<u8> {
+        let mut phrase = Vec::new();
+        write_phrase(text, &mut phrase).unwrap();
+        phrase
+    }
+
+    #[test]
+    fn test_parse_word() {
+        let mut word = Vec::new();
+        assert_eq!(parse_word(b!(b" \"John \\\"J\\\" Smith\" "), &mut word).unwrap(), 20);
+        assert_eq!(word, b"John \"J\" Smith");
+        ok!(skip_word, b"john smith", 5);
+        eof!(skip_word, b" ");
+        tok!(skip_word, b" <john>", Token::Word, b'<', 1);
+    }
+
+    #[test]
+    fn test_parse_phrase() {
+        assert_eq!(phrase(b!(b"John Smith <")).unwrap(), (b"John Smith".to_vec(), 11));
+        assert_eq!(
+            phrase(b!(b" John (the man)\r\n Q. \"Public\" <")).unwrap(),
+            (b"John Q. Public".to_vec(), 30)
+        );
+        assert_eq!(phrase(b!(b"\"John\"Smith")).unwrap(), (b"JohnSmith".to_vec(), 11));
+        assert_eq!(phrase(b!(b"\"John\" Smith")).unwrap(), (b"John Smith".to_vec(), 12));
+        assert_eq!(phrase(b!(b"john@example.com")).unwrap(), (b"john".to_vec(), 4));
+        eof!(phrase, b"");
+        eof!(phrase, b"\"John");
+        tok!(phrase, b".John", Token::Word, b'.', 0);
+        tok!(phrase, b"(comment) <", Token::Word, b'<', 10);
+    }
+
+    #[test]
+    fn test_utf8_phrase() {
+        assert_eq!(phrase(u!("José \"Ñoño\" Pérez <")).unwrap(), ("José Ñoño Pérez".as_bytes().to_vec(), 22));
+        let mut word = Vec::new();
+        assert_eq!(parse_word(u!("\"a\\é\""), &mut word).unwrap(), 6);
+        assert_eq!(word, "aé".as_bytes());
+        tok!(skip_word, "\"é\"", Token::QuotedString, 0xc3, 1);
+        enc!(skip_word, b"\"a\xff\"", Token::QuotedText, 2);
+        enc!(skip_word, b"\"\xff\"", Token::QuotedText, 1);
+        enc!(skip_word, b"\"\\\xe2\x82\"", Token::QuotedPair, 2);
+    }
+
+    #[test]
+    fn test_write_phrase() {
+        assert_eq!(written_phrase(b"John Smith"), b"John Smith");
+        assert_eq!(written_phrase(b"John Q. Public"), b"\"John Q. Public\"");
+        assert_eq!(written_phrase(b"Smith, John"), b"\"Smith, John\"");
+        assert_eq!(written_phrase(b"a \"b\" \\c"), b"\"a \\\"b\\\" \\\\c\"");
+        assert_eq!(written_phrase(b"John  Smith"), b"\"John  Smith\"");
+        assert_eq!(written_phrase(b""), b"\"\"");
+        for text in &[&b"John Q. Public"[..], b"Smith, John", b"a \"b\" \\c", b" x "] {
+            let written = written_phrase(text);
+            assert_eq!(phrase(b!(written)).unwrap().0, text.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_write_unstructured() {
+        let mut value = Vec::new();
+        write_unstructured(b"Hello, world!", &mut value).unwrap();
+        assert_eq!(value, b"Hello, world!");
+
+        let mut value = Vec::new();
+        write_unstructured(b"=?utf-8?q?not_encoded?=", &mut value).unwrap();
+        assert_eq!(value, b"=?utf-8?b?PT91dGYtOD9xP25vdF9lbmNvZGVkPz0=?=");
+    }
+}
@@ -0,0 +1,161 @@
+use alloc::borrow::Cow;
+use core::str::{self, Utf8Error};
+
+use errors::Error;
+use prelude::*;
+use sink::Sink;
+use Buffer;
+
+/// A writer that borrows what is written from the input of a parser when possible, and only
+/// allocates when the output differs from the input. It is used to implement the zero-copy
+/// variants of the parsers that write into a [`Sink`], such as [`parse_quoted_string_cow`].
+///
+/// The output is borrowed as long as each write is the continuation of the previous ones in the
+/// input: a quoted string without quoted-pair, or a phrase whose words are separated by single
+/// spaces, are borrowed, while unfolded whitespaces or unescaped quoted-pairs are copied.
+///
+/// [`Sink`]: ../sink/trait.Sink.html
+/// [`parse_quoted_string_cow`]: ../quoted_string/fn.parse_quoted_string_cow.html
+#[derive(Debug)]
+pub struct CowWriter<'buf> {
+    input: &'buf [u8],
+    /// The range of the input that was written, while the output is borrowed
+    start: usize,
+    end: usize,
+    owned: Option<Vec<u8>>,
+}
+
+impl<'buf> CowWriter<'buf> {
+    /// Create a writer for a parser reading from the given buffer.
+    pub fn new(input: &Buffer<'buf>) -> Self {
+        CowWriter {
+            input: input.remaining(),
+            start: 0,
+            end: 0,
+            owned: None,
+        }
+    }
+
+    /// Return the position in the input of the given bytes, if they are a slice of the input.
+    fn offset(&self, bytes: &[u8]) -> Option<usize> {
+        let offset = (bytes.as_ptr() as usize).wrapping_sub(self.input.as_ptr() as usize);
+        if offset <= self.input.len() && bytes.len() <= self.input.len() - offset {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    pub fn into_cow(self) -> Cow<'buf, [u8]> {
+        match self.owned {
+            Some(owned) => Cow::Owned(owned),
+            None => Cow::Borrowed(&self.input[self.start..self.end]),
+        }
+    }
+}
+
+impl<'buf> Sink for CowWriter<'buf> {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), Error> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if let Some(ref mut owned) = self.owned {
+            owned.extend_from_slice(buf);
+            return Ok(());
+        }
+        let offset = self.offset(buf);
+        if self.start == self.end && offset.is_some() {
+            // the first bytes written
+            self.start = offset.unwrap_or(0);
+            self.end = self.start + buf.len();
+        } else if self.start != self.end && self.input[self.end..].starts_with(buf) {
+            // bytes that are the same as the following bytes of the input, such as the dots of a
+            // dot-atom, do not need to be a slice of the input
+            self.end += buf.len();
+        } else {
+            let mut owned = self.input[self.start..self.end].to_vec();
+            owned.extend_from_slice(buf);
+            self.owned = Some(owned);
+        }
+        Ok(())
+    }
+}
+
+/// Run a parser that writes into a sink, and return what it wrote, borrowed from the
+/// input if possible (see [`CowWriter`]), along with the number of bytes read.
+///
+/// ```rust
+/// use std::borrow::Cow;
+/// use imf::cow::parse_cow;
+/// use imf::quoted_string::parse_quoted_string;
+/// use imf::Buffer;
+///
+/// let (text, _) = parse_cow(&Buffer::new(b"\"John Doe\""), parse_quoted_string).unwrap();
+/// assert_eq!(text, Cow::Borrowed(&b"John Doe"[..]));
+///
+/// let (text, _) = parse_cow(&Buffer::new(b"\"John \\\"Doe\\\"\""), parse_quoted_string).unwrap();
+/// assert_eq!(text, Cow::<[u8]>::Owned(b"John \"Doe\"".to_vec()));
+/// ```
+///
+/// [`CowWriter`]: struct.CowWriter.html
+pub fn parse_cow<'buf, F>(input: &Buffer<'buf>, parse: F) -> Result<(Cow<'buf, [u8]>, usize), Error>
+where
+    F: FnOnce(&Buffer<'buf>, &mut CowWriter<'buf>) -> Result<usize, Error>,
+{
+    let mut writer = CowWriter::new(input);
+    let len = parse(input, &mut writer)?;
+    Ok((writer.into_cow(), len))
+}
+
+/// Convert the output of a zero-copy parser into text, without copying it.
+pub fn into_str(bytes: Cow<'_, [u8]>) -> Result<Cow<'_, str>, Utf8Error> {
+    match bytes {
+        Cow::Borrowed(bytes) => str::from_utf8(bytes).map(Cow::Borrowed),
+        Cow::Owned(bytes) => String::from_utf8(bytes)
+            .map(Cow::Owned)
+            .map_err(|e| e.utf8_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address::parse_local_part_cow;
+    use common::parse_phrase_cow;
+    use quoted_string::parse_quoted_string_cow;
+    use whitespaces::unfold_fws_cow;
+
+    #[test]
+    fn test_parse_cow() {
+        let (phrase, len) = parse_phrase_cow(b!(b" John  (comment) Doe\r\n")).unwrap();
+        assert_eq!((&phrase[..], len), (&b"John Doe"[..], 20));
+        assert!(matches!(phrase, Cow::Owned(_)));
+        let (phrase, _) = parse_phrase_cow(b!(b" John Q. Doe <")).unwrap();
+        assert_eq!(&phrase[..], b"John Q. Doe");
+        assert!(matches!(phrase, Cow::Borrowed(_)));
+
+        let (local_part, _) = parse_local_part_cow(b!(b"john.doe@example.com")).unwrap();
+        assert_eq!(&local_part[..], b"john.doe");
+        assert!(matches!(local_part, Cow::Borrowed(_)));
+        let (local_part, _) = parse_local_part_cow(b!(b"john . doe@example.com")).unwrap();
+        assert_eq!(&local_part[..], b"john.doe");
+        assert!(matches!(local_part, Cow::Owned(_)));
+
+        let (fws, _) = unfold_fws_cow(b!(b"  x")).unwrap();
+        assert!(matches!(fws, Cow::Borrowed(_)));
+        let (fws, _) = unfold_fws_cow(b!(b" \r\n x")).unwrap();
+        assert_eq!(fws, Cow::<[u8]>::Owned(b"  ".to_vec()));
+
+        // nothing written
+        let (empty, _) = parse_quoted_string_cow(b!(b"\"\"")).unwrap();
+        assert_eq!(empty, Cow::Borrowed(&b""[..]));
+        assert!(parse_quoted_string_cow(b!(b"\"a")).unwrap_err().is_eof());
+    }
+
+    #[test]
+    fn test_into_str() {
+        assert_eq!(into_str(Cow::Borrowed(&b"caf\xc3\xa9"[..])).unwrap(), Cow::Borrowed("café"));
+        assert_eq!(into_str(Cow::Owned(b"a b".to_vec())).unwrap(), Cow::<str>::Owned("a b".to_string()));
+        assert_eq!(into_str(Cow::Borrowed(&b"a\xc3"[..])).unwrap_err().valid_up_to(), 1);
+    }
+}
@@ -0,0 +1,608 @@
+use core::hash::{Hash, Hasher};
+use core::ops::Range;
+
+use atom::starts_atext;
+use common::is_special;
+use errors::{Error, ErrorKind, Token};
+use message::parse_field;
+use prelude::*;
+use sink::Sink;
+use Buffer;
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// Return the index where the folding white space that starts at index `i` ends, or `i` if there
+/// is none. Line breaks (CRLF or a lone LF) are only part of the FWS when a whitespace follows
+/// them.
+fn fws_end(bytes: &[u8], mut i: usize) -> usize {
+    loop {
+        if i < bytes.len() && is_wsp(bytes[i]) {
+            i += 1;
+        } else if bytes[i..].starts_with(b"\r\n") && i + 2 < bytes.len() && is_wsp(bytes[i + 2]) {
+            i += 3;
+        } else if bytes[i..].starts_with(b"\n") && i + 1 < bytes.len() && is_wsp(bytes[i + 1]) {
+            i += 2;
+        } else {
+            return i;
+        }
+    }
+}
+
+/// The kind of a node of a concrete syntax tree.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum NodeKind {
+    /// Folding white space, including the line breaks used for folding
+    Fws,
+    /// A comment, including its parentheses. Its children are its content.
+    Comment,
+    /// A quoted string, including its quotes. Its children are its content.
+    QuotedString,
+    /// A domain literal, including its brackets. Its children are its content.
+    DomainLiteral,
+    /// Text in a comment, a quoted string, a domain literal, or an unstructured value
+    Text,
+    /// A quoted pair, including its backslash
+    QuotedPair,
+    /// A sequence of `atext` characters
+    Atom,
+    /// A special character other than the delimiters of comments, quoted strings and domain
+    /// literals, for instance `@` or `,`
+    Special,
+}
+
+/// A node of a concrete syntax tree. Nodes keep the bytes they were parsed from, so that writing
+/// the nodes of a value one after the other gives back the original bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node<'buf> {
+    kind: NodeKind,
+    position: usize,
+    bytes: &'buf [u8],
+    children: Vec<Node<'buf>>,
+}
+
+impl<'buf> Node<'buf> {
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /// Return the bytes of the node, exactly as found in the input.
+    pub fn bytes(&self) -> &'buf [u8] {
+        self.bytes
+    }
+
+    /// Return the position of the node in the buffer it was parsed from.
+    pub fn span(&self) -> Range<usize> {
+        self.position..self.position + self.bytes.len()
+    }
+
+    /// Return the content of a comment, a quoted string or a domain literal. Other nodes have no
+    /// children.
+    pub fn children(&self) -> &[Node<'buf>] {
+        &self.children
+    }
+
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_bytes(self.bytes)?;
+        Ok(())
+    }
+}
+
+/// Return `true` if the byte ends a run of text in the given context.
+fn ends_text(c: u8, context: NodeKind) -> bool {
+    if is_wsp(c) || c == b'\r' || c == b'\n' || c == b'\\' {
+        return true;
+    }
+    match context {
+        NodeKind::Comment => c == b'(' || c == b')',
+        NodeKind::QuotedString => c == b'"',
+        _ => c == b'[' || c == b']',
+    }
+}
+
+/// Parse a structured field value into a concrete syntax tree, without losing any byte: FWS,
+/// comments and their nesting, quoted strings and domain literals are kept, along with their
+/// position. The whole input is consumed.
+///
+/// The value is only split into tokens: whether the tokens form, for instance, an address list
+/// is not checked. Bytes that cannot appear in a structured value result in a `Token` error, and
+/// unterminated comments, quoted strings and domain literals in `ErrorKind::Eof`.
+pub fn parse_structured_value<'buf>(input: &Buffer<'buf>) -> Result<Vec<Node<'buf>>, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    // comments, quoted strings and domain literals being read, with their start and children
+    let mut open: Vec<(NodeKind, usize, Vec<Node<'buf>>)> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        let context = open.last().map(|&(kind, _, _)| kind);
+        let c = bytes[i];
+        let error = |token| -> Error {
+            ErrorKind::Token {
+                token,
+                byte: c,
+                position: pos + i,
+            }.into()
+        };
+
+        let opened = match (c, context) {
+            (b'(', None) | (b'(', Some(NodeKind::Comment)) => Some(NodeKind::Comment),
+            (b'"', None) => Some(NodeKind::QuotedString),
+            (b'[', None) => Some(NodeKind::DomainLiteral),
+            _ => None,
+        };
+        if let Some(kind) = opened {
+            open.push((kind, i, Vec::new()));
+            i += 1;
+            continue;
+        }
+        let closed = matches!(
+            (c, context),
+            (b')', Some(NodeKind::Comment)) | (b'"', Some(NodeKind::QuotedString)) | (b']', Some(NodeKind::DomainLiteral))
+        );
+        if closed {
+            let (kind, start, children) = open.pop().unwrap();
+            let node = Node {
+                kind,
+                position: pos + start,
+                bytes: &bytes[start..=i],
+                children,
+            };
+            match open.last_mut() {
+                Some(&mut (_, _, ref mut parent)) => parent.push(node),
+                None => nodes.push(node),
+            }
+            i += 1;
+            continue;
+        }
+
+        let (kind, end) = if is_wsp(c) || c == b'\r' || c == b'\n' {
+            let end = fws_end(bytes, i);
+            if end == i {
+                return Err(if i + 2 >= bytes.len() { ErrorKind::Eof.into() } else { error(Token::Fws) });
+            }
+            (NodeKind::Fws, end)
+        } else if let Some(context) = context {
+            if c == b'\\' {
+                if i + 1 >= bytes.len() {
+                    return Err(ErrorKind::Eof.into());
+                }
+                (NodeKind::QuotedPair, i + 2)
+            } else if ends_text(c, context) {
+                // a bracket in a domain literal
+                return Err(error(Token::Domain));
+            } else {
+                let len = bytes[i..].iter().take_while(|c| !ends_text(**c, context)).count();
+                (NodeKind::Text, i + len)
+            }
+        } else if starts_atext(input, c) {
+            (NodeKind::Atom, i + bytes[i..].iter().take_while(|c| starts_atext(input, **c)).count())
+        } else if is_special(c) && c != b')' && c != b']' && c != b'\\' {
+            (NodeKind::Special, i + 1)
+        } else {
+            return Err(error(Token::Field));
+        };
+        let node = Node {
+            kind,
+            position: pos + i,
+            bytes: &bytes[i..end],
+            children: Vec::new(),
+        };
+        match open.last_mut() {
+            Some(&mut (_, _, ref mut parent)) => parent.push(node),
+            None => nodes.push(node),
+        }
+        i = end;
+    }
+
+    if !open.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
+    Ok(nodes)
+}
+
+/// Parse an unstructured field value into a concrete syntax tree made of FWS and text nodes,
+/// without losing any byte. The whole input is consumed.
+pub fn parse_unstructured_value<'buf>(input: &Buffer<'buf>) -> Vec<Node<'buf>> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut nodes = Vec::new();
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        let mut end = fws_end(bytes, i);
+        let kind = if end > i {
+            NodeKind::Fws
+        } else {
+            while end < bytes.len() && fws_end(bytes, end) == end {
+                end += 1;
+            }
+            NodeKind::Text
+        };
+        nodes.push(Node {
+            kind,
+            position: pos + i,
+            bytes: &bytes[i..end],
+            children: Vec::new(),
+        });
+        i = end;
+    }
+    nodes
+}
+
+/// Return the span of the first `len` bytes of the input, without their leading and trailing
+/// CFWS. If the bytes cannot be split into tokens (see [`parse_structured_value`]), the span of
+/// all of them is returned.
+///
+/// [`parse_structured_value`]: fn.parse_structured_value.html
+pub fn trim_cfws(input: &Buffer, len: usize) -> Range<usize> {
+    let pos = input.position();
+    let inner = input.clone().into_inner();
+    let buffer = Buffer::with_offset(&inner[..pos + len], pos).utf8(input.accepts_utf8());
+    let nodes = match parse_structured_value(&buffer) {
+        Ok(nodes) => nodes,
+        Err(_) => return pos..pos + len,
+    };
+    let mut tokens = nodes.iter().filter(|node| node.kind != NodeKind::Fws && node.kind != NodeKind::Comment);
+    match tokens.next() {
+        Some(first) => first.span().start..tokens.next_back().unwrap_or(first).span().end,
+        None => pos..pos,
+    }
+}
+
+/// The position of a parsed element in the buffer it was parsed from, or nothing if the element
+/// was not parsed. Spans are ignored when elements are compared or hashed, so that a parsed
+/// element is equal to the same element created with `new`.
+#[derive(Clone, Debug, Default)]
+pub struct Span(Option<Range<usize>>);
+
+impl Span {
+    pub fn new(range: Range<usize>) -> Self {
+        Span(Some(range))
+    }
+
+    pub fn get(&self) -> Option<Range<usize>> {
+        self.0.clone()
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _: &Span) -> bool {
+        true
+    }
+}
+
+impl Eq for Span {}
+
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+/// Return `true` if the value of fields with the given name is structured, i.e. made of tokens
+/// that can be separated by CFWS. The name is case insensitive.
+pub fn is_structured(name: &[u8]) -> bool {
+    static NAMES: [&[u8]; 25] = [
+        b"date", b"from", b"sender", b"reply-to", b"to", b"cc", b"bcc",
+        b"message-id", b"in-reply-to", b"references", b"keywords",
+        b"resent-date", b"resent-from", b"resent-sender", b"resent-to", b"resent-cc",
+        b"resent-bcc", b"resent-message-id", b"return-path", b"received",
+        b"mime-version", b"content-type", b"content-transfer-encoding", b"content-id",
+        b"content-disposition",
+    ];
+    NAMES.iter().any(|known| known.eq_ignore_ascii_case(name))
+}
+
+/// A header field and the concrete syntax tree of its value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldTree<'buf> {
+    position: usize,
+    raw: &'buf [u8],
+    name: &'buf [u8],
+    value_position: usize,
+    value: &'buf [u8],
+    structured: bool,
+    nodes: Vec<Node<'buf>>,
+}
+
+impl<'buf> FieldTree<'buf> {
+    /// Parse a header field (see [`parse_field`]). On success, the field and the number of bytes
+    /// read are returned.
+    ///
+    /// The value of known structured fields (see [`is_structured`]) is parsed with
+    /// [`parse_structured_value`], and the value of other fields, or of structured fields that
+    /// cannot be split into tokens, with [`parse_unstructured_value`].
+    ///
+    /// [`parse_field`]: ../message/fn.parse_field.html
+    /// [`is_structured`]: fn.is_structured.html
+    /// [`parse_structured_value`]: fn.parse_structured_value.html
+    /// [`parse_unstructured_value`]: fn.parse_unstructured_value.html
+    pub fn parse(input: &Buffer<'buf>) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let (field, len) = parse_field(input)?;
+
+        let name = field.name();
+        let value = field.value();
+        let value_span = field.value_span();
+        let inner = input.clone().into_inner();
+        let value_buffer = Buffer::with_offset(&inner[..value_span.end], value_span.start).utf8(input.accepts_utf8());
+
+        let structured_nodes = if is_structured(name) {
+            parse_structured_value(&value_buffer).ok()
+        } else {
+            None
+        };
+        let structured = structured_nodes.is_some();
+        let nodes = structured_nodes.unwrap_or_else(|| parse_unstructured_value(&value_buffer));
+        let tree = FieldTree {
+            position: pos,
+            raw: &bytes[..len],
+            name,
+            value_position: value_span.start,
+            value,
+            structured,
+            nodes,
+        };
+        Ok((tree, len))
+    }
+
+    pub fn name(&self) -> &'buf [u8] {
+        self.name
+    }
+
+    /// Return the raw value, as returned by [`Field::value`].
+    ///
+    /// [`Field::value`]: ../message/struct.Field.html#method.value
+    pub fn value(&self) -> &'buf [u8] {
+        self.value
+    }
+
+    /// Return the whole field, including its terminating line break.
+    pub fn raw(&self) -> &'buf [u8] {
+        self.raw
+    }
+
+    /// Return the position of the whole field in the buffer it was parsed from.
+    pub fn span(&self) -> Range<usize> {
+        self.position..self.position + self.raw.len()
+    }
+
+    /// Return the position of the value in the buffer it was parsed from.
+    pub fn value_span(&self) -> Range<usize> {
+        self.value_position..self.value_position + self.value.len()
+    }
+
+    /// Return `true` if the value was parsed as a structured value.
+    pub fn is_structured(&self) -> bool {
+        self.structured
+    }
+
+    /// Return the nodes of the value.
+    pub fn nodes(&self) -> &[Node<'buf>] {
+        &self.nodes
+    }
+
+    /// Write the field, exactly as it was parsed.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_bytes(self.raw)?;
+        Ok(())
+    }
+}
+
+/// A header section, made of field trees, that can be written back byte for byte, with some
+/// parts replaced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderTree<'buf> {
+    position: usize,
+    raw: &'buf [u8],
+    fields: Vec<FieldTree<'buf>>,
+}
+
+impl<'buf> HeaderTree<'buf> {
+    /// Parse a header section, as [`parse_header_section`] does. On success, the header and the
+    /// number of bytes read (including the empty line) are returned.
+    ///
+    /// [`parse_header_section`]: ../message/fn.parse_header_section.html
+    pub fn parse(input: &Buffer<'buf>) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+        let mut fields = Vec::new();
+        let mut i: usize = 0;
+
+        while i < bytes.len() {
+            if bytes[i..].starts_with(b"\r\n") {
+                i += 2;
+                break;
+            } else if bytes[i] == b'\n' {
+                i += 1;
+                break;
+            }
+            buffer.set_position(pos + i);
+            let (field, len) = FieldTree::parse(&buffer)?;
+            fields.push(field);
+            i += len;
+        }
+        let header = HeaderTree {
+            position: pos,
+            raw: &bytes[..i],
+            fields,
+        };
+        Ok((header, i))
+    }
+
+    pub fn fields(&self) -> &[FieldTree<'buf>] {
+        &self.fields
+    }
+
+    /// Return the first field with the given name. The name is case insensitive.
+    pub fn field(&self, name: &[u8]) -> Option<&FieldTree<'buf>> {
+        self.fields.iter().find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Return the position of the header section in the buffer it was parsed from.
+    pub fn span(&self) -> Range<usize> {
+        self.position..self.position + self.raw.len()
+    }
+
+    /// Write the header section, exactly as it was parsed.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_bytes(self.raw)?;
+        Ok(())
+    }
+
+    /// Write the header section, replacing the bytes in the given spans (for instance the span of
+    /// a node or of a field value) with new bytes. All the other bytes are written as they were
+    /// parsed. Replacements can be given in any order, and an empty span inserts bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a span is not within the header section, or if spans overlap.
+    pub fn write_with_replacements<W: Sink>(
+        &self,
+        replacements: &[(Range<usize>, &[u8])],
+        writer: &mut W,
+    ) -> Result<(), Error> {
+        let mut replacements: Vec<&(Range<usize>, &[u8])> = replacements.iter().collect();
+        replacements.sort_by_key(|(span, _)| (span.start, span.end));
+        let mut next_write = self.position;
+        for (span, bytes) in replacements {
+            assert!(
+                span.start >= next_write && span.start <= span.end && span.end <= self.position + self.raw.len(),
+                "invalid or overlapping replacement span {:?}",
+                span
+            );
+            writer.write_bytes(&self.raw[next_write - self.position..span.start - self.position])?;
+            writer.write_bytes(bytes)?;
+            next_write = span.end;
+        }
+        writer.write_bytes(&self.raw[next_write - self.position..])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = b"From: \"Smith, John\" (the (real) man)\r\n <john@[192.0.2.1]>\r\nSubject: (not a comment) \"hi\"\r\n\tthere\r\nDate: Fri, 21 Nov 1997 09:55:06 -0600 (\\(CST\\))\r\n\r\nbody";
+
+    fn kinds(nodes: &[Node]) -> Vec<NodeKind> {
+        nodes.iter().map(|node| node.kind()).collect()
+    }
+
+    fn concat(nodes: &[Node]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for node in nodes {
+            node.write_to(&mut output).unwrap();
+        }
+        output
+    }
+
+    #[test]
+    fn test_parse_structured_value() {
+        use self::NodeKind::*;
+
+        let input = b" \"Smith, John\" (the (real)\r\n man) <john@[192.0.2.1]>";
+        let nodes = parse_structured_value(b!(input)).unwrap();
+        assert_eq!(concat(&nodes), &input[..]);
+        assert_eq!(kinds(&nodes), vec![Fws, QuotedString, Fws, Comment, Fws, Special, Atom, Special, DomainLiteral, Special]);
+        assert_eq!(kinds(nodes[1].children()), vec![Text, Fws, Text]);
+        let comment = &nodes[3];
+        assert_eq!(comment.span(), 15..33);
+        assert_eq!(kinds(comment.children()), vec![Text, Fws, Comment, Fws, Text]);
+        assert_eq!(comment.children()[2].bytes(), b"(real)");
+        assert_eq!(comment.children()[3].bytes(), b"\r\n ");
+        assert_eq!(nodes[8].children()[0].bytes(), b"192.0.2.1");
+
+        let nodes = parse_structured_value(b!(b"\"a\\\"b\" (\\)x)")).unwrap();
+        assert_eq!(kinds(nodes[0].children()), vec![Text, QuotedPair, Text]);
+        assert_eq!(kinds(nodes[2].children()), vec![QuotedPair, Text]);
+
+        eof!(parse_structured_value, b"(a (b)");
+        eof!(parse_structured_value, b"\"abc");
+        eof!(parse_structured_value, b"[1.2.3");
+        eof!(parse_structured_value, b"a\r\n");
+        tok!(parse_structured_value, b"a) b", Token::Field, b')', 1);
+        tok!(parse_structured_value, b"a\r\nb", Token::Fws, b'\r', 1);
+        tok!(parse_structured_value, b"[a[b]", Token::Domain, b'[', 2);
+    }
+
+    #[test]
+    fn test_parse_unstructured_value() {
+        let input = b" Re: (hi)\r\n  \"there\"\rx";
+        let nodes = parse_unstructured_value(b!(input));
+        assert_eq!(concat(&nodes), &input[..]);
+        let bytes: Vec<&[u8]> = nodes.iter().map(|node| node.bytes()).collect();
+        assert_eq!(bytes, vec![&b" "[..], b"Re:", b" ", b"(hi)", b"\r\n  ", b"\"there\"\rx"]);
+        assert_eq!(nodes[5].kind(), NodeKind::Text);
+    }
+
+    #[test]
+    fn test_parse_header_tree() {
+        let (header, len) = HeaderTree::parse(b!(HEADER)).unwrap();
+        assert_eq!(len, HEADER.len() - 4);
+        assert_eq!(header.fields().len(), 3);
+        let mut output = Vec::new();
+        header.write_to(&mut output).unwrap();
+        assert_eq!(output, &HEADER[..len]);
+
+        let from = header.field(b"from").unwrap();
+        assert!(from.is_structured());
+        assert_eq!(from.span(), 0..59);
+        assert_eq!(&HEADER[from.value_span()], from.value());
+        assert_eq!(concat(from.nodes()), from.value());
+
+        let subject = header.field(b"Subject").unwrap();
+        assert!(!subject.is_structured());
+        assert_eq!(subject.nodes()[1].bytes(), b"(not");
+
+        let date = header.field(b"Date").unwrap();
+        let comment = date.nodes().last().unwrap();
+        assert_eq!(comment.kind(), NodeKind::Comment);
+        assert_eq!(&HEADER[comment.span()], b"(\\(CST\\))");
+
+        // a structured field that cannot be tokenized is kept as unstructured
+        let (field, _) = FieldTree::parse(b!(b"To: a@example.com)\r\n")).unwrap();
+        assert!(!field.is_structured());
+        assert_eq!(concat(field.nodes()), b" a@example.com)");
+    }
+
+    #[test]
+    fn test_write_with_replacements() {
+        let (header, _) = HeaderTree::parse(b!(HEADER)).unwrap();
+        let from = header.field(b"From").unwrap();
+        let comment = from.nodes().iter().find(|node| node.kind() == NodeKind::Comment).unwrap();
+        let subject = header.field(b"Subject").unwrap();
+        let date = header.field(b"Date").unwrap();
+
+        let mut output = Vec::new();
+        header.write_with_replacements(&[
+            (subject.value_span(), &b" Hello"[..]),
+            (comment.span(), b"(someone)"),
+            (date.span().start..date.span().start, b"X-Gateway: 1\r\n"),
+        ], &mut output).unwrap();
+        assert_eq!(
+            &output[..],
+            &b"From: \"Smith, John\" (someone)\r\n <john@[192.0.2.1]>\r\nSubject: Hello\r\nX-Gateway: 1\r\nDate: Fri, 21 Nov 1997 09:55:06 -0600 (\\(CST\\))\r\n\r\n"[..]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_overlapping_replacements() {
+        let (header, _) = HeaderTree::parse(b!(HEADER)).unwrap();
+        header.write_with_replacements(&[(0..10, &b""[..]), (5..12, b"")], &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn test_trim_cfws() {
+        let input = b!(b" (a) \"b c\" d (e)\r\n f");
+        assert_eq!(trim_cfws(input, 16), 5..12);
+        assert_eq!(trim_cfws(input, 17), 0..17);
+        assert_eq!(trim_cfws(input, 4), 0..0);
+        assert_eq!(trim_cfws(b!(b"a)"), 2), 0..2);
+    }
+}
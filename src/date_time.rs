@@ -0,0 +1,550 @@
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::{display, parse_all};
+use errors::{Error, ErrorKind, Token};
+use sink::Sink;
+use whitespaces::skip_cfws;
+use Buffer;
+
+static DAY_NAMES: [&[u8]; 7] = [b"Mon", b"Tue", b"Wed", b"Thu", b"Fri", b"Sat", b"Sun"];
+
+static MONTH_NAMES: [&[u8]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun",
+    b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+
+/// Time zone names allowed by `obs-zone`, and their offset in hours.
+static ZONE_NAMES: [(&[u8], i16); 10] = [
+    (b"UT", 0), (b"GMT", 0),
+    (b"EST", -5), (b"EDT", -4),
+    (b"CST", -6), (b"CDT", -5),
+    (b"MST", -7), (b"MDT", -6),
+    (b"PST", -8), (b"PDT", -7),
+];
+
+fn is_leap_year(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Return the number of days between 1970-01-01 and the given date of the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Return the year, month and day of the date that is the given number of days after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A date and time, as found in the `Date` header field. See [RFC5322 section 3.3].
+///
+/// ```no_rust
+/// date-time       =   [ day-of-week "," ] date time [CFWS]
+/// day-of-week     =   ([FWS] day-name) / obs-day-of-week
+/// day-name        =   "Mon" / "Tue" / "Wed" / "Thu" /
+///                     "Fri" / "Sat" / "Sun"
+/// date            =   day month year
+/// day             =   ([FWS] 1*2DIGIT FWS) / obs-day
+/// month           =   "Jan" / "Feb" / "Mar" / "Apr" /
+///                     "May" / "Jun" / "Jul" / "Aug" /
+///                     "Sep" / "Oct" / "Nov" / "Dec"
+/// year            =   (FWS 4*DIGIT FWS) / obs-year
+/// time            =   time-of-day zone
+/// time-of-day     =   hour ":" minute [ ":" second ]
+/// hour            =   2DIGIT / obs-hour
+/// minute          =   2DIGIT / obs-minute
+/// second          =   2DIGIT / obs-second
+/// zone            =   (FWS ( "+" / "-" ) 4DIGIT) / obs-zone
+/// ```
+///
+/// The zone is stored as an offset in minutes from UTC. The zone `-0000` has an offset of zero
+/// too, but means that the local zone is unknown: it is remembered, so that it is written back as
+/// `-0000` and not `+0000` (see [`is_zone_unknown`]).
+///
+/// [`is_zone_unknown`]: #method.is_zone_unknown
+///
+/// [RFC5322 section 3.3]: https://tools.ietf.org/html/rfc5322#section-3.3
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    offset: i16,
+    unknown_zone: bool,
+}
+
+impl DateTime {
+    /// Parse a date-time. On success, the date-time and the number of bytes read are returned.
+    ///
+    /// The obsolete syntax is accepted: CFWS can appear anywhere, two and three digits years are
+    /// interpreted as described in [RFC5322 section 4.3], and the obsolete zone names are
+    /// converted into offsets, military zones being considered equivalent to `-0000`. Names are
+    /// case insensitive.
+    ///
+    /// [RFC5322 section 4.3]: https://tools.ietf.org/html/rfc5322#section-4.3
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let mut parser = Parser {
+            bytes: input.remaining(),
+            buffer: input.clone(),
+            pos: input.position(),
+            i: 0,
+        };
+        parser.skip_cfws();
+
+        // [ day-of-week "," ]
+        if parser.peek()?.is_ascii_alphabetic() {
+            parser.name(&DAY_NAMES)?;
+            parser.skip_cfws();
+            parser.expect(b',')?;
+            parser.skip_cfws();
+        }
+
+        // day month year
+        let day_start = parser.i;
+        let day = parser.number(1, 2)?.0 as u8;
+        parser.skip_cfws();
+        let month = parser.name(&MONTH_NAMES)? as u8 + 1;
+        parser.skip_cfws();
+        let year = match parser.number(2, 4)? {
+            (year, 2) if year < 50 => year + 2000,
+            (year, 2) | (year, 3) => year + 1900,
+            (year, _) => year,
+        };
+        parser.skip_cfws();
+
+        // hour ":" minute [ ":" second ]
+        let time_start = parser.i;
+        let hour = parser.number(2, 2)?.0 as u8;
+        parser.skip_cfws();
+        parser.expect(b':')?;
+        parser.skip_cfws();
+        let minute = parser.number(2, 2)?.0 as u8;
+        parser.skip_cfws();
+        let mut second = 0;
+        if parser.peek().ok() == Some(b':') {
+            parser.i += 1;
+            parser.skip_cfws();
+            second = parser.number(2, 2)?.0 as u8;
+            parser.skip_cfws();
+        }
+
+        // zone
+        let (offset, unknown_zone) = parser.zone()?;
+        parser.skip_cfws();
+
+        if year < 1900 || day == 0 || day > days_in_month(year as u16, month) {
+            return Err(parser.error_at(day_start));
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err(parser.error_at(time_start));
+        }
+        let date_time = DateTime {
+            year: year as u16,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            offset,
+            unknown_zone,
+        };
+        Ok((date_time, parser.i))
+    }
+
+    /// Create a date-time from a Unix timestamp (a number of seconds since 1970-01-01T00:00:00Z),
+    /// expressed in the zone which offset from UTC is given in minutes. `None` is returned if the
+    /// year is before 1900 or after 9999, or if the offset is not between -99:59 and +99:59.
+    pub fn from_timestamp(timestamp: i64, offset: i16) -> Option<Self> {
+        if offset.abs() > 99 * 60 + 59 {
+            return None;
+        }
+        let local = timestamp.checked_add(i64::from(offset) * 60)?;
+        let (year, month, day) = civil_from_days(local.div_euclid(86_400));
+        let seconds = local.rem_euclid(86_400);
+        if !(1900..=9999).contains(&year) {
+            return None;
+        }
+        Some(DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+            offset,
+            unknown_zone: false,
+        })
+    }
+
+    /// Return the current date-time, in UTC.
+    #[cfg(feature = "std")]
+    pub fn now() -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        DateTime::from_timestamp(timestamp, 0).expect("the current date is between 1900 and 9999")
+    }
+
+    /// Return the Unix timestamp of the date-time. A leap second is counted as the first second of
+    /// the next minute.
+    pub fn timestamp(&self) -> i64 {
+        let days = days_from_civil(i64::from(self.year), i64::from(self.month), i64::from(self.day));
+        days * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+            - i64::from(self.offset) * 60
+    }
+
+    /// Return the day of the week, from 0 (Monday) to 6 (Sunday).
+    pub fn weekday(&self) -> u8 {
+        let days = days_from_civil(i64::from(self.year), i64::from(self.month), i64::from(self.day));
+        // 1970-01-01 was a Thursday
+        (days + 3).rem_euclid(7) as u8
+    }
+
+    /// Write the date-time in the format recommended by [RFC5322 section 3.3], for instance
+    /// `Fri, 21 Nov 1997 09:55:06 -0600`.
+    ///
+    /// [RFC5322 section 3.3]: https://tools.ietf.org/html/rfc5322#section-3.3
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_bytes(DAY_NAMES[self.weekday() as usize])?;
+        write!(writer, ", {} ", self.day)?;
+        writer.write_bytes(MONTH_NAMES[self.month as usize - 1])?;
+        write!(
+            writer,
+            " {:04} {:02}:{:02}:{:02} {}{:02}{:02}",
+            self.year,
+            self.hour,
+            self.minute,
+            self.second,
+            if self.offset < 0 || self.unknown_zone { '-' } else { '+' },
+            self.offset.abs() / 60,
+            self.offset.abs() % 60
+        )?;
+        Ok(())
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// Return the month, from 1 (January) to 12 (December).
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    /// Return the offset of the zone from UTC, in minutes.
+    pub fn offset(&self) -> i16 {
+        self.offset
+    }
+
+    /// Return whether the zone is `-0000`, which means that the date-time is in UTC but that the
+    /// local zone is unknown. See [RFC5322 section 3.3].
+    ///
+    /// [RFC5322 section 3.3]: https://tools.ietf.org/html/rfc5322#section-3.3
+    pub fn is_zone_unknown(&self) -> bool {
+        self.unknown_zone
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for DateTime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::DateTime, DateTime::parse)
+    }
+}
+
+/// Helper to read the successive tokens of a date-time.
+struct Parser<'buf> {
+    bytes: &'buf [u8],
+    buffer: Buffer<'buf>,
+    pos: usize,
+    i: usize,
+}
+
+impl<'buf> Parser<'buf> {
+    fn error_at(&self, i: usize) -> Error {
+        match self.bytes.get(i) {
+            Some(&byte) => ErrorKind::Token {
+                token: Token::DateTime,
+                byte,
+                position: self.pos + i,
+            }.into(),
+            None => ErrorKind::Eof.into(),
+        }
+    }
+
+    fn peek(&self) -> Result<u8, Error> {
+        self.bytes.get(self.i).cloned().ok_or_else(|| ErrorKind::Eof.into())
+    }
+
+    fn skip_cfws(&mut self) {
+        self.buffer.set_position(self.pos + self.i);
+        self.i += skip_cfws(&self.buffer).unwrap_or(0);
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), Error> {
+        if self.peek()? != c {
+            return Err(self.error_at(self.i));
+        }
+        self.i += 1;
+        Ok(())
+    }
+
+    /// Read between `min` and `max` digits, and return the number they represent and the number
+    /// of digits read.
+    fn number(&mut self, min: usize, max: usize) -> Result<(u32, usize), Error> {
+        let digits = self.bytes[self.i..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits < min || digits > max {
+            return Err(self.error_at(self.i + digits.min(max)));
+        }
+        let value = self.bytes[self.i..self.i + digits]
+            .iter()
+            .fold(0, |value, c| value * 10 + u32::from(c - b'0'));
+        self.i += digits;
+        Ok((value, digits))
+    }
+
+    /// Read one of the given names, and return its index.
+    fn name(&mut self, names: &[&[u8]]) -> Result<usize, Error> {
+        let rest = &self.bytes[self.i..];
+        for (index, name) in names.iter().enumerate() {
+            if rest.len() >= name.len() && rest[..name.len()].eq_ignore_ascii_case(name) {
+                self.i += name.len();
+                return Ok(index);
+            }
+        }
+        Err(self.error_at(self.i))
+    }
+
+    /// Read a zone and return its offset in minutes, and whether it is unknown (`-0000`).
+    fn zone(&mut self) -> Result<(i16, bool), Error> {
+        let c = self.peek()?;
+        if c == b'+' || c == b'-' {
+            self.i += 1;
+            let start = self.i;
+            let (value, _) = self.number(4, 4)?;
+            let (hours, minutes) = ((value / 100) as i16, (value % 100) as i16);
+            if minutes > 59 {
+                return Err(self.error_at(start + 2));
+            }
+            let offset = hours * 60 + minutes;
+            return Ok(if c == b'-' { (-offset, offset == 0) } else { (offset, false) });
+        }
+
+        let len = self.bytes[self.i..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .count();
+        if len == 0 {
+            return Err(self.error_at(self.i));
+        }
+        let name = &self.bytes[self.i..self.i + len];
+        let offset = ZONE_NAMES
+            .iter()
+            .find(|&&(zone, _)| zone.eq_ignore_ascii_case(name))
+            .map(|&(_, hours)| hours * 60);
+        match offset {
+            Some(offset) => {
+                self.i += len;
+                Ok((offset, false))
+            }
+            // military zones
+            None if len == 1 && c != b'j' && c != b'J' => {
+                self.i += len;
+                Ok((0, true))
+            }
+            None => Err(self.error_at(self.i)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prelude::*;
+
+    fn parse(input: &[u8]) -> (DateTime, usize) {
+        DateTime::parse(b!(input)).unwrap()
+    }
+
+    fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8, offset: i16) -> DateTime {
+        DateTime { year, month, day, hour, minute, second, offset, unknown_zone: false }
+    }
+
+    fn unknown_zone(date_time: DateTime) -> DateTime {
+        DateTime { unknown_zone: true, ..date_time }
+    }
+
+    #[test]
+    fn test_parse_date_time() {
+        assert_eq!(
+            parse(b"Fri, 21 Nov 1997 09:55:06 -0600"),
+            (date(1997, 11, 21, 9, 55, 6, -360), 31)
+        );
+        assert_eq!(
+            parse(b"Tue, 1 Jul 2003 10:52:37 +0200"),
+            (date(2003, 7, 1, 10, 52, 37, 120), 30)
+        );
+        assert_eq!(parse(b"1 Jul 2003 10:52 +0530"), (date(2003, 7, 1, 10, 52, 0, 330), 22));
+        assert_eq!(parse(b"29 Feb 2000 23:59:60 +0000"), (date(2000, 2, 29, 23, 59, 60, 0), 26));
+        // trailing CFWS
+        assert_eq!(
+            parse(b"Thu, 13 Feb 1969 23:32:54 -0330 (Newfoundland Time)\r\n"),
+            (date(1969, 2, 13, 23, 32, 54, -210), 51)
+        );
+    }
+
+    #[test]
+    fn test_parse_obsolete_date_time() {
+        assert_eq!(
+            parse(b"Thu,\r\n     13\r\n       Feb\r\n         1969\r\n     23:32\r\n              -0330"),
+            (date(1969, 2, 13, 23, 32, 0, -210), 73)
+        );
+        assert_eq!(
+            parse(b"21 Nov 97 09:55:06 GMT"),
+            (date(1997, 11, 21, 9, 55, 6, 0), 22)
+        );
+        assert_eq!(parse(b"21 nov 03 09 : 55 : 06 est"), (date(2003, 11, 21, 9, 55, 6, -300), 26));
+        assert_eq!(parse(b"21 Nov 103 09:55 PDT"), (date(2003, 11, 21, 9, 55, 0, -420), 20));
+        assert_eq!(parse(b"Fri(comment), 21 Nov 1997 09:55:06 Z"), (unknown_zone(date(1997, 11, 21, 9, 55, 6, 0)), 36));
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let date_time = date(1997, 11, 21, 9, 55, 6, -360);
+        assert_eq!(date_time.timestamp(), 880_127_706);
+        assert_eq!(date_time.weekday(), 4);
+        assert_eq!(DateTime::from_timestamp(880_127_706, -360), Some(date_time));
+        assert_eq!(DateTime::from_timestamp(0, 0), Some(date(1970, 1, 1, 0, 0, 0, 0)));
+        assert_eq!(DateTime::from_timestamp(951_782_400, 0), Some(date(2000, 2, 29, 0, 0, 0, 0)));
+        assert_eq!(DateTime::from_timestamp(-1, 60), Some(date(1970, 1, 1, 0, 59, 59, 60)));
+        assert_eq!(DateTime::from_timestamp(-2_208_988_801, 0), None);
+        assert_eq!(DateTime::from_timestamp(0, 6000), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_now() {
+        assert!(DateTime::now().year() >= 2024);
+
+        let date_time = DateTime::now();
+        let mut output = Vec::new();
+        date_time.write_to(&mut output).unwrap();
+        assert_eq!(DateTime::parse(b!(output)).unwrap(), (date_time, output.len()));
+    }
+
+    #[test]
+    fn test_write_date_time() {
+        let mut output = Vec::new();
+        date(1997, 11, 21, 9, 55, 6, -360).write_to(&mut output).unwrap();
+        assert_eq!(output, b"Fri, 21 Nov 1997 09:55:06 -0600");
+    }
+
+    #[test]
+    fn test_parse_invalid_date_time() {
+        let error = |input: &[u8]| {
+            let e = DateTime::parse(b!(input)).unwrap_err();
+            match *e.kind() {
+                ErrorKind::Token { token, byte, position } => {
+                    assert_eq!(token, Token::DateTime);
+                    Some((byte, position))
+                }
+                ErrorKind::Eof => None,
+                _ => panic!("unexpected error {:?}", e),
+            }
+        };
+        assert_eq!(error(b"Fri 21 Nov 1997 09:55:06 -0600"), Some((b'2', 4)));
+        assert_eq!(error(b"Fry, 21 Nov 1997 09:55:06 -0600"), Some((b'F', 0)));
+        assert_eq!(error(b"21 Now 1997 09:55:06 -0600"), Some((b'N', 3)));
+        assert_eq!(error(b"21 Nov 1997 9:55:06 -0600"), Some((b':', 13)));
+        assert_eq!(error(b"21 Nov 1997 09:55:06 -060"), None);
+        assert_eq!(error(b"21 Nov 19970 09:55:06 -0600"), Some((b'0', 11)));
+        assert_eq!(error(b"21 Nov 1997 09:55:06 CET"), Some((b'C', 21)));
+        assert_eq!(error(b"31 Nov 1997 09:55:06 -0600"), Some((b'3', 0)));
+        assert_eq!(error(b"29 Feb 1900 09:55:06 -0600"), Some((b'2', 0)));
+        assert_eq!(error(b"21 Nov 1997 24:55:06 -0600"), Some((b'2', 12)));
+        assert_eq!(error(b"21 Nov 1997 09:55:06 -0660"), Some((b'6', 24)));
+        assert_eq!(error(b"21 Nov 1997 09:55:06"), None);
+        assert_eq!(error(b"21 Nov"), None);
+    }
+
+    #[test]
+    fn test_display_date_time() {
+        let date: DateTime = "21 nov 97 09:55:06 GMT".parse().unwrap();
+        assert_eq!(date.to_string(), "Fri, 21 Nov 1997 09:55:06 +0000");
+        let date: DateTime = "Fri, 21 Nov 1997 09:55:06 -0600 (CST)".parse().unwrap();
+        assert_eq!(date.to_string(), "Fri, 21 Nov 1997 09:55:06 -0600");
+        assert_eq!(date.to_string().parse::<DateTime>().unwrap(), date);
+        assert!("Fri, 21 Nov 1997 09:55:06 -0600 x".parse::<DateTime>().unwrap_err().is_token());
+
+        // -0000 and military zones are written as -0000, other zero offsets as +0000
+        for input in &["21 Nov 1997 09:55:06 -0000", "21 Nov 1997 09:55:06 A"] {
+            let date: DateTime = input.parse().unwrap();
+            assert!(date.is_zone_unknown());
+            assert_eq!(date.to_string(), "Fri, 21 Nov 1997 09:55:06 -0000");
+            assert_eq!(date.to_string().parse::<DateTime>().unwrap(), date);
+        }
+        let date: DateTime = "21 Nov 1997 09:55:06 +0000".parse().unwrap();
+        assert!(!date.is_zone_unknown());
+        assert_eq!(date.to_string(), "Fri, 21 Nov 1997 09:55:06 +0000");
+    }
+}
@@ -0,0 +1,256 @@
+use core::fmt;
+
+use errors::{write_expected, Error, ErrorKind};
+use prelude::*;
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// The location of a byte in a message, for human readable diagnostics. Lines are terminated by
+/// LF (with or without the preceding CR), and numbered from 1. Columns are byte offsets in the
+/// line, numbered from 1.
+///
+/// In the header section, a field folded over several lines keeps its lines: the location of a
+/// byte in the second line of a field is on the second line of the field, and the line where the
+/// field starts is available with [`field_line`].
+///
+/// [`field_line`]: #method.field_line
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Location {
+    line: usize,
+    column: usize,
+    field_line: usize,
+    /// The position of the first byte of the line
+    line_start: usize,
+    /// The position of the first byte of the field
+    field_start: usize,
+}
+
+impl Location {
+    /// Locate the byte at the given position in the input. Positions past the end of the input
+    /// are located at the end of the input.
+    pub fn locate(input: &[u8], position: usize) -> Self {
+        let position = position.min(input.len());
+        let mut location = Location {
+            line: 1,
+            column: 1,
+            field_line: 1,
+            line_start: 0,
+            field_start: 0,
+        };
+        let mut header = true;
+        for (i, c) in input[..position].iter().enumerate() {
+            if *c != b'\n' {
+                continue;
+            }
+            location.line += 1;
+            location.line_start = i + 1;
+            // an empty line ends the header section: lines starting with a whitespace are not
+            // continuations of a field anymore
+            let rest = &input[i + 1..];
+            if header && (rest.starts_with(b"\r\n") || rest.starts_with(b"\n")) {
+                header = false;
+            }
+            if !(header && rest.first().is_some_and(|c| is_wsp(*c))) {
+                location.field_line = location.line;
+                location.field_start = location.line_start;
+            }
+        }
+        location.column = position - location.line_start + 1;
+        location
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Return the line where the field containing the byte starts. Outside of the header
+    /// section, this is the line of the byte.
+    pub fn field_line(&self) -> usize {
+        self.field_line
+    }
+}
+
+/// Remove the line terminator at the end of a line.
+fn trim_eol(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// A human readable description of a parsing error, with what was being parsed when it occured
+/// (see [`Error::chain`]), the lines of the input where it occured, and a caret under the byte
+/// that triggered it:
+///
+/// ```rust
+/// use imf::address::parse_address_list;
+/// use imf::diagnostic::Diagnostic;
+/// use imf::Buffer;
+///
+/// let input = b"To: a@example.com,\r\n b@example.com, c@@example.com\r\n";
+/// let error = parse_address_list(&Buffer::with_offset(input, 3)).unwrap_err();
+/// assert_eq!(
+///     Diagnostic::new(&error, input).to_string(),
+///     "unexpected byte '@' while parsing domain at line 2, column 19\n\
+///      in address at line 2, column 17\n\
+///      1 | To: a@example.com,\n\
+///      2 |  b@example.com, c@@example.com\n  \
+///        |                   ^\n"
+/// );
+/// ```
+///
+/// Positions in errors are positions in the buffer that was parsed: the input given here must be
+/// that buffer. Errors without position, such as [`ErrorKind::Eof`], are only described.
+///
+/// [`Error::chain`]: ../errors/struct.Error.html#method.chain
+/// [`ErrorKind::Eof`]: ../errors/enum.ErrorKind.html#variant.Eof
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostic<'a> {
+    error: &'a Error,
+    input: &'a [u8],
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(error: &'a Error, input: &'a [u8]) -> Self {
+        Diagnostic { error, input }
+    }
+
+    /// Return the location of the byte that triggered the error, if the root cause of the error
+    /// has a position.
+    pub fn location(&self) -> Option<Location> {
+        self.error.root_cause().position().map(|position| Location::locate(self.input, position))
+    }
+
+    /// Write the contexts of the error, the innermost first.
+    fn write_contexts(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let contexts: Vec<&Error> = self.error.chain().collect();
+        for error in contexts.into_iter().rev() {
+            if let ErrorKind::Context { token, position } = *error.kind() {
+                let location = Location::locate(self.input, position);
+                writeln!(f, "in {} at line {}, column {}", token, location.line, location.column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Diagnostic<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let root = self.error.root_cause();
+        let (token, position) = match *root.kind() {
+            ErrorKind::Token { token, byte, position } => {
+                if byte.is_ascii() {
+                    write!(f, "unexpected byte '{}'", byte.escape_ascii())?;
+                } else {
+                    write!(f, "unexpected byte 0x{:02x}", byte)?;
+                }
+                (token, position)
+            }
+            ErrorKind::Encoding { token, position } => {
+                f.write_str("invalid encoding")?;
+                (token, position)
+            }
+            _ => {
+                writeln!(f, "{}", root)?;
+                return self.write_contexts(f);
+            }
+        };
+        let location = Location::locate(self.input, position);
+        write!(f, " while parsing {} at line {}, column {}", token, location.line, location.column)?;
+        if !root.expected().is_empty() {
+            f.write_str(", ")?;
+            write_expected(f, root.expected())?;
+        }
+        writeln!(f)?;
+        self.write_contexts(f)?;
+
+        // the lines of the field, up to the line of the error
+        let width = location.line.to_string().len();
+        let end = self.input[location.line_start..]
+            .iter()
+            .position(|c| *c == b'\n')
+            .map_or(self.input.len(), |i| location.line_start + i + 1);
+        let lines = self.input[location.field_start..end].split_inclusive(|c| *c == b'\n');
+        for (number, line) in (location.field_line..).zip(lines) {
+            writeln!(f, "{:>width$} | {}", number, String::from_utf8_lossy(trim_eol(line)), width = width)?;
+        }
+        let prefix = String::from_utf8_lossy(&self.input[location.line_start..position.min(self.input.len())]);
+        let indent: String = prefix.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        writeln!(f, "{:>width$} | {}^", "", indent, width = width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address::Address;
+    use encoded_word::EncodedWord;
+    use errors::Token;
+    use message::Message;
+    use Buffer;
+
+    #[test]
+    fn test_locate() {
+        let input = b"Subject: a\r\n b\r\n\tc\nTo: d\r\n\r\n e\r\n";
+        let locate = |position| {
+            let location = Location::locate(input, position);
+            (location.line(), location.column(), location.field_line())
+        };
+        assert_eq!(locate(0), (1, 1, 1));
+        assert_eq!(locate(10), (1, 11, 1));
+        assert_eq!(locate(13), (2, 2, 1));
+        assert_eq!(locate(17), (3, 2, 1));
+        assert_eq!(locate(19), (4, 1, 4));
+        // the body does not contain fields
+        assert_eq!(locate(29), (6, 2, 6));
+        assert_eq!(locate(100), (7, 1, 7));
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let input = b"From: a@example.com\r\nSubject: a\r\n\tb\r\nX Bad: c\r\n\r\n";
+        let error = Message::parse(&Buffer::new(input)).unwrap_err();
+        let diagnostic = Diagnostic::new(&error, input);
+        assert_eq!(diagnostic.location().map(|l| (l.line(), l.column())), Some((4, 3)));
+        assert_eq!(
+            diagnostic.to_string(),
+            "unexpected byte 'B' while parsing field at line 4, column 3\n4 | X Bad: c\n  |   ^\n"
+        );
+
+        let error: Error = ErrorKind::Token { token: Token::Comment, byte: 0xc3, position: 11 }.into();
+        let input = "Date: x\r\n\t(é\tb)".as_bytes();
+        assert_eq!(
+            Diagnostic::new(&error, input).to_string(),
+            "unexpected byte 0xc3 while parsing comment at line 2, column 3\n1 | Date: x\n2 | \t(é\tb)\n  | \t ^\n"
+        );
+
+        let input = b"Subject:=?utf-8?q?caf=E?=\r\n";
+        let error = Message::parse(&Buffer::new(input)).unwrap().fields()[0]
+            .parse_value(EncodedWord::parse)
+            .unwrap_err();
+        assert_eq!(
+            Diagnostic::new(&error, input).to_string(),
+            "invalid encoding while parsing encoded-word at line 1, column 22\n\
+             in field at line 1, column 1\n\
+             1 | Subject:=?utf-8?q?caf=E?=\n\
+             \x20 |                      ^\n"
+        );
+
+        let input = b"john.@example.com";
+        let error = Address::parse(&Buffer::new(input)).unwrap_err();
+        assert_eq!(
+            Diagnostic::new(&error, input).to_string(),
+            "unexpected byte '@' while parsing word at line 1, column 6, expected one of atom, quoted-string\n\
+             1 | john.@example.com\n\
+             \x20 |      ^\n"
+        );
+
+        let error: Error = ErrorKind::Eof.into();
+        assert_eq!(Diagnostic::new(&error, b"").location(), None);
+        assert_eq!(Diagnostic::new(&error, b"").to_string(), "no more byte to read in the buffer\n");
+    }
+}
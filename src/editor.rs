@@ -0,0 +1,254 @@
+use core::ops::Range;
+
+use cst::{FieldTree, HeaderTree};
+use errors::Error;
+use folding::write_unstructured_field;
+use message::check_field_name;
+use prelude::*;
+use sink::Sink;
+use Buffer;
+
+/// Write a new field, with an unstructured value.
+fn new_field(name: &[u8], value: &[u8]) -> Result<Vec<u8>, Error> {
+    check_field_name(name)?;
+    let mut field = Vec::new();
+    write_unstructured_field(name, value, &mut field)?;
+    Ok(field)
+}
+
+/// An editor for the header section of a raw message. Edits are recorded, and applied when the
+/// message is written: the fields that are not edited, and the body, are written exactly as they
+/// were parsed, so that, for instance, DKIM signatures over them stay valid.
+///
+/// Fields are designated by their index in the original header section (see [`fields`]), which
+/// does not change when edits are recorded. New values are written as unstructured values (see
+/// [`write_unstructured_field`]), and new fields are terminated by CRLF.
+///
+/// ```rust
+/// use imf::editor::MessageEditor;
+/// use imf::Buffer;
+///
+/// let input = b"Subject: Hello\r\nBcc: secret@example.com\r\n\r\nHi!\r\n";
+/// let mut editor = MessageEditor::parse(&Buffer::new(input)).unwrap();
+/// editor.prepend("Received", "from mx.example.com; Fri, 21 Nov 1997 09:55:06 -0600").unwrap();
+/// editor.remove_all("Bcc");
+/// let subject = editor.find("Subject").unwrap();
+/// editor.replace_value(subject, "[External] Hello").unwrap();
+/// assert_eq!(
+///     editor.build().unwrap(),
+///     &b"Received: from mx.example.com; Fri, 21 Nov 1997 09:55:06 -0600\r\nSubject: [External] Hello\r\n\r\nHi!\r\n"[..]
+/// );
+/// ```
+///
+/// [`fields`]: #method.fields
+/// [`write_unstructured_field`]: ../folding/fn.write_unstructured_field.html
+#[derive(Clone, Debug)]
+pub struct MessageEditor<'buf> {
+    header: HeaderTree<'buf>,
+    body: &'buf [u8],
+    prepended: Vec<Vec<u8>>,
+    /// The fields inserted after each field
+    inserted: Vec<Vec<Vec<u8>>>,
+    /// The new bytes of each field: empty if the field is removed
+    replaced: Vec<Option<Vec<u8>>>,
+}
+
+impl<'buf> MessageEditor<'buf> {
+    /// Parse a message. The whole buffer is consumed: everything after the header section is the
+    /// body.
+    pub fn parse(input: &Buffer<'buf>) -> Result<Self, Error> {
+        let (header, len) = HeaderTree::parse(input)?;
+        let count = header.fields().len();
+        Ok(MessageEditor {
+            header,
+            body: &input.remaining()[len..],
+            prepended: Vec::new(),
+            inserted: vec![Vec::new(); count],
+            replaced: vec![None; count],
+        })
+    }
+
+    /// Return the fields of the original header section.
+    pub fn fields(&self) -> &[FieldTree<'buf>] {
+        self.header.fields()
+    }
+
+    pub fn body(&self) -> &'buf [u8] {
+        self.body
+    }
+
+    /// Return the index of the first field with the given name. The name is case insensitive.
+    pub fn find<N: AsRef<[u8]>>(&self, name: N) -> Option<usize> {
+        self.fields().iter().position(|field| field.name().eq_ignore_ascii_case(name.as_ref()))
+    }
+
+    /// Add a field at the beginning of the header section, after the fields previously
+    /// prepended. This is where trace fields such as `Received` are added.
+    pub fn prepend<N: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, name: N, value: V) -> Result<(), Error> {
+        self.prepended.push(new_field(name.as_ref(), value.as_ref())?);
+        Ok(())
+    }
+
+    /// Add a field after the field at the given index, and after the fields previously inserted
+    /// there. The field is inserted even if the field at the given index is removed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn insert_after<N: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, index: usize, name: N, value: V) -> Result<(), Error> {
+        let field = new_field(name.as_ref(), value.as_ref())?;
+        self.inserted[index].push(field);
+        Ok(())
+    }
+
+    /// Remove the field at the given index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) {
+        self.replaced[index] = Some(Vec::new());
+    }
+
+    /// Remove all the fields with the given name. The name is case insensitive.
+    pub fn remove_all<N: AsRef<[u8]>>(&mut self, name: N) {
+        for index in 0..self.fields().len() {
+            if self.fields()[index].name().eq_ignore_ascii_case(name.as_ref()) {
+                self.remove(index);
+            }
+        }
+    }
+
+    /// Replace the value of the field at the given index. The field keeps its name, as written
+    /// in the original header section.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn replace_value<V: AsRef<[u8]>>(&mut self, index: usize, value: V) -> Result<(), Error> {
+        let name = self.fields()[index].name();
+        self.replaced[index] = Some(new_field(name, value.as_ref())?);
+        Ok(())
+    }
+
+    /// Write the message, with the edits applied.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        let start = self.header.span().start;
+        let prepended = self.prepended.concat();
+        let mut replacements: Vec<(Range<usize>, &[u8])> = vec![(start..start, &prepended[..])];
+        // whether the bytes written so far end with a line break
+        let mut terminated = true;
+        for (index, field) in self.fields().iter().enumerate() {
+            let bytes = match self.replaced[index] {
+                Some(ref bytes) => {
+                    replacements.push((field.span(), bytes));
+                    &bytes[..]
+                }
+                None => field.raw(),
+            };
+            if !bytes.is_empty() {
+                terminated = bytes.ends_with(b"\n");
+            }
+            let end = field.span().end;
+            if !terminated && !self.inserted[index].is_empty() {
+                // the last field of a message without body may not be terminated
+                replacements.push((end..end, b"\r\n"));
+                terminated = true;
+            }
+            for bytes in &self.inserted[index] {
+                replacements.push((end..end, bytes));
+            }
+        }
+        self.header.write_with_replacements(&replacements, writer)?;
+        writer.write_bytes(self.body)?;
+        Ok(())
+    }
+
+    /// Write the message, with the edits applied, into a new vector.
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let mut message = Vec::new();
+        self.write_to(&mut message)?;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use errors::{ErrorKind, Token};
+
+    const MESSAGE: &[u8] = b"From: John (the man) <john@example.com>\n\
+To: mary@example.com\n\
+Bcc: a@example.com,\n  b@example.com\n\
+Subject: Hello\n\
+Bcc: c@example.com\n\
+\n\
+Body with a line that looks like a field:\n\
+Subject: not a field\n";
+
+    fn editor() -> MessageEditor<'static> {
+        MessageEditor::parse(&Buffer::new(MESSAGE)).unwrap()
+    }
+
+    #[test]
+    fn test_no_edit() {
+        let editor = editor();
+        assert_eq!(editor.fields().len(), 5);
+        assert_eq!(editor.body(), &b"Body with a line that looks like a field:\nSubject: not a field\n"[..]);
+        assert_eq!(editor.build().unwrap(), MESSAGE);
+    }
+
+    #[test]
+    fn test_edit_header() {
+        let mut editor = editor();
+        editor.prepend("Received", "from a by b; Fri, 21 Nov 1997 09:55:06 -0600").unwrap();
+        editor.prepend("X-First", "1").unwrap();
+        editor.remove_all("bcc");
+        let subject = editor.find("subject").unwrap();
+        editor.replace_value(subject, "Réponse").unwrap();
+        editor.insert_after(subject, "X-Scanned", "yes").unwrap();
+        editor.insert_after(4, "X-Last", "2").unwrap();
+        let to = editor.find("To").unwrap();
+        editor.replace_value(to, "ignored").unwrap();
+        editor.remove(to);
+
+        assert_eq!(
+            String::from_utf8(editor.build().unwrap()).unwrap(),
+            "Received: from a by b; Fri, 21 Nov 1997 09:55:06 -0600\r\n\
+X-First: 1\r\n\
+From: John (the man) <john@example.com>\n\
+Subject: =?utf-8?q?R=C3=A9ponse?=\r\n\
+X-Scanned: yes\r\n\
+X-Last: 2\r\n\
+\n\
+Body with a line that looks like a field:\n\
+Subject: not a field\n"
+        );
+    }
+
+    #[test]
+    fn test_edit_unterminated_header() {
+        let mut editor = MessageEditor::parse(&Buffer::new(b"Subject: Hello")).unwrap();
+        editor.insert_after(0, "X-Note", "a").unwrap();
+        assert_eq!(editor.build().unwrap(), &b"Subject: Hello\r\nX-Note: a\r\n"[..]);
+
+        // a replaced field is terminated
+        let mut editor = MessageEditor::parse(&Buffer::new(b"Subject: Hello")).unwrap();
+        editor.replace_value(0, "x").unwrap();
+        editor.insert_after(0, "X-Note", "a").unwrap();
+        assert_eq!(editor.build().unwrap(), &b"Subject: x\r\nX-Note: a\r\n"[..]);
+
+        // the field before a removed field is terminated
+        let mut editor = MessageEditor::parse(&Buffer::new(b"A: 1\r\nB: 2")).unwrap();
+        editor.remove(1);
+        editor.insert_after(1, "X-Note", "a").unwrap();
+        assert_eq!(editor.build().unwrap(), &b"A: 1\r\nX-Note: a\r\n"[..]);
+
+        let e = editor.prepend("X Note", "a").unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::FieldName, b' ', 1));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use base64::{decode_base64, encode_base64};
+use errors::{Error, ErrorKind, Token};
+use mime::is_token_char;
+use prelude::*;
+use sink::Sink;
+use Buffer;
+
+/// Maximum length of an encoded-word. See [RFC2047 section 2].
+///
+/// [RFC2047 section 2]: https://tools.ietf.org/html/rfc2047#section-2
+pub const MAX_ENCODED_WORD_LENGTH: usize = 75;
+
+/// Return `true` if the byte can be part of the charset or of the encoding of an encoded-word.
+///
+/// ```no_rust
+/// token = 1*<Any CHAR except SPACE, CTLs, and especials>
+/// especials = "(" / ")" / "<" / ">" / "@" / "," / ";" / ":" / "
+///             <"> / "/" / "[" / "]" / "?" / "." / "="
+/// ```
+fn is_word_token_char(c: u8) -> bool {
+    is_token_char(c) && c != b'.' && c != b'=' && c != b'?'
+}
+
+/// Return `true` if the byte can be written as is in the text of a "Q"-encoded word. Only the
+/// characters allowed in a phrase are used, so that encoded-words can be used anywhere.
+fn is_q_literal(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'!' || c == b'*' || c == b'+' || c == b'-' || c == b'/'
+}
+
+/// Return the length of the UTF-8 sequence that starts with the given byte. Bytes that cannot
+/// start a sequence are considered to be sequences of one byte, so that text that is not UTF-8
+/// can still be split.
+fn char_length(c: u8) -> usize {
+    match c {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    }
+}
+
+/// The encoding used for the text of an encoded-word.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum WordEncoding {
+    /// `B`: base64
+    B,
+    /// `Q`: similar to quoted-printable, with `_` representing a space
+    Q,
+}
+
+/// An encoded-word. See [RFC2047 section 2].
+///
+/// ```no_rust
+/// encoded-word = "=?" charset "?" encoding "?" encoded-text "?="
+/// ```
+///
+/// The language specification of [RFC2231 section 5] (`charset*language`) is supported.
+///
+/// [RFC2047 section 2]: https://tools.ietf.org/html/rfc2047#section-2
+/// [RFC2231 section 5]: https://tools.ietf.org/html/rfc2231#section-5
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodedWord {
+    charset: Vec<u8>,
+    language: Option<Vec<u8>>,
+    encoding: WordEncoding,
+    text: Vec<u8>,
+}
+
+impl EncodedWord {
+    /// Parse an encoded-word, and decode its text. On success, the encoded-word and the number of
+    /// bytes read are returned.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let error = |i: usize| -> Error {
+            if i >= bytes.len() {
+                ErrorKind::Eof.into()
+            } else {
+                ErrorKind::Token {
+                    token: Token::EncodedWord,
+                    byte: bytes[i],
+                    position: pos + i,
+                }.into()
+            }
+        };
+
+        // "=?"
+        if !bytes.starts_with(b"=?") {
+            return Err(error(if bytes.first() == Some(&b'=') { 1 } else { 0 }));
+        }
+        let mut i = 2;
+
+        // charset [ "*" language ] "?"
+        let start = i;
+        while i < bytes.len() && is_word_token_char(bytes[i]) {
+            i += 1;
+        }
+        let specification = &bytes[start..i];
+        if specification.is_empty() || i >= bytes.len() || bytes[i] != b'?' {
+            return Err(error(i));
+        }
+        let (charset, language) = match specification.iter().position(|c| *c == b'*') {
+            Some(star) => (&specification[..star], Some(specification[star + 1..].to_vec())),
+            None => (specification, None),
+        };
+        i += 1;
+
+        // encoding "?"
+        let encoding = match bytes.get(i) {
+            Some(&b'b') | Some(&b'B') => WordEncoding::B,
+            Some(&b'q') | Some(&b'Q') => WordEncoding::Q,
+            _ => return Err(error(i)),
+        };
+        i += 1;
+        if i >= bytes.len() || bytes[i] != b'?' {
+            return Err(error(i));
+        }
+        i += 1;
+
+        // encoded-text "?="
+        let start = i;
+        while i < bytes.len() && bytes[i] != b'?' && (33..=126).contains(&bytes[i]) {
+            i += 1;
+        }
+        if !bytes[i..].starts_with(b"?=") {
+            return Err(error(if bytes.get(i) == Some(&b'?') { i + 1 } else { i }));
+        }
+        let encoded_text = &bytes[start..i];
+        i += 2;
+
+        let mut text = Vec::with_capacity(encoded_text.len());
+        match encoding {
+            WordEncoding::B => {
+                decode_base64(&Buffer::with_offset(&bytes[..i - 2], start), &mut text)?;
+            }
+            WordEncoding::Q => {
+                let mut j = 0;
+                while j < encoded_text.len() {
+                    match encoded_text[j] {
+                        b'_' => text.push(b' '),
+                        b'=' => {
+                            let value = encoded_text
+                                .get(j + 1..j + 3)
+                                .and_then(|hex| ::core::str::from_utf8(hex).ok())
+                                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                            match value {
+                                Some(value) => {
+                                    text.push(value);
+                                    j += 3;
+                                    continue;
+                                }
+                                None => {
+                                    return Err(ErrorKind::Encoding {
+                                        token: Token::EncodedWord,
+                                        position: pos + start + j,
+                                    }.into())
+                                }
+                            }
+                        }
+                        c => text.push(c),
+                    }
+                    j += 1;
+                }
+            }
+        }
+
+        let word = EncodedWord {
+            charset: charset.to_ascii_lowercase(),
+            language,
+            encoding,
+            text,
+        };
+        Ok((word, i))
+    }
+
+    /// Return the charset, in lower case.
+    pub fn charset(&self) -> &[u8] {
+        &self.charset
+    }
+
+    pub fn language(&self) -> Option<&[u8]> {
+        self.language.as_ref().map(|language| &language[..])
+    }
+
+    pub fn encoding(&self) -> WordEncoding {
+        self.encoding
+    }
+
+    /// Return the decoded text. It is encoded with the word's charset.
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+}
+
+/// Decode the encoded-words found in an unstructured value or in a phrase. Only encoded-words
+/// which charset is `utf-8` or `us-ascii` are decoded, the others are left as is, so the result
+/// is UTF-8 if the input is. Whitespaces between two adjacent encoded-words are removed, as
+/// required by [RFC2047 section 6.2].
+///
+/// [RFC2047 section 6.2]: https://tools.ietf.org/html/rfc2047#section-6.2
+pub fn decode_encoded_words(value: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(value.len());
+    // whitespaces seen since the last decoded encoded-word, which are dropped if another
+    // encoded-word follows
+    let mut pending: Option<usize> = None;
+    let mut i = 0;
+    while i < value.len() {
+        if value[i..].starts_with(b"=?") && (i == 0 || value[i - 1] == b' ' || value[i - 1] == b'\t') {
+            if let Ok((word, len)) = EncodedWord::parse(&Buffer::new(&value[i..])) {
+                if word.charset() == b"utf-8" || word.charset() == b"us-ascii" {
+                    if let Some(start) = pending {
+                        decoded.truncate(start);
+                    }
+                    decoded.extend_from_slice(word.text());
+                    i += len;
+                    pending = Some(decoded.len());
+                    continue;
+                }
+            }
+        }
+        let c = value[i];
+        if c != b' ' && c != b'\t' && c != b'\r' && c != b'\n' {
+            pending = None;
+        }
+        decoded.push(c);
+        i += 1;
+    }
+    decoded
+}
+
+/// Write the given text as a sequence of encoded-words, separated by spaces. UTF-8 text is labeled
+/// as `utf-8`, and other text as `unknown-8bit`. Each encoded-word is at most 75 characters long,
+/// and does not split a UTF-8 sequence. The encoding ("Q" or "B") that produces the shortest
+/// output is used, "Q" being preferred when both have the same length.
+///
+/// The encoded-words only contain characters that are allowed in a phrase, so they can be used in
+/// display names, and in unstructured field values.
+pub fn write_encoded_words<W: Sink>(text: &[u8], writer: &mut W) -> Result<(), Error> {
+    let charset: &[u8] = if ::core::str::from_utf8(text).is_ok() { b"utf-8" } else { b"unknown-8bit" };
+    let escaped = text.iter().filter(|c| !is_q_literal(**c) && **c != b' ').count();
+    // each escaped byte takes three characters with the "Q" encoding, whereas the "B" encoding
+    // makes the text grow by a third
+    let encoding = if text.len() + 2 * escaped <= text.len().div_ceil(3) * 4 {
+        WordEncoding::Q
+    } else {
+        WordEncoding::B
+    };
+    // "=?" charset "?" encoding "?" encoded-text "?="
+    let max_length = MAX_ENCODED_WORD_LENGTH - charset.len() - 7;
+
+    let mut i = 0;
+    while i < text.len() {
+        // find the longest sequence of characters that fits in an encoded-word
+        let start = i;
+        let mut length = 0;
+        while i < text.len() {
+            let end = ::core::cmp::min(i + char_length(text[i]), text.len());
+            let char_encoded_length = match encoding {
+                WordEncoding::Q => text[i..end].iter().map(|c| if is_q_literal(*c) || *c == b' ' { 1 } else { 3 }).sum(),
+                WordEncoding::B => (end - start).div_ceil(3) * 4 - length,
+            };
+            if length + char_encoded_length > max_length && i > start {
+                break;
+            }
+            length += char_encoded_length;
+            i = end;
+        }
+
+        if start > 0 {
+            writer.write_bytes(b" ")?;
+        }
+        writer.write_bytes(b"=?")?;
+        writer.write_bytes(charset)?;
+        match encoding {
+            WordEncoding::Q => {
+                writer.write_bytes(b"?q?")?;
+                for &c in &text[start..i] {
+                    if c == b' ' {
+                        writer.write_bytes(b"_")?;
+                    } else if is_q_literal(c) {
+                        writer.write_bytes(&[c])?;
+                    } else {
+                        write!(writer, "={:02X}", c)?;
+                    }
+                }
+            }
+            WordEncoding::B => {
+                writer.write_bytes(b"?b?")?;
+                encode_base64(&Buffer::new(&text[start..i]), writer)?;
+            }
+        }
+        writer.write_bytes(b"?=")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use errors::ErrorCategory;
+
+    fn word(input: &[u8]) -> (Vec<u8>, WordEncoding, Vec<u8>, usize) {
+        let (word, len) = EncodedWord::parse(&Buffer::new(input)).unwrap();
+        (word.charset().to_vec(), word.encoding(), word.text().to_vec(), len)
+    }
+
+    fn word_len(input: &Buffer) -> Result<usize, Error> {
+        EncodedWord::parse(input).map(|(_, len)| len)
+    }
+
+    fn encoded(text: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        write_encoded_words(text, &mut encoded).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn test_parse_encoded_word() {
+        assert_eq!(
+            word(b"=?ISO-8859-1?Q?Andr=E9_Pirard?= <a@b>"),
+            (b"iso-8859-1".to_vec(), WordEncoding::Q, b"Andr\xe9 Pirard".to_vec(), 31)
+        );
+        assert_eq!(
+            word(b"=?utf-8?B?w6l0w6k=?="),
+            (b"utf-8".to_vec(), WordEncoding::B, "été".as_bytes().to_vec(), 20)
+        );
+        let (word, _) = EncodedWord::parse(b!(b"=?US-ASCII*EN?Q?Keith_Moore?=")).unwrap();
+        assert_eq!(word.language(), Some(&b"EN"[..]));
+        assert_eq!(word.text(), b"Keith Moore");
+        eof!(word_len, b"=?utf-8?q?abc");
+        eof!(word_len, b"=?utf-8?q?abc?");
+        tok!(word_len, b"=?utf-8?x?abc?=", Token::EncodedWord, b'x', 8);
+        tok!(word_len, b"=?utf-8?q?a c?=", Token::EncodedWord, b' ', 11);
+        let e = word_len(b!(b"=?utf-8?q?a=Zc?=")).unwrap_err();
+        assert_eq!((e.category(), e.position()), (ErrorCategory::InvalidEncoding, Some(11)));
+        tok!(word_len, b"=?utf-8?q?abc?x", Token::EncodedWord, b'x', 14);
+    }
+
+    #[test]
+    fn test_decode_encoded_words() {
+        assert_eq!(decode_encoded_words(b"=?utf-8?q?a?= =?utf-8?q?b?="), b"ab");
+        assert_eq!(decode_encoded_words(b"=?utf-8?q?a?= b =?utf-8?q?c?="), b"a b c");
+        assert_eq!(decode_encoded_words(b"=?utf-8?q?a_?=\r\n =?utf-8?q?b?="), b"a b");
+        assert_eq!(decode_encoded_words(b"x=?utf-8?q?a?="), b"x=?utf-8?q?a?=");
+        assert_eq!(decode_encoded_words(b"=?koi8-r?q?a?= b"), b"=?koi8-r?q?a?= b");
+        assert_eq!(decode_encoded_words(b"=?utf-8?q?a"), b"=?utf-8?q?a");
+    }
+
+    #[test]
+    fn test_write_encoded_words() {
+        assert_eq!(encoded("Prix spécial pour vous".as_bytes()), b"=?utf-8?q?Prix_sp=C3=A9cial_pour_vous?=");
+        assert_eq!(encoded("Café crème".as_bytes()), b"=?utf-8?b?Q2Fmw6kgY3LDqG1l?=");
+        assert_eq!(encoded("été".as_bytes()), b"=?utf-8?b?w6l0w6k=?=");
+        assert_eq!(encoded(b"caf\xe9"), b"=?unknown-8bit?q?caf=E9?=");
+        assert_eq!(encoded(b""), b"");
+
+        let text = "Ceci est un très long sujet, qui ne tient pas dans un seul mot encodé, \
+                    et qui doit donc être découpé en plusieurs mots";
+        for text in &[text, "日本語のとても長い件名です。日本語のとても長い件名です。日本語のとても長い件名です。"] {
+            let encoded = encoded(text.as_bytes());
+            for word in encoded.split(|c| *c == b' ') {
+                assert!(word.len() <= MAX_ENCODED_WORD_LENGTH);
+                let (word, _) = EncodedWord::parse(&Buffer::new(word)).unwrap();
+                assert!(::core::str::from_utf8(word.text()).is_ok());
+            }
+            assert_eq!(decode_encoded_words(&encoded), text.as_bytes());
+        }
+    }
+}
@@ -1,13 +1,17 @@
-use std::fmt;
-use std::error::Error as StdError;
+use core::error::Error as StdError;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io::Error as IoError;
 
+use prelude::*;
+
 pub type ParseResult<'a> = Result<(&'a [u8], &'a [u8]), Error>;
 
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
     cause: Option<Box<Error>>,
+    expected: &'static [Token],
 }
 
 impl Error {
@@ -15,33 +19,131 @@ impl Error {
         self.cause = Some(Box::new(error));
     }
 
+    /// Wrap the error in a `Context` error, to record that it occured while parsing the given
+    /// token, which starts at the given position.
+    pub fn context(self, token: Token, position: usize) -> Self {
+        let mut error: Error = ErrorKind::Context { token, position }.into();
+        error.set_cause(self);
+        error
+    }
+
+    /// Record the tokens that could have been parsed where the error occured. For instance, a
+    /// word is either an atom or a quoted string.
+    pub fn expecting(mut self, tokens: &'static [Token]) -> Self {
+        self.expected = tokens;
+        self
+    }
+
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
 
-    pub fn is_token(&self) -> bool {
-        match self.kind {
-            ErrorKind::Token { .. } => true,
-            _ => false,
+    /// Return the tokens that could have been parsed where the error occured, if the parser
+    /// recorded them.
+    pub fn expected(&self) -> &'static [Token] {
+        self.expected
+    }
+
+    /// Return the category of the root cause of the error, so that callers can react to errors
+    /// without matching every kind of error.
+    pub fn category(&self) -> ErrorCategory {
+        match self.root_cause().kind {
+            ErrorKind::Eof => ErrorCategory::UnexpectedEof,
+            ErrorKind::Token { .. } | ErrorKind::Context { .. } => ErrorCategory::UnexpectedByte,
+            ErrorKind::Encoding { .. } | ErrorKind::Utf8 => ErrorCategory::InvalidEncoding,
+            ErrorKind::TooDeep { .. } | ErrorKind::TooLarge { .. } | ErrorKind::LineTooLong { .. } => {
+                ErrorCategory::LimitExceeded
+            }
+            #[cfg(feature = "std")]
+            ErrorKind::Io(_) => ErrorCategory::Io,
+            ErrorKind::Fmt => ErrorCategory::Io,
         }
     }
+
+    /// Return the errors of the chain of causes, starting with this error. For instance, an
+    /// invalid quoted string in the second address of a field gives a `Context` error for the
+    /// field, a `Context` error for the address, and a `Token` error for the quoted string.
+    pub fn chain(&self) -> Chain<'_> {
+        Chain { next: Some(self) }
+    }
+
+    /// Return the last error of the chain of causes: the error that triggered the others.
+    pub fn root_cause(&self) -> &Error {
+        self.chain().last().unwrap()
+    }
+
+    // These look at the root cause, so that they are not affected by the context of the error
+    pub fn is_token(&self) -> bool {
+        matches!(self.root_cause().kind, ErrorKind::Token { .. })
+    }
+    /// Return whether the root cause is an I/O error. Errors of the `Fmt` kind are in the same
+    /// category (see [`category`]) but are not I/O errors.
+    ///
+    /// [`category`]: #method.category
+    #[cfg(feature = "std")]
     pub fn is_io(&self) -> bool {
-        match self.kind {
-            ErrorKind::Io(_) => true,
-            _ => false,
-        }
+        matches!(self.root_cause().kind, ErrorKind::Io(_))
+    }
+    #[cfg(not(feature = "std"))]
+    pub fn is_io(&self) -> bool {
+        false
     }
     pub fn is_eof(&self) -> bool {
+        matches!(self.root_cause().kind, ErrorKind::Eof)
+    }
+
+    /// Return the position of the error, if the error is a token, encoding or context error.
+    pub fn position(&self) -> Option<usize> {
         match self.kind {
-            ErrorKind::Eof => true,
-            _ => false,
+            ErrorKind::Token { position, .. }
+            | ErrorKind::Encoding { position, .. }
+            | ErrorKind::Context { position, .. } => Some(position),
+            _ => None,
         }
     }
 }
 
+/// Write `expected one of` followed by the names of the given tokens, or `expected` followed by
+/// the name of the token if there is only one.
+pub fn write_expected(f: &mut fmt::Formatter, tokens: &[Token]) -> fmt::Result {
+    if tokens.len() == 1 {
+        return write!(f, "expected {}", tokens[0]);
+    }
+    f.write_str("expected one of ")?;
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", token)?;
+    }
+    Ok(())
+}
+
+/// An iterator over the chain of causes of an error. See [`Error::chain`].
+///
+/// [`Error::chain`]: struct.Error.html#method.chain
+#[derive(Clone, Debug)]
+pub struct Chain<'a> {
+    next: Option<&'a Error>,
+}
+
+impl<'a> Iterator for Chain<'a> {
+    type Item = &'a Error;
+
+    fn next(&mut self) -> Option<&'a Error> {
+        let error = self.next?;
+        self.next = error.cause.as_deref();
+        Some(error)
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
-        Error { kind: kind, cause: None }
+        Error {
+            kind,
+            cause: None,
+            expected: &[],
+        }
     }
 }
 
@@ -79,7 +181,14 @@ pub enum Token {
     ///  %d1-8 / %d11 / %d12 / %d14-31 / %d33 / %d35-91 / %d93-126 / %d127
     /// ```
     QuotedText,
+    /// ```no_rust
+    /// mailbox   = name-addr / addr-spec
+    /// addr-spec = local-part "@" domain
+    /// ```
     Address,
+    /// ```no_rust
+    /// domain = dot-atom / domain-literal / obs-domain
+    /// ```
     Domain,
     /// ```no_rust
     /// [CFWS] 1*atext [CFWS]
@@ -97,6 +206,112 @@ pub enum Token {
     /// atom / quoted-string
     /// ```
     Word,
+    /// Any US-ASCII character except space, controls, and "tspecials"
+    /// ```no_rust
+    /// token := 1*<any (US-ASCII) CHAR except SPACE, CTLs, or tspecials>
+    /// ```
+    MimeToken,
+    /// ```no_rust
+    /// field-name = 1*ftext
+    /// ftext      = %d33-57 / %d59-126
+    /// ```
+    FieldName,
+    /// ```no_rust
+    /// field = field-name ":" unstructured CRLF
+    /// ```
+    Field,
+    /// ```no_rust
+    /// date-time       =   [ day-of-week "," ] date time [CFWS]
+    /// date            =   day month year
+    /// time            =   time-of-day zone
+    /// ```
+    DateTime,
+    /// ```no_rust
+    /// content := "Content-Type" ":" type "/" subtype *(";" parameter)
+    /// ```
+    ContentType,
+    /// ```no_rust
+    /// parameter := attribute "=" value
+    /// attribute := token
+    /// value := token / quoted-string
+    /// ```
+    Parameter,
+    /// ```no_rust
+    /// disposition := "Content-Disposition" ":" disposition-type *(";" disposition-parm)
+    /// ```
+    ContentDisposition,
+    /// ```no_rust
+    /// dash-boundary := "--" boundary
+    /// ```
+    Boundary,
+    /// ```no_rust
+    /// encoded-word = "=?" charset "?" encoding "?" encoded-text "?="
+    /// ```
+    EncodedWord,
+    /// ```no_rust
+    /// msg-id = [CFWS] "<" id-left "@" id-right ">" [CFWS]
+    /// ```
+    MessageId,
+    /// A label of a domain name, in its ASCII form (an A-label such as `xn--bcher-kva`) or in its
+    /// Unicode form (a U-label such as `bücher`). See [RFC5890 section 2.3.2].
+    ///
+    /// [RFC5890 section 2.3.2]: https://tools.ietf.org/html/rfc5890#section-2.3.2
+    Label,
+}
+
+impl Token {
+    /// Return the name of the token, as it appears in the grammar of the RFCs.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Token::Fws => "FWS",
+            Token::Cfws => "CFWS",
+            Token::Comment => "comment",
+            Token::QuotedPair => "quoted-pair",
+            Token::QuotedString => "quoted-string",
+            Token::QuotedText => "qtext",
+            Token::Address => "address",
+            Token::Domain => "domain",
+            Token::Atom => "atom",
+            Token::DotAtom => "dot-atom",
+            Token::Atext => "atext",
+            Token::Word => "word",
+            Token::MimeToken => "token",
+            Token::FieldName => "field-name",
+            Token::Field => "field",
+            Token::DateTime => "date-time",
+            Token::ContentType => "content",
+            Token::Parameter => "parameter",
+            Token::ContentDisposition => "disposition",
+            Token::Boundary => "dash-boundary",
+            Token::EncodedWord => "encoded-word",
+            Token::MessageId => "msg-id",
+            Token::Label => "label",
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The categories of errors. See [`Error::category`].
+///
+/// [`Error::category`]: struct.Error.html#method.category
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ErrorCategory {
+    /// A byte cannot be parsed: `Token` errors
+    UnexpectedByte,
+    /// The input ends before what is being parsed: `Eof` errors
+    UnexpectedEof,
+    /// A limit such as the nesting depth of MIME parts or the length of lines is exceeded:
+    /// `TooDeep`, `TooLarge` and `LineTooLong` errors
+    LimitExceeded,
+    /// Bytes are not valid in the encoding they use: `Encoding` errors
+    InvalidEncoding,
+    /// Reading or writing failed: `Io` and `Fmt` errors
+    Io,
 }
 
 #[derive(Debug)]
@@ -111,34 +326,110 @@ pub enum ErrorKind {
         /// index where the failure occured
         position: usize,
     },
+    #[cfg(feature = "std")]
     Io(IoError),
+    /// Writing into a `fmt::Write` failed
+    Fmt,
+    /// Text written into a `fmt::Write` is not valid UTF-8
+    Utf8,
+    /// The bytes of a token are not valid in the encoding they use, for instance an invalid
+    /// escape sequence in an encoded-word
+    Encoding {
+        /// the token whose bytes are invalid
+        token: Token,
+        /// index of the first invalid byte
+        position: usize,
+    },
+    /// An error occured while parsing a token: the cause of the error is the error that
+    /// occured, and this error records what was being parsed
+    Context {
+        /// the token that was being parsed
+        token: Token,
+        /// index where the token starts
+        position: usize,
+    },
+    /// MIME parts are nested deeper than the configured limit
+    TooDeep {
+        /// maximum nesting depth
+        max_depth: usize,
+    },
+    /// A header section is larger than the configured limit
+    TooLarge {
+        /// maximum size of a header section, in bytes
+        max_size: usize,
+    },
+    /// A header field cannot be folded into lines short enough
+    LineTooLong {
+        /// maximum length of a line, excluding the CRLF
+        max_length: usize,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.description())
+        match self.kind {
+            ErrorKind::Token { token, position, .. } => {
+                write!(f, "unexpected byte at position {} while parsing {}", position, token)?
+            }
+            ErrorKind::Encoding { token, position } => {
+                write!(f, "invalid encoding at position {} while parsing {}", position, token)?
+            }
+            ErrorKind::Context { token, position } => write!(f, "failed to parse {} at position {}", token, position)?,
+            _ => f.write_str(self.describe())?,
+        }
+        if !self.expected.is_empty() {
+            f.write_str(", ")?;
+            write_expected(f, self.expected)?;
+        }
+        Ok(())
     }
 }
 
 impl StdError for Error {
-    fn cause(&self) -> Option<&StdError> {
-        match *self.kind() {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.kind {
+            #[cfg(feature = "std")]
             ErrorKind::Io(ref err) => Some(err),
-            _ => None,
+            _ => self.cause.as_ref().map(|cause| &**cause as &(dyn StdError + 'static)),
         }
     }
 
     fn description(&self) -> &str {
+        self.describe()
+    }
+}
+
+impl Error {
+    fn describe(&self) -> &'static str {
         match self.kind {
             ErrorKind::Eof => "no more byte to read in the buffer",
             ErrorKind::Token { .. } => "failed to parse a byte sequence",
+            #[cfg(feature = "std")]
             ErrorKind::Io(_) => "IO error",
+            ErrorKind::Fmt => "failed to write formatted text",
+            ErrorKind::Utf8 => "text is not valid UTF-8",
+            ErrorKind::Encoding { .. } => "invalid encoding",
+            ErrorKind::Context { .. } => "failed to parse a token",
+            ErrorKind::TooDeep { .. } => "MIME parts are nested too deeply",
+            ErrorKind::TooLarge { .. } => "header section is too large",
+            ErrorKind::LineTooLong { .. } => "header field cannot be folded into short enough lines",
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<IoError> for Error {
     fn from(err: IoError) -> Self {
         From::from(ErrorKind::Io(err))
     }
 }
+
+#[cfg(feature = "std")]
+impl From<Error> for IoError {
+    fn from(err: Error) -> Self {
+        match err.kind {
+            ErrorKind::Io(err) => err,
+            _ => IoError::other(err),
+        }
+    }
+}
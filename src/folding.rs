@@ -0,0 +1,231 @@
+use encoded_word::write_encoded_words;
+use common::write_unstructured;
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use sink::Sink;
+
+/// Maximum length of a line, excluding the CRLF. See [RFC5322 section 2.1.1].
+///
+/// [RFC5322 section 2.1.1]: https://tools.ietf.org/html/rfc5322#section-2.1.1
+pub const MAX_LINE_LENGTH: usize = 998;
+
+/// Recommended maximum length of a line, excluding the CRLF. See [RFC5322 section 2.1.1].
+///
+/// [RFC5322 section 2.1.1]: https://tools.ietf.org/html/rfc5322#section-2.1.1
+pub const LINE_LENGTH: usize = 78;
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// Return the positions where a structured field value can be folded, i.e. where a CRLF can be
+/// inserted. These are the whitespaces that follow a non-whitespace character, and that are not
+/// in a quoted string, a comment, or a domain literal: in a value written by this crate, they
+/// separate the elements of a list, the words of a phrase, or the parameters of a MIME field.
+///
+/// The value must not be folded already.
+pub fn structured_fold_points(value: &[u8]) -> Vec<usize> {
+    let mut points = Vec::new();
+    let mut quoted = false;
+    let mut comment_depth: usize = 0;
+    let mut literal = false;
+    let mut escaped = false;
+    for (i, &c) in value.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            b'\\' if quoted || comment_depth > 0 || literal => escaped = true,
+            b'"' if comment_depth == 0 && !literal => quoted = !quoted,
+            b'(' if !quoted && !literal => comment_depth += 1,
+            b')' if !quoted && !literal && comment_depth > 0 => comment_depth -= 1,
+            b'[' if !quoted && comment_depth == 0 => literal = true,
+            b']' if !quoted && comment_depth == 0 => literal = false,
+            c if is_wsp(c) && !quoted && comment_depth == 0 && !literal && i > 0 && !is_wsp(value[i - 1]) => {
+                points.push(i);
+            }
+            _ => {}
+        }
+    }
+    points
+}
+
+/// Return the positions where an unstructured field value can be folded: the whitespaces that
+/// follow a non-whitespace character.
+///
+/// The value must not be folded already.
+pub fn unstructured_fold_points(value: &[u8]) -> Vec<usize> {
+    (1..value.len())
+        .filter(|i| is_wsp(value[*i]) && !is_wsp(value[*i - 1]))
+        .collect()
+}
+
+/// Fold a field at the given positions so that lines are at most `LINE_LENGTH` characters long
+/// when possible. Return the field, including the final CRLF, and the length of the longest
+/// segment that cannot be folded.
+fn fold(name: &[u8], value: &[u8], points: &[usize]) -> (Vec<u8>, usize) {
+    let mut field = Vec::with_capacity(name.len() + value.len() + 4);
+    field.extend_from_slice(name);
+    field.extend_from_slice(b":");
+    let first = points.first().cloned().unwrap_or(value.len());
+    // the space that follows the colon is a fold point too, used when the first segment only
+    // fits on a line of its own
+    if field.len() + 1 + first > LINE_LENGTH && first < LINE_LENGTH {
+        field.extend_from_slice(b"\r\n");
+    }
+    field.extend_from_slice(b" ");
+    let mut line_length = field.len() - field.iter().rposition(|c| *c == b'\n').map_or(0, |i| i + 1);
+    let mut longest: usize = 0;
+    let mut start: usize = 0;
+    for end in points.iter().cloned().chain(Some(value.len())) {
+        let segment = &value[start..end];
+        // the first segment cannot be moved to the next line, and a line cannot be made of
+        // whitespaces only
+        let is_blank = segment.iter().all(|c| is_wsp(*c));
+        if start > 0 && !is_blank && line_length + segment.len() > LINE_LENGTH {
+            field.extend_from_slice(b"\r\n");
+            line_length = 0;
+        }
+        field.extend_from_slice(segment);
+        line_length += segment.len();
+        longest = longest.max(segment.len());
+        start = end;
+    }
+    field.extend_from_slice(b"\r\n");
+    (field, longest)
+}
+
+/// Return the length of the longest line, excluding the line breaks.
+fn longest_line(field: &[u8]) -> usize {
+    field
+        .split(|c| *c == b'\n')
+        .map(|line| line.len() - usize::from(line.ends_with(b"\r")))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Write a field with a structured value, folding it at the positions returned by
+/// [`structured_fold_points`] so that lines are at most [`LINE_LENGTH`] characters long when
+/// possible. See [RFC5322 section 2.2.3].
+///
+/// If a line is still longer than [`MAX_LINE_LENGTH`] characters, `ErrorKind::LineTooLong` is
+/// returned and nothing is written. The value must not contain CR, LF or NUL, which would end the
+/// field or corrupt it: an `ErrorKind::Token` error is returned instead.
+///
+/// [`structured_fold_points`]: fn.structured_fold_points.html
+/// [`LINE_LENGTH`]: constant.LINE_LENGTH.html
+/// [`MAX_LINE_LENGTH`]: constant.MAX_LINE_LENGTH.html
+/// [RFC5322 section 2.2.3]: https://tools.ietf.org/html/rfc5322#section-2.2.3
+pub fn write_structured_field<W: Sink>(name: &[u8], value: &[u8], writer: &mut W) -> Result<(), Error> {
+    if let Some(i) = value.iter().position(|c| *c == b'\r' || *c == b'\n' || *c == 0) {
+        return Err(ErrorKind::Token {
+            token: Token::Field,
+            byte: value[i],
+            position: i,
+        }.into());
+    }
+    let (field, _) = fold(name, value, &structured_fold_points(value));
+    if longest_line(&field) > MAX_LINE_LENGTH {
+        return Err(ErrorKind::LineTooLong {
+            max_length: MAX_LINE_LENGTH,
+        }.into());
+    }
+    writer.write_bytes(&field)?;
+    Ok(())
+}
+
+/// Write a field with an unstructured value, folding it at whitespaces so that lines are at most
+/// [`LINE_LENGTH`] characters long. See [RFC5322 section 2.2.3].
+///
+/// The text is written with [`write_unstructured`]. If it contains a word that is too long to fit
+/// on a line, the whole text is written as encoded-words instead, which are short enough to be
+/// folded (see [RFC2047 section 2]).
+///
+/// [`LINE_LENGTH`]: constant.LINE_LENGTH.html
+/// [`write_unstructured`]: ../common/fn.write_unstructured.html
+/// [RFC5322 section 2.2.3]: https://tools.ietf.org/html/rfc5322#section-2.2.3
+/// [RFC2047 section 2]: https://tools.ietf.org/html/rfc2047#section-2
+pub fn write_unstructured_field<W: Sink>(name: &[u8], text: &[u8], writer: &mut W) -> Result<(), Error> {
+    let mut value = Vec::new();
+    write_unstructured(text, &mut value)?;
+    let (mut field, longest) = fold(name, &value, &unstructured_fold_points(&value));
+    if longest > LINE_LENGTH {
+        value.clear();
+        write_encoded_words(text, &mut value)?;
+        field = fold(name, &value, &unstructured_fold_points(&value)).0;
+    }
+    if longest_line(&field) > MAX_LINE_LENGTH {
+        return Err(ErrorKind::LineTooLong {
+            max_length: MAX_LINE_LENGTH,
+        }.into());
+    }
+    writer.write_bytes(&field)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn structured(name: &str, value: &str) -> String {
+        let mut field = Vec::new();
+        write_structured_field(name.as_bytes(), value.as_bytes(), &mut field).unwrap();
+        String::from_utf8(field).unwrap()
+    }
+
+    fn unstructured(name: &str, text: &str) -> String {
+        let mut field = Vec::new();
+        write_unstructured_field(name.as_bytes(), text.as_bytes(), &mut field).unwrap();
+        String::from_utf8(field).unwrap()
+    }
+
+    #[test]
+    fn test_fold_points() {
+        assert_eq!(structured_fold_points(b"John Smith <john@example.com>, mary@example.com"), vec![4, 10, 30]);
+        assert_eq!(structured_fold_points(b"\"a b\" (c d) [e f] g  h"), vec![5, 11, 17, 19]);
+        assert_eq!(structured_fold_points(b"\"a\\\" b\" c"), vec![7]);
+        assert_eq!(unstructured_fold_points(b" a  b\tc "), vec![2, 5, 7]);
+    }
+
+    #[test]
+    fn test_write_structured_field() {
+        assert_eq!(structured("To", "john@example.com"), "To: john@example.com\r\n");
+        let value = "Alice Anderson <alice@example.com>, Bob Brown <bob@example.com>, \"Smith, Carol\" <carol@example.com>";
+        assert_eq!(
+            structured("To", value),
+            "To: Alice Anderson <alice@example.com>, Bob Brown <bob@example.com>,\r\n \"Smith, Carol\" <carol@example.com>\r\n"
+        );
+        let value = format!("<{}@example.com>", "a".repeat(100));
+        assert_eq!(structured("Message-ID", &value), format!("Message-ID: {}\r\n", value));
+        assert_eq!(structured("Keywords", "a   "), "Keywords: a   \r\n");
+
+        let value = format!("\"{}\" <a@example.com>", "a b ".repeat(300));
+        let e = write_structured_field(b"From", value.as_bytes(), &mut Vec::new()).unwrap_err();
+        assert!(matches!(*e.kind(), ErrorKind::LineTooLong { max_length: 998 }));
+
+        let e = write_structured_field(b"To", b"a@example.com\r\nBcc: b@example.com", &mut Vec::new()).unwrap_err();
+        assert!(matches!(*e.kind(), ErrorKind::Token { token: Token::Field, byte: b'\r', position: 13 }));
+        assert!(write_structured_field(b"To", b"a\n", &mut Vec::new()).unwrap_err().is_token());
+        assert!(write_structured_field(b"To", b"a\0", &mut Vec::new()).unwrap_err().is_token());
+    }
+
+    #[test]
+    fn test_write_unstructured_field() {
+        let text = "This is a rather long subject line, which has to be folded in order to respect the recommended line length";
+        let field = unstructured("Subject", text);
+        assert_eq!(
+            field,
+            "Subject: This is a rather long subject line, which has to be folded in order\r\n to respect the recommended line length\r\n"
+        );
+        assert!(field.lines().all(|line| line.len() <= LINE_LENGTH));
+
+        // a word that is too long is written as encoded-words
+        let text = format!("see {}", "x".repeat(100));
+        let field = unstructured("Subject", &text);
+        assert!(field.starts_with("Subject:\r\n =?utf-8?"));
+        assert!(field.lines().all(|line| line.len() <= LINE_LENGTH));
+        let unfolded = field["Subject:".len()..].replace("\r\n", "");
+        assert_eq!(::encoded_word::decode_encoded_words(unfolded.trim().as_bytes()), text.as_bytes());
+    }
+}
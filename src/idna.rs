@@ -0,0 +1,283 @@
+use core::str;
+
+use common::ascii_str;
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use punycode::{decode_punycode, encode_punycode};
+use Buffer;
+
+/// Prefix of the A-labels, the ASCII form of internationalized labels. See [RFC5890 section
+/// 2.3.2.1].
+///
+/// [RFC5890 section 2.3.2.1]: https://tools.ietf.org/html/rfc5890#section-2.3.2.1
+pub static ACE_PREFIX: &[u8] = b"xn--";
+
+/// Maximum length of a label, in its ASCII form. See [RFC1034 section 3.1].
+///
+/// [RFC1034 section 3.1]: https://tools.ietf.org/html/rfc1034#section-3.1
+pub static MAX_LABEL_LENGTH: usize = 63;
+
+/// Maximum length of a domain name in its ASCII form, without a trailing dot. See [RFC1034
+/// section 3.1].
+///
+/// [RFC1034 section 3.1]: https://tools.ietf.org/html/rfc1034#section-3.1
+pub static MAX_DOMAIN_LENGTH: usize = 253;
+
+fn error(position: usize) -> Error {
+    ErrorKind::Encoding {
+        token: Token::Label,
+        position,
+    }.into()
+}
+
+/// Return `true` if the character separates labels: a full stop, or one of the dots that are
+/// equivalent to it. See [RFC3490 section 3.1].
+///
+/// [RFC3490 section 3.1]: https://tools.ietf.org/html/rfc3490#section-3.1
+fn is_label_separator(c: char) -> bool {
+    c == '.' || c == '\u{3002}' || c == '\u{ff0e}' || c == '\u{ff61}'
+}
+
+/// Return `true` if the character is a combining mark, which cannot start a label. Only the
+/// blocks of combining diacritical marks are recognized.
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{300}'..='\u{36f}' | '\u{1ab0}'..='\u{1aff}' | '\u{1dc0}'..='\u{1dff}' | '\u{20d0}'..='\u{20ff}' | '\u{fe20}'..='\u{fe2f}'
+    )
+}
+
+/// Return `true` if the label starts with the [`ACE_PREFIX`], which is case insensitive.
+///
+/// [`ACE_PREFIX`]: static.ACE_PREFIX.html
+fn has_ace_prefix(label: &str) -> bool {
+    label.len() >= ACE_PREFIX.len() && label.as_bytes()[..ACE_PREFIX.len()].eq_ignore_ascii_case(ACE_PREFIX)
+}
+
+/// Split a domain into its labels, along with their positions.
+fn labels(domain: &str) -> Vec<(usize, &str)> {
+    let mut labels = Vec::new();
+    let mut start = 0;
+    for (i, c) in domain.char_indices() {
+        if is_label_separator(c) {
+            labels.push((start, &domain[start..i]));
+            start = i + c.len_utf8();
+        }
+    }
+    labels.push((start, &domain[start..]));
+    labels
+}
+
+/// Check that a label, mapped to lowercase, is a valid U-label. This is a basic validation of
+/// [RFC5891 section 5.4]: the hyphen restrictions and the leading combining mark are checked,
+/// and ASCII characters must be letters, digits or hyphens, but the normalization, the
+/// contextual rules and the Bidi rule are not checked, and non-ASCII characters are only rejected
+/// if they are controls, whitespaces, or uppercase without a lowercase mapping.
+///
+/// [RFC5891 section 5.4]: https://tools.ietf.org/html/rfc5891#section-5.4
+fn check_u_label(label: &str, position: usize) -> Result<(), Error> {
+    let bytes = label.as_bytes();
+    if bytes.is_empty() || bytes[0] == b'-' || bytes[bytes.len() - 1] == b'-' || bytes.get(2..4) == Some(&b"--"[..]) {
+        return Err(error(position));
+    }
+    let valid = label.chars().enumerate().all(|(i, c)| match c {
+        'a'..='z' | '0'..='9' | '-' => true,
+        c if c.is_ascii() => false,
+        c => !(c.is_control() || c.is_whitespace() || c.is_uppercase() || (i == 0 && is_combining_mark(c))),
+    });
+    if !valid {
+        return Err(error(position));
+    }
+    Ok(())
+}
+
+/// Convert a non-ASCII label into an A-label.
+fn to_a_label(label: &str, position: usize) -> Result<String, Error> {
+    let label = label.to_lowercase();
+    check_u_label(&label, position)?;
+    let mut a_label = ACE_PREFIX.to_vec();
+    encode_punycode(&label, &mut a_label)?;
+    Ok(ascii_str(&a_label).to_string())
+}
+
+/// Convert an A-label into a U-label. A-labels are case insensitive, so the ASCII characters of
+/// the U-label are mapped to lowercase. The A-label must be the encoding of a valid U-label, and
+/// be identical, except for the case, to the encoding of this U-label. See [RFC5891 section 5.4].
+///
+/// [RFC5891 section 5.4]: https://tools.ietf.org/html/rfc5891#section-5.4
+fn to_u_label(domain: &str, label: &str, position: usize) -> Result<String, Error> {
+    let encoded = &label.as_bytes()[ACE_PREFIX.len()..];
+    let mut u_label = Vec::new();
+    let buffer = Buffer::with_offset(&domain.as_bytes()[..position + label.len()], position + ACE_PREFIX.len());
+    decode_punycode(&buffer, &mut u_label)?;
+    let u_label = String::from_utf8(u_label).map_err(|_| error(position))?.to_ascii_lowercase();
+    if u_label.is_ascii() {
+        return Err(error(position));
+    }
+    check_u_label(&u_label, position)?;
+    let mut reencoded = Vec::new();
+    encode_punycode(&u_label, &mut reencoded)?;
+    if !reencoded.eq_ignore_ascii_case(encoded) {
+        return Err(error(position));
+    }
+    Ok(u_label)
+}
+
+/// Check that the domain is UTF-8 and return it as a string slice.
+fn domain_str(domain: &[u8]) -> Result<&str, Error> {
+    if domain.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
+    str::from_utf8(domain).map_err(|e| error(e.valid_up_to()))
+}
+
+/// Convert a domain name into its ASCII form, where internationalized labels are written as
+/// A-labels (see [RFC5891 section 4]). Labels that contain non-ASCII characters are mapped to
+/// lowercase and encoded with Punycode, A-labels are checked, and other ASCII labels are kept as
+/// they are. The dots that are equivalent to a full stop (`。`, `．` and `｡`) are replaced by a
+/// full stop.
+///
+/// An empty domain results in `ErrorKind::Eof`. An invalid label, or a label or a domain that is
+/// too long, results in an `Encoding` error at the position of the label, or of the domain.
+///
+/// ```rust
+/// use imf::idna::domain_to_ascii;
+///
+/// assert_eq!(domain_to_ascii("Bücher.example".as_bytes()).unwrap(), "xn--bcher-kva.example");
+/// assert_eq!(domain_to_ascii("例子。广告".as_bytes()).unwrap(), "xn--fsqu00a.xn--4rr70v");
+/// assert!(domain_to_ascii("bü cher.example".as_bytes()).is_err());
+/// ```
+///
+/// [RFC5891 section 4]: https://tools.ietf.org/html/rfc5891#section-4
+pub fn domain_to_ascii(domain: &[u8]) -> Result<String, Error> {
+    let domain = domain_str(domain)?;
+    let mut output = String::with_capacity(domain.len());
+    for (position, label) in labels(domain) {
+        if position > 0 {
+            output.push('.');
+        }
+        let start = output.len();
+        if label.is_empty() {
+            return Err(error(position));
+        } else if !label.is_ascii() {
+            output.push_str(&to_a_label(label, position)?);
+        } else {
+            if has_ace_prefix(label) {
+                to_u_label(domain, label, position)?;
+            }
+            output.push_str(label);
+        }
+        if output.len() - start > MAX_LABEL_LENGTH {
+            return Err(error(position));
+        }
+    }
+    if output.len() > MAX_DOMAIN_LENGTH {
+        return Err(error(0));
+    }
+    Ok(output)
+}
+
+/// Convert a domain name into its Unicode form, where internationalized labels are written as
+/// U-labels (see [RFC5891 section 5]). A-labels are decoded and checked, labels that contain
+/// non-ASCII characters are mapped to lowercase and checked, and other ASCII labels are kept as
+/// they are. The dots that are equivalent to a full stop are replaced by a full stop.
+///
+/// Errors are the same as the errors of [`domain_to_ascii`], except that the length of the
+/// domain is not checked.
+///
+/// ```rust
+/// use imf::idna::domain_to_unicode;
+///
+/// assert_eq!(domain_to_unicode(b"xn--bcher-kva.example").unwrap(), "bücher.example");
+/// assert_eq!(domain_to_unicode("XN--fsqu00a.广告".as_bytes()).unwrap(), "例子.广告");
+/// assert!(domain_to_unicode(b"xn--bcher-kv!.example").is_err());
+/// ```
+///
+/// [RFC5891 section 5]: https://tools.ietf.org/html/rfc5891#section-5
+/// [`domain_to_ascii`]: fn.domain_to_ascii.html
+pub fn domain_to_unicode(domain: &[u8]) -> Result<String, Error> {
+    let domain = domain_str(domain)?;
+    let mut output = String::with_capacity(domain.len());
+    for (position, label) in labels(domain) {
+        if position > 0 {
+            output.push('.');
+        }
+        if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+            return Err(error(position));
+        } else if !label.is_ascii() {
+            let label = label.to_lowercase();
+            check_u_label(&label, position)?;
+            output.push_str(&label);
+        } else if has_ace_prefix(label) {
+            output.push_str(&to_u_label(domain, label, position)?);
+        } else {
+            output.push_str(label);
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_ascii(domain: &str) -> Result<String, Error> {
+        domain_to_ascii(domain.as_bytes())
+    }
+
+    fn to_unicode(domain: &str) -> Result<String, Error> {
+        domain_to_unicode(domain.as_bytes())
+    }
+
+    #[test]
+    fn test_domain_to_ascii() {
+        assert_eq!(to_ascii("example.com").unwrap(), "example.com");
+        assert_eq!(to_ascii("Example.COM").unwrap(), "Example.COM");
+        assert_eq!(to_ascii("bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(to_ascii("BÜCHER.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(to_ascii("例子．广告").unwrap(), "xn--fsqu00a.xn--4rr70v");
+        assert_eq!(to_ascii("xn--bcher-kva.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(to_ascii("правительство.рф").unwrap(), "xn--80aealotwbjpid2k.xn--p1ai");
+    }
+
+    #[test]
+    fn test_domain_to_ascii_errors() {
+        let position = |domain: &str| to_ascii(domain).unwrap_err().position();
+        assert!(to_ascii("").unwrap_err().is_eof());
+        assert_eq!(position("a..b"), Some(2));
+        assert_eq!(position("a.-bücher"), Some(2));
+        assert_eq!(position("a.bücher-"), Some(2));
+        assert_eq!(position("ab--ü.com"), Some(0));
+        assert_eq!(position("a b ü.com"), Some(0));
+        assert_eq!(position("x.\u{301}a"), Some(2));
+        assert_eq!(position(&"ü".repeat(60)), Some(0));
+        let long = ["a"; 127].join(".");
+        assert_eq!(to_ascii(&long).unwrap().len(), 253);
+        assert_eq!(position(&format!("{}a", long)), Some(0));
+        // invalid A-labels
+        assert_eq!(position("a.xn--bcher-k!a"), Some(13));
+        assert_eq!(position("a.xn--abc-"), Some(2));
+        assert_eq!(domain_to_ascii(b"a.b\xff").unwrap_err().position(), Some(3));
+    }
+
+    #[test]
+    fn test_domain_to_unicode() {
+        assert_eq!(to_unicode("example.com").unwrap(), "example.com");
+        assert_eq!(to_unicode("xn--bcher-kva.example").unwrap(), "bücher.example");
+        assert_eq!(to_unicode("XN--BCHER-KVA.example").unwrap(), "bücher.example");
+        assert_eq!(to_unicode("Bücher｡example").unwrap(), "bücher.example");
+        assert_eq!(to_unicode("xn--fsqu00a.xn--4rr70v").unwrap(), "例子.广告");
+        for domain in &["bücher.example", "例子.广告", "правительство.рф"] {
+            assert_eq!(to_unicode(&to_ascii(domain).unwrap()).unwrap(), *domain);
+        }
+
+        let position = |domain: &str| to_unicode(domain).unwrap_err().position();
+        assert_eq!(position("a.xn--bcher-kv"), Some(14));
+        assert_eq!(to_unicode("a.xn--Bcher-kva").unwrap(), "a.bücher");
+        // uppercase characters are not encoded
+        assert_eq!(position("a.xn--wca"), Some(2));
+        // the U-label would be invalid
+        assert_eq!(position("xn---bcher-kva.example"), Some(0));
+        assert_eq!(position("a.xn--"), Some(2));
+    }
+}
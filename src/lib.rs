@@ -1,15 +1,61 @@
+//! Without the default `std` feature, the crate only depends on `core` and `alloc`: the
+//! parsers, encoders and decoders still write into a [`Sink`](sink/trait.Sink.html), but the
+//! modules that read message bodies or build messages (`mime_part`, `stream`, `builder`) are not
+//! available.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "async")]
+extern crate tokio;
+
 // Macros are only necessary for tests. It's important to import the macro module first otherwise
 // the macros are not available to for the other modules.
 #[cfg(test)]
 #[macro_use]
 pub mod macros;
 
+/// The types of the standard prelude that are not in the `core` prelude.
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+}
+
 pub mod errors;
+pub mod diagnostic;
 pub mod whitespaces;
+pub mod comment;
 pub mod quoted_string;
-// pub mod atom;
-// pub mod address;
-// pub mod common;
+pub mod base64;
+pub mod quoted_printable;
+pub mod transfer_encoding;
+pub mod mime;
+pub mod multipart;
+pub mod message;
+#[cfg(feature = "std")]
+pub mod mime_part;
+#[cfg(feature = "std")]
+pub mod stream;
+pub mod date_time;
+pub mod atom;
+pub mod common;
+pub mod cow;
+pub mod sink;
+pub mod encoded_word;
+pub mod punycode;
+pub mod idna;
+pub mod address;
+pub mod message_id;
+pub mod folding;
+pub mod cst;
+pub mod editor;
+#[cfg(feature = "std")]
+pub mod builder;
 mod buffer;
 
 pub use buffer::Buffer;
@@ -13,14 +13,16 @@ macro_rules! eof {
     ($function:ident, $bytes:expr) => (assert!($function(b!($bytes)).unwrap_err().is_eof()));
 }
 
-// the tok! macro makes sure parsing fails with ErrorKind::Token
+// the tok! macro makes sure parsing fails with ErrorKind::Token, possibly in some context
 macro_rules! tok {
     ($function:ident, $bytes:expr, $token:expr, $byte:expr, $position:expr) => (
         let e = $function(b!($bytes)).unwrap_err();
-        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+        if let ErrorKind::Token { token, byte, position } = *e.root_cause().kind() {
             assert_eq!(token, $token);
             assert_eq!(byte, $byte);
             assert_eq!(position, $position);
+        } else {
+            panic!("unexpected error {:?}", e);
         }
         );
     ($function:ident, $bytes:expr) => (
@@ -28,3 +30,21 @@ macro_rules! tok {
         assert!(e.is_token());
         )
 }
+
+// create a new buffer accepting UTF-8 from a string
+macro_rules! u {
+    ($e:expr) => (&Buffer::new($e.as_ref()).utf8(true));
+}
+
+// the enc! macro makes sure parsing a buffer accepting UTF-8 fails with ErrorKind::Encoding
+macro_rules! enc {
+    ($function:ident, $bytes:expr, $token:expr, $position:expr) => (
+        let e = $function(u!($bytes)).unwrap_err();
+        if let ErrorKind::Encoding { token, position } = *e.root_cause().kind() {
+            assert_eq!(token, $token);
+            assert_eq!(position, $position);
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+        );
+}
@@ -0,0 +1,533 @@
+use core::ops::Range;
+use core::str;
+
+use common::{ascii_str, parse_remaining};
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use Buffer;
+
+/// Return `true` if the byte can be part of a field name. See [RFC5322 section 3.6.8].
+///
+/// ```no_rust
+/// ftext           =   %d33-57 /          ; Printable US-ASCII
+///                     %d59-126           ;  characters not including
+///                                        ;  ":".
+/// ```
+///
+/// [RFC5322 section 3.6.8]: https://tools.ietf.org/html/rfc5322#section-3.6.8
+pub fn is_ftext(c: u8) -> bool {
+    c > 32 && c < 127 && c != b':'
+}
+
+/// Check that the given bytes form a valid field name. An empty name results in
+/// `ErrorKind::Eof`, and an invalid byte in a `Token` error at its index.
+///
+/// ```no_rust
+/// field-name      =   1*ftext
+/// ```
+pub fn check_field_name(name: &[u8]) -> Result<(), Error> {
+    if name.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
+    if let Some(i) = name.iter().position(|c| !is_ftext(*c)) {
+        return Err(ErrorKind::Token {
+            token: Token::FieldName,
+            byte: name[i],
+            position: i,
+        }.into());
+    }
+    Ok(())
+}
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// A header field. Both the name and the value are slices of the original buffer: the value is
+/// raw, i.e. it is neither unfolded nor decoded, and it does not include the CRLF that terminates
+/// the field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field<'buf> {
+    name: &'buf [u8],
+    value: &'buf [u8],
+    position: usize,
+    value_position: usize,
+    /// The buffer the field was parsed from, up to the end of the value
+    inner: &'buf [u8],
+    /// Whether the buffer the field was parsed from accepts UTF-8
+    utf8: bool,
+}
+
+impl<'buf> Field<'buf> {
+    pub fn name(&self) -> &'buf [u8] {
+        self.name
+    }
+
+    /// Return the name of the field as a string slice: field names are US-ASCII.
+    pub fn name_str(&self) -> &'buf str {
+        ascii_str(self.name)
+    }
+
+    pub fn value(&self) -> &'buf [u8] {
+        self.value
+    }
+
+    /// Return the position of the field in the buffer it was parsed from, from the start of its
+    /// name to the end of its value.
+    pub fn span(&self) -> Range<usize> {
+        self.position..self.value_position + self.value.len()
+    }
+
+    /// Return the position of the name in the buffer it was parsed from.
+    pub fn name_span(&self) -> Range<usize> {
+        self.position..self.position + self.name.len()
+    }
+
+    /// Return the position of the raw value in the buffer it was parsed from.
+    pub fn value_span(&self) -> Range<usize> {
+        self.value_position..self.value_position + self.value.len()
+    }
+
+    /// Parse the value of the field with the given parser, which must read the whole value, except
+    /// for trailing CFWS. Errors are given the context of the field, and their positions are
+    /// positions in the buffer the field was parsed from.
+    ///
+    /// ```rust
+    /// use imf::address::parse_address_list;
+    /// use imf::errors::{ErrorKind, Token};
+    /// use imf::message::parse_field;
+    /// use imf::Buffer;
+    ///
+    /// let (field, _) = parse_field(&Buffer::new(b"To: a@example.com, b@@example.com\r\n")).unwrap();
+    /// let error = field.parse_value(parse_address_list).unwrap_err();
+    /// let contexts: Vec<_> = error
+    ///     .chain()
+    ///     .filter_map(|e| match *e.kind() {
+    ///         ErrorKind::Context { token, position } => Some((token, position)),
+    ///         _ => None,
+    ///     })
+    ///     .collect();
+    /// assert_eq!(contexts, vec![(Token::Field, 0), (Token::Address, 19)]);
+    /// assert_eq!(error.root_cause().position(), Some(21));
+    /// ```
+    pub fn parse_value<T, F>(&self, parse: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Buffer<'buf>) -> Result<(T, usize), Error>,
+    {
+        let buffer = Buffer::with_offset(self.inner, self.value_position).utf8(self.utf8);
+        parse_remaining(&buffer, Token::Field, parse).map_err(|e| e.context(Token::Field, self.position))
+    }
+
+    /// Return `true` if the field has the given name. Field names are case insensitive.
+    pub fn is(&self, name: &[u8]) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    /// Return the value of the field, without the line breaks used for folding, and without
+    /// leading and trailing whitespaces. See [RFC5322 section 2.2.3].
+    ///
+    /// [RFC5322 section 2.2.3]: https://tools.ietf.org/html/rfc5322#section-2.2.3
+    pub fn unfolded_value(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(self.value.len());
+        for &c in self.value {
+            if c != b'\r' && c != b'\n' {
+                value.push(c);
+            }
+        }
+        let start = value.iter().take_while(|c| is_wsp(**c)).count();
+        let end = value.len() - value[start..].iter().rev().take_while(|c| is_wsp(**c)).count();
+        value.truncate(end);
+        value.drain(..start);
+        value
+    }
+}
+
+/// Parse a header field. On success, the field and the number of bytes read (including the
+/// terminating line break) are returned. See [RFC5322 section 3.6.8].
+///
+/// ```no_rust
+/// optional-field  =   field-name ":" unstructured CRLF
+/// field-name      =   1*ftext
+/// obs-optional    =   field-name *WSP ":" unstructured CRLF
+/// ```
+///
+/// Lines can end with CRLF or with a lone LF, and the last field of the buffer does not need to
+/// be terminated by a line break. A line starting with a whitespace is the continuation of the
+/// previous line. The value can contain any byte, unless the buffer accepts UTF-8 (see
+/// [`Buffer::utf8`]): malformed UTF-8 then results in an `Encoding` error.
+///
+/// [`Buffer::utf8`]: ../struct.Buffer.html#method.utf8
+/// [RFC5322 section 3.6.8]: https://tools.ietf.org/html/rfc5322#section-3.6.8
+pub fn parse_field<'buf>(input: &Buffer<'buf>) -> Result<(Field<'buf>, usize), Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    if bytes.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
+
+    // read field-name *WSP ":"
+    let name_len = bytes.iter().take_while(|c| is_ftext(**c)).count();
+    if name_len == 0 {
+        return Err(ErrorKind::Token {
+            token: Token::FieldName,
+            byte: bytes[0],
+            position: pos,
+        }.into());
+    }
+    let mut i = name_len;
+    while i < bytes.len() && is_wsp(bytes[i]) {
+        i += 1;
+    }
+    if i >= bytes.len() {
+        return Err(ErrorKind::Eof.into());
+    } else if bytes[i] != b':' {
+        return Err(ErrorKind::Token {
+            token: Token::Field,
+            byte: bytes[i],
+            position: pos + i,
+        }.into());
+    }
+    i += 1;
+
+    // read the value, until a line break that is not followed by a whitespace
+    let value_start = i;
+    let value_end;
+    loop {
+        match bytes[i..].iter().position(|c| *c == b'\n') {
+            Some(n) => {
+                let line_end = i + n;
+                i = line_end + 1;
+                if i < bytes.len() && is_wsp(bytes[i]) {
+                    continue;
+                }
+                value_end = if line_end > value_start && bytes[line_end - 1] == b'\r' {
+                    line_end - 1
+                } else {
+                    line_end
+                };
+                break;
+            }
+            None => {
+                i = bytes.len();
+                value_end = i;
+                break;
+            }
+        }
+    }
+
+    // unstructured =/ UTF8-non-ascii
+    if input.accepts_utf8() {
+        if let Err(e) = str::from_utf8(&bytes[value_start..value_end]) {
+            return Err(ErrorKind::Encoding {
+                token: Token::Field,
+                position: pos + value_start + e.valid_up_to(),
+            }.into());
+        }
+    }
+
+    let field = Field {
+        name: &bytes[..name_len],
+        value: &bytes[value_start..value_end],
+        position: pos,
+        value_position: pos + value_start,
+        inner: &input.clone().into_inner()[..pos + value_end],
+        utf8: input.accepts_utf8(),
+    };
+    Ok((field, i))
+}
+
+/// A header field that could not be parsed, and was skipped by a recovering parser (see
+/// [`parse_header_section_recovering`]).
+///
+/// [`parse_header_section_recovering`]: fn.parse_header_section_recovering.html
+#[derive(Debug)]
+pub struct FieldError {
+    error: Error,
+    span: Range<usize>,
+}
+
+impl FieldError {
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// Return the position of the skipped lines in the buffer they were parsed from, without the
+    /// line break that terminates them.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    pub fn into_error(self) -> Error {
+        self.error
+    }
+}
+
+/// Return the length of the first line of the bytes and of the lines that follow it and start
+/// with a whitespace, including the line break that terminates them.
+fn lines_len(bytes: &[u8]) -> usize {
+    let mut i: usize = 0;
+    while let Some(n) = bytes[i..].iter().position(|c| *c == b'\n') {
+        i += n + 1;
+        if i >= bytes.len() || !is_wsp(bytes[i]) {
+            return i;
+        }
+    }
+    bytes.len()
+}
+
+/// Parse a header section. If `errors` is given, the fields that cannot be parsed are skipped
+/// and their errors are pushed to it.
+fn parse_fields<'buf>(
+    input: &Buffer<'buf>,
+    mut errors: Option<&mut Vec<FieldError>>,
+) -> Result<(Vec<Field<'buf>>, usize), Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+    let mut fields = Vec::new();
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"\r\n") {
+            return Ok((fields, i + 2));
+        } else if bytes[i] == b'\n' {
+            return Ok((fields, i + 1));
+        }
+        buffer.set_position(pos + i);
+        match parse_field(&buffer) {
+            Ok((field, len)) => {
+                fields.push(field);
+                i += len;
+            }
+            Err(error) => match errors {
+                Some(ref mut errors) => {
+                    let len = lines_len(&bytes[i..]);
+                    let mut end = i + len;
+                    if bytes[..end].ends_with(b"\n") {
+                        end -= 1;
+                    }
+                    if bytes[..end].ends_with(b"\r") {
+                        end -= 1;
+                    }
+                    errors.push(FieldError {
+                        error,
+                        span: pos + i..pos + end,
+                    });
+                    i += len;
+                }
+                None => return Err(error),
+            },
+        }
+    }
+    Ok((fields, i))
+}
+
+/// Parse a header section: a list of fields terminated by an empty line. On success, the fields
+/// and the number of bytes read (including the empty line) are returned. If the buffer ends
+/// before an empty line is found, the whole buffer is considered to be the header section.
+pub fn parse_header_section<'buf>(input: &Buffer<'buf>) -> Result<(Vec<Field<'buf>>, usize), Error> {
+    parse_fields(input, None)
+}
+
+/// Parse a header section like [`parse_header_section`], but keep parsing after a malformed
+/// field: the error is recorded with the span of the field, and parsing continues with the next
+/// line that does not start with a whitespace. The fields, the errors, and the number of bytes
+/// read are returned.
+///
+/// [`parse_header_section`]: fn.parse_header_section.html
+pub fn parse_header_section_recovering<'buf>(input: &Buffer<'buf>) -> (Vec<Field<'buf>>, Vec<FieldError>, usize) {
+    let mut errors = Vec::new();
+    // errors are only returned when they are not recorded
+    let (fields, len) = parse_fields(input, Some(&mut errors)).unwrap();
+    (fields, errors, len)
+}
+
+/// A message: a header section and a body. See [RFC5322 section 3.5].
+///
+/// ```no_rust
+/// message         =   (fields / obs-fields)
+///                     [CRLF body]
+/// ```
+///
+/// [RFC5322 section 3.5]: https://tools.ietf.org/html/rfc5322#section-3.5
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message<'buf> {
+    fields: Vec<Field<'buf>>,
+    body: &'buf [u8],
+}
+
+impl<'buf> Message<'buf> {
+    /// Parse a message. The whole buffer is consumed.
+    pub fn parse(input: &Buffer<'buf>) -> Result<Self, Error> {
+        let (fields, len) = parse_header_section(input)?;
+        Ok(Message {
+            fields,
+            body: &input.remaining()[len..],
+        })
+    }
+
+    /// Parse a message, skipping the header fields that cannot be parsed. The message and the
+    /// errors of the skipped fields are returned. See [`parse_header_section_recovering`].
+    ///
+    /// ```rust
+    /// use imf::message::Message;
+    /// use imf::Buffer;
+    ///
+    /// let input = b"From: me\r\nnot a field\r\nTo: you\r\n\r\nbody";
+    /// let (message, errors) = Message::parse_recovering(&Buffer::new(input));
+    /// assert_eq!(message.fields().len(), 2);
+    /// assert_eq!(message.body(), b"body");
+    /// assert_eq!(&input[errors[0].span()], b"not a field");
+    /// ```
+    ///
+    /// [`parse_header_section_recovering`]: fn.parse_header_section_recovering.html
+    pub fn parse_recovering(input: &Buffer<'buf>) -> (Self, Vec<FieldError>) {
+        let (fields, errors, len) = parse_header_section_recovering(input);
+        let message = Message {
+            fields,
+            body: &input.remaining()[len..],
+        };
+        (message, errors)
+    }
+
+    pub fn fields(&self) -> &[Field<'buf>] {
+        &self.fields
+    }
+
+    /// Return the first field with the given name.
+    pub fn field(&self, name: &[u8]) -> Option<&Field<'buf>> {
+        self.fields.iter().find(|field| field.is(name))
+    }
+
+    pub fn body(&self) -> &'buf [u8] {
+        self.body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address::Address;
+
+    fn field(input: &[u8]) -> (&[u8], &[u8], usize) {
+        let (field, len) = parse_field(&Buffer::new(input)).unwrap();
+        (field.name(), field.value(), len)
+    }
+
+    fn field_len(input: &Buffer) -> Result<usize, Error> {
+        parse_field(input).map(|(_, len)| len)
+    }
+
+    #[test]
+    fn test_parse_field() {
+        assert_eq!(field(b"Subject: hello\r\n"), (&b"Subject"[..], &b" hello"[..], 16));
+        assert_eq!(field(b"Subject: hello\r\nTo: me"), (&b"Subject"[..], &b" hello"[..], 16));
+        assert_eq!(field(b"Subject:hello"), (&b"Subject"[..], &b"hello"[..], 13));
+        assert_eq!(field(b"Subject :\r\n"), (&b"Subject"[..], &b""[..], 11));
+        assert_eq!(field(b"Subject: hello\n"), (&b"Subject"[..], &b" hello"[..], 15));
+        assert_eq!(
+            field(b"Subject: hello\r\n world\r\n\tagain\r\nTo: me\r\n"),
+            (&b"Subject"[..], &b" hello\r\n world\r\n\tagain"[..], 32)
+        );
+        assert_eq!(field(b"X-Empty:\r\n \r\n"), (&b"X-Empty"[..], &b"\r\n "[..], 13));
+        eof!(field_len, b"");
+        eof!(field_len, b"Subject");
+        tok!(field_len, b" Subject: hello", Token::FieldName, b' ', 0);
+        tok!(field_len, b":hello", Token::FieldName, b':', 0);
+        tok!(field_len, b"Sub ject: hello", Token::Field, b'j', 4);
+    }
+
+    #[test]
+    fn test_utf8_field() {
+        let (field, len) = parse_field(u!("Subject: ¡Hola!\r\n")).unwrap();
+        assert_eq!((field.value(), len), (" ¡Hola!".as_bytes(), 18));
+        assert_eq!(field_len(b!(b"Subject: caf\xe9\r\n")).unwrap(), 15);
+        enc!(field_len, b"Subject: caf\xe9\r\n", Token::Field, 12);
+
+        // the value is parsed with the same buffer
+        let (field, _) = parse_field(u!("To: 用户@例子.广告\r\n")).unwrap();
+        assert_eq!(field.parse_value(Address::parse).unwrap().domain_str(), "例子.广告");
+    }
+
+    #[test]
+    fn test_field_spans() {
+        let input = b"Date: x\r\nSubject : hello\r\n world\r\n";
+        let (field, len) = parse_field(&Buffer::with_offset(input, 9)).unwrap();
+        assert_eq!(len, 25);
+        assert_eq!(field.name_span(), 9..16);
+        assert_eq!(field.name_str(), "Subject");
+        assert_eq!(field.value_span(), 18..32);
+        assert_eq!(&input[field.value_span()], field.value());
+        assert_eq!(&input[field.span()], &b"Subject : hello\r\n world"[..]);
+    }
+
+    #[test]
+    fn test_unfolded_value() {
+        let (field, _) = parse_field(b!(b"Subject:  hello\r\n  world \r\n")).unwrap();
+        assert_eq!(field.unfolded_value(), b"hello  world");
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let input = b"From: me\r\nTo: you\r\nSubject: hello\r\n world\r\n\r\nbody\r\n";
+        let message = Message::parse(&Buffer::new(input)).unwrap();
+        let names: Vec<&[u8]> = message.fields().iter().map(|field| field.name()).collect();
+        assert_eq!(names, vec![&b"From"[..], b"To", b"Subject"]);
+        assert_eq!(message.field(b"subject").unwrap().value(), b" hello\r\n world");
+        assert!(message.field(b"Cc").is_none());
+        assert_eq!(message.body(), b"body\r\n");
+
+        let message = Message::parse(b!(b"From: me\nTo: you\n\nbody\n")).unwrap();
+        assert_eq!(message.fields().len(), 2);
+        assert_eq!(message.body(), b"body\n");
+
+        // no body
+        let message = Message::parse(b!(b"From: me\r\n")).unwrap();
+        assert_eq!(message.fields().len(), 1);
+        assert_eq!(message.body(), b"");
+
+        // no header
+        let message = Message::parse(b!(b"\r\nbody")).unwrap();
+        assert!(message.fields().is_empty());
+        assert_eq!(message.body(), b"body");
+
+        let e = Message::parse(b!(b"From: me\r\nnot a field\r\n\r\n")).unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::Field, b'a', 14));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let input = b"From: me\r\n\
+not a field\r\n \tcontinued\r\n\
+To: you\n\
+: no name\n\
+Subject: hello\r\n\
+X-Bad\r\n\
+\r\n\
+body\r\nnot a field\r\n";
+        let (fields, errors, len) = parse_header_section_recovering(b!(input));
+        let names: Vec<&[u8]> = fields.iter().map(|field| field.name()).collect();
+        assert_eq!(names, vec![&b"From"[..], b"To", b"Subject"]);
+        assert_eq!(len, 79);
+        let skipped: Vec<&[u8]> = errors.iter().map(|e| &input[e.span()]).collect();
+        assert_eq!(skipped, vec![&b"not a field\r\n \tcontinued"[..], b": no name", b"X-Bad"]);
+        assert_eq!(errors[0].error().position(), Some(14));
+        assert!(errors[1].error().is_token());
+        assert!(errors[2].error().is_token());
+
+        let (message, errors) = Message::parse_recovering(b!(input));
+        assert_eq!(message.fields(), &fields[..]);
+        assert_eq!(message.body(), b"body\r\nnot a field\r\n");
+        assert_eq!(errors.len(), 3);
+
+        // an unterminated field at the end of the buffer
+        let (message, errors) = Message::parse_recovering(b!(b"From: me\nX-Bad"));
+        assert_eq!(message.fields().len(), 1);
+        assert!(errors[0].error().is_eof());
+        assert_eq!(errors[0].span(), 9..14);
+    }
+}
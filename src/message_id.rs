@@ -0,0 +1,330 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use core::hash::{BuildHasher, Hasher};
+use core::ops::Range;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::process;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use address::{is_dtext, parse_domain, parse_local_part, write_local_part};
+use atom::invalid_dot_atom_text_position;
+use common::{ascii_str, display, parse_all};
+use cst::{trim_cfws, Span};
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use sink::Sink;
+use whitespaces::skip_cfws;
+use Buffer;
+
+/// Build the error returned when an unexpected byte is found at index `i`.
+fn error(bytes: &[u8], pos: usize, i: usize) -> Error {
+    if i >= bytes.len() {
+        ErrorKind::Eof.into()
+    } else {
+        ErrorKind::Token {
+            token: Token::MessageId,
+            byte: bytes[i],
+            position: pos + i,
+        }.into()
+    }
+}
+
+/// Return `true` if the given bytes form a valid `no-fold-literal`.
+///
+/// ```no_rust
+/// no-fold-literal =   "[" *dtext "]"
+/// ```
+fn is_no_fold_literal(bytes: &[u8]) -> bool {
+    bytes.len() >= 2
+        && bytes[0] == b'['
+        && bytes[bytes.len() - 1] == b']'
+        && bytes[1..bytes.len() - 1].iter().all(|c| is_dtext(*c))
+}
+
+/// A message identifier, as found in the `Message-ID`, `In-Reply-To` and `References` fields.
+/// See [RFC5322 section 3.6.4].
+///
+/// ```no_rust
+/// msg-id          =   [CFWS] "<" id-left "@" id-right ">" [CFWS]
+/// id-left         =   dot-atom-text / obs-id-left
+/// id-right        =   dot-atom-text / no-fold-literal / obs-id-right
+/// no-fold-literal =   "[" *dtext "]"
+/// obs-id-left     =   local-part
+/// obs-id-right    =   domain
+/// ```
+///
+/// [RFC5322 section 3.6.4]: https://tools.ietf.org/html/rfc5322#section-3.6.4
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct MessageId {
+    left: Vec<u8>,
+    right: Vec<u8>,
+    span: Span,
+}
+
+impl MessageId {
+    /// Create a message identifier, checking that the left part is a dot-atom, and that the right
+    /// part is a dot-atom or a literal (`[192.0.2.1]`).
+    pub fn new(left: &[u8], right: &[u8]) -> Result<Self, Error> {
+        if let Some(i) = invalid_dot_atom_text_position(left) {
+            return Err(error(left, 0, i));
+        }
+        if !is_no_fold_literal(right) {
+            if let Some(i) = invalid_dot_atom_text_position(right) {
+                return Err(error(right, 0, i));
+            }
+        }
+        Ok(MessageId {
+            left: left.to_vec(),
+            right: right.to_vec(),
+            span: Span::default(),
+        })
+    }
+
+    /// Generate a new, unique, message identifier for the given domain. The left part is made of
+    /// the current time, the process identifier, a counter, and a random number.
+    #[cfg(feature = "std")]
+    pub fn generate(domain: &[u8]) -> Result<Self, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(now.as_nanos());
+        let left = format!(
+            "{:x}.{:x}.{:x}.{:016x}",
+            now.as_secs(),
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            hasher.finish()
+        );
+        MessageId::new(left.as_bytes(), domain)
+    }
+
+    /// Parse a message identifier. On success, the message identifier and the number of bytes
+    /// read are returned. The obsolete syntax is accepted.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+
+        // [CFWS] "<"
+        let mut i = skip_cfws(&buffer).unwrap_or(0);
+        if i >= bytes.len() || bytes[i] != b'<' {
+            return Err(error(bytes, pos, i));
+        }
+        i += 1;
+
+        // id-left "@"
+        let mut left = Vec::new();
+        buffer.set_position(pos + i);
+        i += parse_local_part(&buffer, &mut left)?;
+        if i >= bytes.len() || bytes[i] != b'@' {
+            return Err(error(bytes, pos, i));
+        }
+        i += 1;
+
+        // id-right ">" [CFWS]
+        let mut right = Vec::new();
+        buffer.set_position(pos + i);
+        i += parse_domain(&buffer, &mut right)?;
+        if i >= bytes.len() || bytes[i] != b'>' {
+            return Err(error(bytes, pos, i));
+        }
+        i += 1;
+        let span = Span::new(trim_cfws(input, i));
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+
+        Ok((MessageId { left, right, span }, i))
+    }
+
+    pub fn left(&self) -> &[u8] {
+        &self.left
+    }
+
+    pub fn right(&self) -> &[u8] {
+        &self.right
+    }
+
+    /// Return the left part as a string slice: message identifiers are US-ASCII, or UTF-8 if
+    /// the identifier was parsed from a buffer that accepts UTF-8.
+    pub fn left_str(&self) -> &str {
+        ascii_str(&self.left)
+    }
+
+    /// Return the right part as a string slice: message identifiers are US-ASCII, or UTF-8 if
+    /// the identifier was parsed from a buffer that accepts UTF-8.
+    pub fn right_str(&self) -> &str {
+        ascii_str(&self.right)
+    }
+
+    /// Return the position of the message identifier, including its angle brackets, in the
+    /// buffer it was parsed from. Identifiers created with `new` have no span.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.get()
+    }
+
+    /// Write the message identifier, including the angle brackets. A left part that is not a
+    /// dot-atom, which can only be obtained with the obsolete syntax, is quoted.
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_bytes(b"<")?;
+        write_local_part(&self.left, writer)?;
+        writer.write_bytes(b"@")?;
+        writer.write_bytes(&self.right)?;
+        writer.write_bytes(b">")?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::MessageId, MessageId::parse)
+    }
+}
+
+/// Parse a list of message identifiers, as found in the `In-Reply-To` and `References` fields. On
+/// success, the message identifiers and the number of bytes read are returned. Errors are given
+/// the context of the message identifier where they occured.
+///
+/// ```no_rust
+/// in-reply-to     =   "In-Reply-To:" 1*msg-id CRLF
+/// references      =   "References:" 1*msg-id CRLF
+/// ```
+pub fn parse_message_ids(input: &Buffer) -> Result<(Vec<MessageId>, usize), Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+    let mut message_ids = Vec::new();
+
+    let (message_id, mut i) = MessageId::parse(&buffer).map_err(|e| e.context(Token::MessageId, pos))?;
+    message_ids.push(message_id);
+    loop {
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+        if i >= bytes.len() || bytes[i] != b'<' {
+            break;
+        }
+        buffer.set_position(pos + i);
+        let (message_id, len) = MessageId::parse(&buffer).map_err(|e| e.context(Token::MessageId, pos + i))?;
+        message_ids.push(message_id);
+        i += len;
+    }
+    Ok((message_ids, i))
+}
+
+/// Write a list of message identifiers, as found in the `In-Reply-To` and `References` fields:
+/// the identifiers separated by spaces.
+pub fn write_message_ids<W: Sink>(message_ids: &[MessageId], writer: &mut W) -> Result<(), Error> {
+    for (i, message_id) in message_ids.iter().enumerate() {
+        if i > 0 {
+            writer.write_bytes(b" ")?;
+        }
+        message_id.write_to(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message_id(input: &[u8]) -> (Vec<u8>, Vec<u8>, usize) {
+        let (message_id, len) = MessageId::parse(&Buffer::new(input)).unwrap();
+        (message_id.left().to_vec(), message_id.right().to_vec(), len)
+    }
+
+    fn message_id_len(input: &Buffer) -> Result<usize, Error> {
+        MessageId::parse(input).map(|(_, len)| len)
+    }
+
+    #[test]
+    fn test_parse_message_id() {
+        assert_eq!(message_id(b"<1234@local.machine.example>"), (b"1234".to_vec(), b"local.machine.example".to_vec(), 28));
+        assert_eq!(message_id(b" <a.b@[192.0.2.1]> (comment)"), (b"a.b".to_vec(), b"[192.0.2.1]".to_vec(), 28));
+        assert_eq!(message_id(b"<\"a b\"@example>"), (b"a b".to_vec(), b"example".to_vec(), 15));
+        eof!(message_id_len, b"<1234@example");
+        eof!(message_id_len, b"<1234");
+        tok!(message_id_len, b"1234@example>", Token::MessageId, b'1', 0);
+        tok!(message_id_len, b"<1234@example)", Token::MessageId, b')', 13);
+        tok!(message_id_len, b"<1234 5@example>", Token::MessageId, b'5', 6);
+    }
+
+    #[test]
+    fn test_parse_message_ids() {
+        let input = b"<1234@local.machine.example> <3456@example.net>\r\n <abcd@example.net> x";
+        let (message_ids, len) = parse_message_ids(b!(input)).unwrap();
+        assert_eq!(message_ids.len(), 3);
+        assert_eq!(message_ids[2].left(), b"abcd");
+        assert_eq!(len, input.len() - 1);
+        eof!(message_id_len, b"");
+    }
+
+    #[test]
+    fn test_new_message_id() {
+        let message_id = MessageId::new(b"a.b", b"[192.0.2.1]").unwrap();
+        let mut output = Vec::new();
+        message_id.write_to(&mut output).unwrap();
+        assert_eq!(output, b"<a.b@[192.0.2.1]>");
+        assert_eq!((message_id.left_str(), message_id.right_str()), ("a.b", "[192.0.2.1]"));
+        assert_eq!(MessageId::parse(b!(output)).unwrap(), (message_id, 17));
+
+        assert!(MessageId::new(b"", b"example.com").unwrap_err().is_eof());
+        let e = MessageId::new(b"a b", b"example.com").unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::MessageId, b' ', 1));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+        assert!(MessageId::new(b"a", b"example..com").unwrap_err().is_token());
+
+        let message_id = MessageId::new("标识".as_bytes(), "例子.广告".as_bytes()).unwrap();
+        assert_eq!(message_id.to_string(), "<标识@例子.广告>");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_generate_message_id() {
+        let first = MessageId::generate(b"example.com").unwrap();
+        let second = MessageId::generate(b"example.com").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.right(), b"example.com");
+        let mut output = Vec::new();
+        first.write_to(&mut output).unwrap();
+        assert_eq!(MessageId::parse(b!(output)).unwrap().0, first);
+    }
+
+    #[test]
+    fn test_display_message_id() {
+        let message_id: MessageId = " (comment) <1234 @ local.machine.example> ".parse().unwrap();
+        assert_eq!(message_id.to_string(), "<1234@local.machine.example>");
+        assert!("<1234@example> x".parse::<MessageId>().unwrap_err().is_token());
+
+        let (message_ids, _) = parse_message_ids(b!(b"<a@example> (comment) <b@example>")).unwrap();
+        let mut output = Vec::new();
+        write_message_ids(&message_ids, &mut output).unwrap();
+        assert_eq!(output, b"<a@example> <b@example>");
+        assert_eq!(parse_message_ids(b!(output)).unwrap().0, message_ids);
+    }
+
+    #[test]
+    fn test_message_id_span() {
+        let input = b"<a@example> (comment) <b@example>";
+        let (message_ids, _) = parse_message_ids(b!(input)).unwrap();
+        assert_eq!(message_ids[0].span(), Some(0..11));
+        assert_eq!(message_ids[1].span(), Some(22..33));
+        assert_eq!(MessageId::new(b"a", b"example").unwrap().span(), None);
+    }
+}
@@ -0,0 +1,882 @@
+use core::fmt;
+use core::str::FromStr;
+
+use common::{display, parse_all};
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use sink::Sink;
+use whitespaces::skip_cfws;
+use quoted_string::parse_quoted_string;
+use date_time::DateTime;
+use Buffer;
+
+/// Return `true` if the byte represents a "tspecial" character. See [RFC2045 section 5.1].
+///
+/// ```no_rust
+/// tspecials :=  "(" / ")" / "<" / ">" / "@" /
+///               "," / ";" / ":" / "\" / <">
+///               "/" / "[" / "]" / "?" / "="
+/// ```
+///
+/// [RFC2045 section 5.1]: https://tools.ietf.org/html/rfc2045#section-5.1
+pub fn is_tspecial(c: u8) -> bool {
+    c == b'(' || c == b')' ||
+        c == b'<' || c == b'>' ||
+        c == b'@' || c == b',' ||
+        c == b';' || c == b':' ||
+        c == b'\\' || c == b'"' ||
+        c == b'/' || c == b'[' ||
+        c == b']' || c == b'?' ||
+        c == b'='
+}
+
+/// Return `true` if the byte can be part of a MIME token. See [RFC2045 section 5.1].
+///
+/// ```no_rust
+/// token := 1*<any (US-ASCII) CHAR except SPACE, CTLs, or tspecials>
+/// ```
+///
+/// [RFC2045 section 5.1]: https://tools.ietf.org/html/rfc2045#section-5.1
+pub fn is_token_char(c: u8) -> bool {
+    c > 32 && c < 127 && !is_tspecial(c)
+}
+
+/// Write a MIME token, such as a media type or a parameter name, checking that it only contains
+/// token characters, so that it cannot end the field or inject another one.
+fn write_token<W: Sink>(token: &[u8], writer: &mut W) -> Result<(), Error> {
+    if token.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
+    if let Some(i) = token.iter().position(|c| !is_token_char(*c)) {
+        return Err(ErrorKind::Token {
+            token: Token::MimeToken,
+            byte: token[i],
+            position: i,
+        }.into());
+    }
+    writer.write_bytes(token)
+}
+
+/// Read a MIME token. See [RFC2045 section 5.1].
+///
+/// ```no_rust
+/// token := 1*<any (US-ASCII) CHAR except SPACE, CTLs, or tspecials>
+/// ```
+///
+/// [RFC2045 section 5.1]: https://tools.ietf.org/html/rfc2045#section-5.1
+pub fn skip_token(input: &Buffer) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    if bytes.is_empty() {
+        return Err(ErrorKind::Eof.into());
+    }
+    let len = bytes.iter().take_while(|c| is_token_char(**c)).count();
+    if len == 0 {
+        return Err(ErrorKind::Token {
+            token: Token::MimeToken,
+            byte: bytes[0],
+            position: input.position(),
+        }.into());
+    }
+    Ok(len)
+}
+
+/// Parse a list of parameters, and append them to `parameters`. Attributes are case insensitive,
+/// so they are converted to lower case. Quoted values are un-escaped and unfolded. See
+/// [RFC2045 section 5.1].
+///
+/// ```no_rust
+/// *(";" parameter)
+/// parameter := attribute "=" value
+/// attribute := token
+/// value := token / quoted-string
+/// ```
+///
+/// Parsing stops at the first byte that does not start a new parameter. Empty parameters (i.e.
+/// two consecutive `;`, or a trailing `;`) are ignored.
+///
+/// [RFC2045 section 5.1]: https://tools.ietf.org/html/rfc2045#section-5.1
+pub fn parse_parameters(
+    input: &Buffer,
+    parameters: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let mut buffer = input.clone();
+    let mut i: usize = 0;
+
+    loop {
+        // read [CFWS] ";" [CFWS]
+        buffer.set_position(pos + i);
+        let mut j = i + skip_cfws(&buffer).unwrap_or(0);
+        if j >= bytes.len() || bytes[j] != b';' {
+            return Ok(i);
+        }
+        j += 1;
+        buffer.set_position(pos + j);
+        j += skip_cfws(&buffer).unwrap_or(0);
+        i = j;
+        if i >= bytes.len() || bytes[i] == b';' {
+            continue;
+        }
+
+        // read attribute [CFWS] "=" [CFWS]
+        buffer.set_position(pos + i);
+        let len = skip_token(&buffer)?;
+        let attribute = bytes[i..i + len].to_ascii_lowercase();
+        i += len;
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+        if i >= bytes.len() {
+            return Err(ErrorKind::Eof.into());
+        } else if bytes[i] != b'=' {
+            return Err(ErrorKind::Token {
+                token: Token::Parameter,
+                byte: bytes[i],
+                position: pos + i,
+            }.into());
+        }
+        i += 1;
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+        buffer.set_position(pos + i);
+
+        // read value
+        let mut value = Vec::new();
+        if i < bytes.len() && bytes[i] == b'"' {
+            i += parse_quoted_string(&buffer, &mut value)?;
+        } else {
+            let len = skip_token(&buffer)?;
+            value.extend_from_slice(&bytes[i..i + len]);
+            i += len;
+        }
+        parameters.push((attribute, value));
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+/// Decode `%XX` sequences. Invalid sequences are kept as is.
+fn percent_decode(value: &[u8], output: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < value.len() {
+        if value[i] == b'%' {
+            if let (Some(high), Some(low)) = (
+                value.get(i + 1).and_then(|c| hex_value(*c)),
+                value.get(i + 2).and_then(|c| hex_value(*c)),
+            ) {
+                output.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        output.push(value[i]);
+        i += 1;
+    }
+}
+
+/// Decode an extended value, and append it to `output`. The initial value of an extended
+/// parameter starts with the charset and the language, which are skipped.
+fn decode_extended_value(value: &[u8], initial: bool, output: &mut Vec<u8>) {
+    let mut value = value;
+    if initial {
+        let mut quotes = value.iter().enumerate().filter(|&(_, c)| *c == b'\'');
+        if let (Some(_), Some((second, _))) = (quotes.next(), quotes.next()) {
+            value = &value[second + 1..];
+        }
+    }
+    percent_decode(value, output);
+}
+
+/// Return `true` if the attribute is the given parameter name, or one of its extended or continued
+/// forms defined by [RFC2231] (`name*`, `name*0`, `name*1*`...).
+///
+/// [RFC2231]: https://tools.ietf.org/html/rfc2231
+fn is_parameter_section(attribute: &[u8], name: &[u8]) -> bool {
+    if attribute.len() < name.len() || !attribute[..name.len()].eq_ignore_ascii_case(name) {
+        return false;
+    }
+    match attribute[name.len()..].split_first() {
+        None => true,
+        Some((b'*', rest)) => {
+            let digits = rest.strip_suffix(b"*").unwrap_or(rest);
+            rest.is_empty() || (!digits.is_empty() && digits.iter().all(u8::is_ascii_digit))
+        }
+        Some(_) => false,
+    }
+}
+
+/// Return the value of the given parameter, decoding the extended and continued values defined by
+/// [RFC2231]:
+///
+/// ```no_rust
+/// title*=us-ascii'en-us'This%20is%20%2A%2A%2Afun%2A%2A%2A
+/// title*0*=us-ascii'en'This%20is%20even%20more%20
+/// title*1*=%2A%2A%2Afun%2A%2A%2A%20
+/// title*2="isn't it!"
+/// ```
+///
+/// Extended values are preferred over regular values. The charset is not converted: the bytes
+/// are returned as they were encoded.
+///
+/// [RFC2231]: https://tools.ietf.org/html/rfc2231
+pub fn decode_parameter(parameters: &[(Vec<u8>, Vec<u8>)], name: &[u8]) -> Option<Vec<u8>> {
+    let name = name.to_ascii_lowercase();
+    let mut extended_name = name.clone();
+    extended_name.push(b'*');
+
+    // name*=charset'language'value
+    if let Some((_, value)) = parameters.iter().find(|(attribute, _)| *attribute == extended_name) {
+        let mut output = Vec::new();
+        decode_extended_value(value, true, &mut output);
+        return Some(output);
+    }
+
+    // name*0=value or name*0*=charset'language'value, followed by name*1 etc.
+    let mut output = Vec::new();
+    let mut section = 0;
+    loop {
+        let mut section_name = extended_name.clone();
+        section_name.extend_from_slice(section.to_string().as_bytes());
+        let found = parameters.iter().find_map(|(attribute, value)| {
+            if *attribute == section_name {
+                Some((value, false))
+            } else if attribute.len() == section_name.len() + 1
+                && attribute.starts_with(&section_name)
+                && attribute.ends_with(b"*")
+            {
+                Some((value, true))
+            } else {
+                None
+            }
+        });
+        match found {
+            Some((value, true)) => decode_extended_value(value, section == 0, &mut output),
+            Some((value, false)) => output.extend_from_slice(value),
+            None => break,
+        }
+        section += 1;
+    }
+    if section > 0 {
+        return Some(output);
+    }
+
+    parameters
+        .iter()
+        .find(|(attribute, _)| *attribute == name)
+        .map(|(_, value)| value.clone())
+}
+
+/// Write parameters, each preceded by `; `. Values are written as tokens when possible, as quoted
+/// strings if they are printable US-ASCII, and as UTF-8 extended values otherwise (see [RFC2231
+/// section 4]). An error is returned if an attribute is not a token.
+///
+/// [RFC2231 section 4]: https://tools.ietf.org/html/rfc2231#section-4
+pub fn write_parameters<W: Sink>(parameters: &[(Vec<u8>, Vec<u8>)], writer: &mut W) -> Result<(), Error> {
+    for (attribute, value) in parameters {
+        writer.write_bytes(b"; ")?;
+        write_token(attribute, writer)?;
+        if !value.is_empty() && value.iter().all(|c| is_token_char(*c)) {
+            writer.write_bytes(b"=")?;
+            writer.write_bytes(value)?;
+        } else if value.iter().all(|c| (32..=126).contains(c)) {
+            writer.write_bytes(b"=\"")?;
+            for &c in value {
+                if c == b'"' || c == b'\\' {
+                    writer.write_bytes(b"\\")?;
+                }
+                writer.write_bytes(&[c])?;
+            }
+            writer.write_bytes(b"\"")?;
+        } else {
+            writer.write_bytes(b"*=utf-8''")?;
+            for &c in value {
+                // attribute-char := <any (US-ASCII) CHAR except SPACE, CTLs, "*", "'", "%", or tspecials>
+                if is_token_char(c) && c != b'*' && c != b'\'' && c != b'%' {
+                    writer.write_bytes(&[c])?;
+                } else {
+                    write!(writer, "%{:02X}", c)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A media type, as found in the `Content-Type` header field. See [RFC2045 section 5.1].
+///
+/// ```no_rust
+/// content := "Content-Type" ":" type "/" subtype
+///            *(";" parameter)
+///            ; Matching of media type and subtype
+///            ; is ALWAYS case-insensitive.
+/// ```
+///
+/// The type, subtype, and parameter names are stored in lower case.
+///
+/// [RFC2045 section 5.1]: https://tools.ietf.org/html/rfc2045#section-5.1
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct ContentType {
+    mime_type: Vec<u8>,
+    subtype: Vec<u8>,
+    parameters: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Default for ContentType {
+    /// `text/plain; charset=us-ascii`, which is the default media type of a message that has no
+    /// `Content-Type` field. See [RFC2045 section 5.2].
+    ///
+    /// [RFC2045 section 5.2]: https://tools.ietf.org/html/rfc2045#section-5.2
+    fn default() -> Self {
+        ContentType {
+            mime_type: b"text".to_vec(),
+            subtype: b"plain".to_vec(),
+            parameters: vec![(b"charset".to_vec(), b"us-ascii".to_vec())],
+        }
+    }
+}
+
+impl ContentType {
+    pub fn new(mime_type: &[u8], subtype: &[u8]) -> Self {
+        ContentType {
+            mime_type: mime_type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Set a parameter, replacing any existing parameter with the same name. The name is stored
+    /// in lower case.
+    pub fn with_parameter(mut self, name: &[u8], value: &[u8]) -> Self {
+        let name = name.to_ascii_lowercase();
+        self.parameters.retain(|(attribute, _)| *attribute != name);
+        self.parameters.push((name, value.to_vec()));
+        self
+    }
+
+    /// Parse the value of a `Content-Type` field. On success, the media type and the number of
+    /// bytes read are returned.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+
+        // read [CFWS] type [CFWS]
+        let mut i = skip_cfws(&buffer).unwrap_or(0);
+        buffer.set_position(pos + i);
+        let len = skip_token(&buffer)?;
+        let mime_type = bytes[i..i + len].to_ascii_lowercase();
+        i += len;
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+
+        // read "/"
+        if i >= bytes.len() {
+            return Err(ErrorKind::Eof.into());
+        } else if bytes[i] != b'/' {
+            return Err(ErrorKind::Token {
+                token: Token::ContentType,
+                byte: bytes[i],
+                position: pos + i,
+            }.into());
+        }
+        i += 1;
+
+        // read [CFWS] subtype
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+        buffer.set_position(pos + i);
+        let len = skip_token(&buffer)?;
+        let subtype = bytes[i..i + len].to_ascii_lowercase();
+        i += len;
+
+        // read *(";" parameter) [CFWS]
+        let mut parameters = Vec::new();
+        buffer.set_position(pos + i);
+        i += parse_parameters(&buffer, &mut parameters)?;
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+
+        let content_type = ContentType {
+            mime_type,
+            subtype,
+            parameters,
+        };
+        Ok((content_type, i))
+    }
+
+    pub fn mime_type(&self) -> &[u8] {
+        &self.mime_type
+    }
+
+    pub fn subtype(&self) -> &[u8] {
+        &self.subtype
+    }
+
+    pub fn parameters(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.parameters
+    }
+
+    /// Return the value of the first parameter with the given name. The name is case
+    /// insensitive.
+    pub fn parameter(&self, name: &[u8]) -> Option<&[u8]> {
+        self.parameters
+            .iter()
+            .find(|&(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Return `true` for `multipart/*` media types.
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type == b"multipart"
+    }
+
+    /// Return the `boundary` parameter of a `multipart/*` media type.
+    pub fn boundary(&self) -> Option<&[u8]> {
+        if self.is_multipart() {
+            self.parameter(b"boundary")
+        } else {
+            None
+        }
+    }
+
+    /// Return the `charset` parameter.
+    pub fn charset(&self) -> Option<&[u8]> {
+        self.parameter(b"charset")
+    }
+
+    /// Return the `name` parameter, which is commonly used as a file name. See
+    /// [`decode_parameter`].
+    ///
+    /// [`decode_parameter`]: fn.decode_parameter.html
+    pub fn name(&self) -> Option<Vec<u8>> {
+        decode_parameter(&self.parameters, b"name")
+    }
+
+    /// Write the media type, followed by its parameters (see [`write_parameters`]). An error is
+    /// returned if the type or the subtype is not a token.
+    ///
+    /// [`write_parameters`]: fn.write_parameters.html
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        write_token(&self.mime_type, writer)?;
+        writer.write_bytes(b"/")?;
+        write_token(&self.subtype, writer)?;
+        write_parameters(&self.parameters, writer)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for ContentType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::ContentType, ContentType::parse)
+    }
+}
+
+/// The type of a presentation disposition. See [RFC2183 section 2].
+///
+/// ```no_rust
+/// disposition-type := "inline"
+///                   / "attachment"
+///                   / extension-token
+///                   ; values are not case-sensitive
+/// ```
+///
+/// [RFC2183 section 2]: https://tools.ietf.org/html/rfc2183#section-2
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum DispositionKind {
+    Inline,
+    Attachment,
+    /// An extension token, in lower case
+    Other(Vec<u8>),
+}
+
+impl DispositionKind {
+    /// Return the name of the disposition type, as written in the `Content-Disposition` field.
+    pub fn name(&self) -> &[u8] {
+        match *self {
+            DispositionKind::Inline => b"inline",
+            DispositionKind::Attachment => b"attachment",
+            DispositionKind::Other(ref name) => name,
+        }
+    }
+}
+
+/// A presentation disposition, as found in the `Content-Disposition` header field. See [RFC2183
+/// section 2].
+///
+/// ```no_rust
+/// disposition := "Content-Disposition" ":"
+///                disposition-type
+///                *(";" disposition-parm)
+/// disposition-parm := filename-parm
+///                   / creation-date-parm
+///                   / modification-date-parm
+///                   / read-date-parm
+///                   / size-parm
+///                   / parameter
+/// filename-parm := "filename" "=" value
+/// creation-date-parm := "creation-date" "=" quoted-date-time
+/// modification-date-parm := "modification-date" "=" quoted-date-time
+/// read-date-parm := "read-date" "=" quoted-date-time
+/// size-parm := "size" "=" 1*DIGIT
+/// quoted-date-time := quoted-string
+///                  ; contents MUST be an RFC 822 `date-time'
+///                  ; numeric timezones (+HHMM or -HHMM) MUST be used
+/// ```
+///
+/// Invalid dates and sizes are ignored.
+///
+/// [RFC2183 section 2]: https://tools.ietf.org/html/rfc2183#section-2
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct ContentDisposition {
+    kind: DispositionKind,
+    filename: Option<Vec<u8>>,
+    creation_date: Option<DateTime>,
+    modification_date: Option<DateTime>,
+    read_date: Option<DateTime>,
+    size: Option<u64>,
+    parameters: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ContentDisposition {
+    pub fn new(kind: DispositionKind) -> Self {
+        ContentDisposition {
+            kind,
+            filename: None,
+            creation_date: None,
+            modification_date: None,
+            read_date: None,
+            size: None,
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Set the suggested file name, replacing any existing `filename` parameter, including its
+    /// extended and continued forms (`filename*`, `filename*0`...).
+    pub fn with_filename(mut self, filename: &[u8]) -> Self {
+        self.parameters.retain(|(attribute, _)| !is_parameter_section(attribute, b"filename"));
+        self.parameters.push((b"filename".to_vec(), filename.to_vec()));
+        self.filename = Some(filename.to_vec());
+        self
+    }
+
+    /// Parse the value of a `Content-Disposition` field. On success, the disposition and the
+    /// number of bytes read are returned.
+    pub fn parse(input: &Buffer) -> Result<(Self, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+
+        // read [CFWS] disposition-type
+        let mut i = skip_cfws(&buffer).unwrap_or(0);
+        buffer.set_position(pos + i);
+        let len = skip_token(&buffer)?;
+        let kind = match bytes[i..i + len].to_ascii_lowercase() {
+            ref kind if kind == b"inline" => DispositionKind::Inline,
+            ref kind if kind == b"attachment" => DispositionKind::Attachment,
+            kind => DispositionKind::Other(kind),
+        };
+        i += len;
+
+        // read *(";" disposition-parm) [CFWS]
+        let mut parameters = Vec::new();
+        buffer.set_position(pos + i);
+        i += parse_parameters(&buffer, &mut parameters)?;
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+
+        let date = |name: &[u8]| {
+            parameters
+                .iter()
+                .find(|(attribute, _)| attribute == name)
+                .and_then(|(_, value)| DateTime::parse(&Buffer::new(value)).ok())
+                .map(|(date, _)| date)
+        };
+        let disposition = ContentDisposition {
+            filename: decode_parameter(&parameters, b"filename"),
+            creation_date: date(b"creation-date"),
+            modification_date: date(b"modification-date"),
+            read_date: date(b"read-date"),
+            size: parameters
+                .iter()
+                .find(|(attribute, _)| attribute == b"size")
+                .and_then(|(_, value)| ::core::str::from_utf8(value).ok()?.parse().ok()),
+            kind,
+            parameters,
+        };
+        Ok((disposition, i))
+    }
+
+    pub fn kind(&self) -> &DispositionKind {
+        &self.kind
+    }
+
+    /// Return the suggested file name. See [`decode_parameter`].
+    ///
+    /// [`decode_parameter`]: fn.decode_parameter.html
+    pub fn filename(&self) -> Option<&[u8]> {
+        self.filename.as_ref().map(|filename| &filename[..])
+    }
+
+    pub fn creation_date(&self) -> Option<&DateTime> {
+        self.creation_date.as_ref()
+    }
+
+    pub fn modification_date(&self) -> Option<&DateTime> {
+        self.modification_date.as_ref()
+    }
+
+    pub fn read_date(&self) -> Option<&DateTime> {
+        self.read_date.as_ref()
+    }
+
+    /// Return the approximate size of the file, in bytes.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    pub fn parameters(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.parameters
+    }
+
+    /// Return the value of the first parameter with the given name. The name is case
+    /// insensitive.
+    pub fn parameter(&self, name: &[u8]) -> Option<&[u8]> {
+        self.parameters
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Write the value of a `Content-Disposition` field. Parameters are written with
+    /// [`write_parameters`]. An error is returned if an extension disposition type is not a token.
+    ///
+    /// [`write_parameters`]: fn.write_parameters.html
+    pub fn write_to<W: Sink>(&self, writer: &mut W) -> Result<(), Error> {
+        write_token(self.kind.name(), writer)?;
+        write_parameters(&self.parameters, writer)
+    }
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        display(f, |output| self.write_to(output))
+    }
+}
+
+impl FromStr for ContentDisposition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_all(s.as_bytes(), Token::ContentDisposition, ContentDisposition::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> (ContentType, usize) {
+        ContentType::parse(b!(input)).unwrap()
+    }
+
+    #[test]
+    fn test_token() {
+        ok!(skip_token, b"text", 4);
+        ok!(skip_token, b"x-my_token.1/plain", 12);
+        ok!(skip_token, b"text plain", 4);
+        eof!(skip_token, b"");
+        tok!(skip_token, b"/plain", Token::MimeToken, b'/', 0);
+        tok!(skip_token, b" text", Token::MimeToken, b' ', 0);
+    }
+
+    #[test]
+    fn test_parse_content_type() {
+        let (content_type, len) = parse(b"text/plain");
+        assert_eq!(len, 10);
+        assert_eq!(content_type.mime_type(), b"text");
+        assert_eq!(content_type.subtype(), b"plain");
+        assert!(content_type.parameters().is_empty());
+
+        let (content_type, len) = parse(b" Text / HTML (comment) ; Charset = \"UTF-8\" ;format=flowed ");
+        assert_eq!(len, 58);
+        assert_eq!(content_type, ContentType {
+            mime_type: b"text".to_vec(),
+            subtype: b"html".to_vec(),
+            parameters: vec![
+                (b"charset".to_vec(), b"UTF-8".to_vec()),
+                (b"format".to_vec(), b"flowed".to_vec()),
+            ],
+        });
+        assert_eq!(content_type.charset(), Some(&b"UTF-8"[..]));
+        assert_eq!(content_type.parameter(b"FORMAT"), Some(&b"flowed"[..]));
+        assert_eq!(content_type.boundary(), None);
+
+        let (content_type, _) = parse(b"multipart/mixed;\r\n boundary=\"simple \\\"boundary\\\"\"");
+        assert!(content_type.is_multipart());
+        assert_eq!(content_type.boundary(), Some(&b"simple \"boundary\""[..]));
+
+        let (content_type, _) = parse(b"multipart/alternative; boundary=abc;;");
+        assert_eq!(content_type.boundary(), Some(&b"abc"[..]));
+
+        // parsing stops at the first byte that is not part of a parameter
+        let (_, len) = parse(b"text/plain garbage");
+        assert_eq!(len, 11);
+    }
+
+    #[test]
+    fn test_parse_content_type_failure() {
+        let e = ContentType::parse(b!(b"text")).unwrap_err();
+        assert!(e.is_eof());
+        let e = ContentType::parse(b!(b"text plain")).unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::ContentType, b'p', 5));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+        let e = ContentType::parse(b!(b"text/plain; charset")).unwrap_err();
+        assert!(e.is_eof());
+        let e = ContentType::parse(b!(b"text/plain; charset utf-8")).unwrap_err();
+        if let ErrorKind::Token { token, byte, position } = *e.kind() {
+            assert_eq!((token, byte, position), (Token::Parameter, b'u', 20));
+        } else {
+            panic!("unexpected error {:?}", e);
+        }
+    }
+
+    #[test]
+    fn test_write_content_type() {
+        let content_type = ContentType::new(b"Text", b"Plain")
+            .with_parameter(b"charset", b"us-ascii")
+            .with_parameter(b"Charset", b"utf-8")
+            .with_parameter(b"format", b"a \"b\"")
+            .with_parameter(b"name", "café 100%.txt".as_bytes());
+        let mut output = Vec::new();
+        content_type.write_to(&mut output).unwrap();
+        assert_eq!(
+            &output[..],
+            &b"text/plain; charset=utf-8; format=\"a \\\"b\\\"\"; name*=utf-8''caf%C3%A9%20100%25.txt"[..]
+        );
+        let (parsed, len) = ContentType::parse(b!(output)).unwrap();
+        assert_eq!(len, output.len());
+        assert_eq!(parsed.charset(), Some(&b"utf-8"[..]));
+        assert_eq!(parsed.parameter(b"format"), Some(&b"a \"b\""[..]));
+        assert_eq!(parsed.name(), Some("café 100%.txt".as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_decode_parameter() {
+        let parameters = |input: &[u8]| {
+            let mut parameters = Vec::new();
+            parse_parameters(b!(input), &mut parameters).unwrap();
+            parameters
+        };
+        let decode = |input: &[u8]| decode_parameter(&parameters(input), b"title");
+
+        assert_eq!(decode(b"; title=simple"), Some(b"simple".to_vec()));
+        assert_eq!(decode(b"; other=simple"), None);
+        assert_eq!(
+            decode(b"; title*=us-ascii'en-us'This%20is%20%2A%2A%2Afun%2A%2A%2A"),
+            Some(b"This is ***fun***".to_vec())
+        );
+        assert_eq!(
+            decode(b"; title*=UTF-8''caf%C3%A9.txt; title=fallback"),
+            Some("café.txt".as_bytes().to_vec())
+        );
+        assert_eq!(
+            decode(b"; title*0*=us-ascii'en'This%20is%20even%20more%20;\r\n \
+                     title*1*=%2A%2A%2Afun%2A%2A%2A%20;\r\n \
+                     title*2=\"isn't it!\""),
+            Some(b"This is even more ***fun*** isn't it!".to_vec())
+        );
+        assert_eq!(
+            decode(b"; TITLE*1=\" world\"; Title*0=hello"),
+            Some(b"hello world".to_vec())
+        );
+        // invalid sequences are kept
+        assert_eq!(decode(b"; title*=''100%25%2"), Some(b"100%%2".to_vec()));
+        // missing charset and language
+        assert_eq!(decode(b"; title*=a%20b"), Some(b"a b".to_vec()));
+    }
+
+    #[test]
+    fn test_parse_content_disposition() {
+        let input = b"attachment; filename=genome.jpeg;\r\n \
+            creation-date=\"Wed, 12 Feb 1997 16:29:51 -0500\";\r\n \
+            modification-date=\"Wed, 12 Feb 1997 16:29:51 -0500\";\r\n \
+            read-date=invalid; size=1024";
+        let (disposition, len) = ContentDisposition::parse(b!(input)).unwrap();
+        assert_eq!(len, input.len());
+        assert_eq!(disposition.kind(), &DispositionKind::Attachment);
+        assert_eq!(disposition.filename(), Some(&b"genome.jpeg"[..]));
+        let date = disposition.creation_date().unwrap();
+        assert_eq!((date.year(), date.month(), date.day()), (1997, 2, 12));
+        assert_eq!(disposition.modification_date(), disposition.creation_date());
+        assert_eq!(disposition.read_date(), None);
+        assert_eq!(disposition.size(), Some(1024));
+        assert_eq!(disposition.parameter(b"Read-Date"), Some(&b"invalid"[..]));
+        assert_eq!(disposition.parameters().len(), 5);
+
+        let (disposition, _) = ContentDisposition::parse(b!(b" INLINE ")).unwrap();
+        assert_eq!(disposition.kind(), &DispositionKind::Inline);
+        assert_eq!(disposition.filename(), None);
+        assert_eq!(disposition.size(), None);
+
+        let (disposition, _) = ContentDisposition::parse(b!(b"X-Custom; filename*=utf-8''%E2%82%AC.txt")).unwrap();
+        assert_eq!(disposition.kind(), &DispositionKind::Other(b"x-custom".to_vec()));
+        assert_eq!(disposition.filename(), Some("€.txt".as_bytes()));
+
+        assert!(ContentDisposition::parse(b!(b"")).unwrap_err().is_eof());
+        assert!(ContentDisposition::parse(b!(b"\"attachment\"")).unwrap_err().is_token());
+    }
+
+    #[test]
+    fn test_write_content_disposition() {
+        let (disposition, _) = ContentDisposition::parse(b!(b"attachment; filename*=utf-8''old.txt")).unwrap();
+        let disposition = disposition.with_filename("rapport été.pdf".as_bytes());
+        let mut output = Vec::new();
+        disposition.write_to(&mut output).unwrap();
+        assert_eq!(&output[..], &b"attachment; filename*=utf-8''rapport%20%C3%A9t%C3%A9.pdf"[..]);
+        let (parsed, len) = ContentDisposition::parse(b!(output)).unwrap();
+        assert_eq!(len, output.len());
+        assert_eq!(parsed.kind(), &DispositionKind::Attachment);
+        assert_eq!(parsed.filename(), Some("rapport été.pdf".as_bytes()));
+
+        // continuations of the old file name are removed too
+        let input = b"attachment; filename*0*=utf-8''old; filename*1=.txt; filename*x=y; size=3";
+        let (disposition, _) = ContentDisposition::parse(b!(input)).unwrap();
+        let disposition = disposition.with_filename(b"new.txt");
+        let mut output = Vec::new();
+        disposition.write_to(&mut output).unwrap();
+        assert_eq!(output, b"attachment; filename*x=y; size=3; filename=new.txt");
+        assert_eq!(ContentDisposition::parse(b!(output)).unwrap().0.filename(), Some(&b"new.txt"[..]));
+
+        let mut output = Vec::new();
+        ContentDisposition::new(DispositionKind::Inline).write_to(&mut output).unwrap();
+        assert_eq!(output, b"inline");
+    }
+
+    #[test]
+    fn test_display_mime_fields() {
+        let content_type: ContentType = "Text/HTML (comment) ; Charset = \"UTF-8\" ".parse().unwrap();
+        assert_eq!(content_type.to_string(), "text/html; charset=UTF-8");
+        assert!("text/plain x".parse::<ContentType>().unwrap_err().is_token());
+
+        let disposition: ContentDisposition = "Attachment; filename=\"a b.txt\"".parse().unwrap();
+        assert_eq!(disposition.to_string(), "attachment; filename=\"a b.txt\"");
+        assert_eq!(disposition.to_string().parse::<ContentDisposition>().unwrap(), disposition);
+    }
+}
@@ -0,0 +1,588 @@
+use errors::{Error, ErrorKind};
+use message::{parse_header_section, Field};
+use mime::{ContentDisposition, ContentType, DispositionKind};
+use multipart::split_multipart;
+use sink::Sink;
+use transfer_encoding::{decode, DecodingReader, TransferEncoding};
+use Buffer;
+
+/// Default maximum nesting depth of MIME parts. See [`MimePart::parse_with_max_depth`].
+///
+/// [`MimePart::parse_with_max_depth`]: struct.MimePart.html#method.parse_with_max_depth
+pub static DEFAULT_MAX_DEPTH: usize = 32;
+
+/// The body of a MIME part.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Body<'buf> {
+    /// A body that is not a container, still encoded with the part's transfer encoding.
+    Leaf(&'buf [u8]),
+    /// The parts of a `multipart/*` body. See [RFC2046 section 5.1].
+    ///
+    /// [RFC2046 section 5.1]: https://tools.ietf.org/html/rfc2046#section-5.1
+    Multipart(Vec<MimePart<'buf>>),
+    /// A message embedded in a `message/rfc822` or `message/global` body. See [RFC2046 section
+    /// 5.2.1] and [RFC6532 section 3.7].
+    ///
+    /// [RFC2046 section 5.2.1]: https://tools.ietf.org/html/rfc2046#section-5.2.1
+    /// [RFC6532 section 3.7]: https://tools.ietf.org/html/rfc6532#section-3.7
+    Message(Box<MimePart<'buf>>),
+}
+
+/// A node of the MIME tree of a message: the message itself, one of the parts of a multipart
+/// body, or an embedded message.
+///
+/// Parsing is lenient: a missing or invalid `Content-Type` defaults to `text/plain` (or
+/// `message/rfc822` in a `multipart/digest`), an unknown transfer encoding is treated as
+/// `binary`, and a multipart body without any delimiter is treated as a leaf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MimePart<'buf> {
+    raw: &'buf [u8],
+    raw_body: &'buf [u8],
+    fields: Vec<Field<'buf>>,
+    content_type: ContentType,
+    transfer_encoding: TransferEncoding,
+    body: Body<'buf>,
+}
+
+impl<'buf> MimePart<'buf> {
+    /// Parse a message and all its nested parts. The whole buffer is consumed. Parts cannot be
+    /// nested deeper than [`DEFAULT_MAX_DEPTH`].
+    ///
+    /// [`DEFAULT_MAX_DEPTH`]: static.DEFAULT_MAX_DEPTH.html
+    pub fn parse(input: &Buffer<'buf>) -> Result<Self, Error> {
+        MimePart::parse_with_max_depth(input, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Parse a message and all its nested parts. The whole buffer is consumed. If the parts are
+    /// nested deeper than `max_depth`, `ErrorKind::TooDeep` is returned. The message itself is at
+    /// depth 0.
+    pub fn parse_with_max_depth(input: &Buffer<'buf>, max_depth: usize) -> Result<Self, Error> {
+        MimePart::parse_part(input.remaining(), ContentType::default(), 0, max_depth)
+    }
+
+    fn parse_part(
+        raw: &'buf [u8],
+        default_type: ContentType,
+        depth: usize,
+        max_depth: usize,
+    ) -> Result<Self, Error> {
+        if depth > max_depth {
+            return Err(ErrorKind::TooDeep { max_depth }.into());
+        }
+        let (fields, len) = parse_header_section(&Buffer::new(raw))?;
+        let raw_body = &raw[len..];
+        let body = raw_body;
+
+        let content_type = find_content_type(&fields, default_type);
+        let transfer_encoding = find_transfer_encoding(&fields);
+
+        let body = if let Some(boundary) = content_type.boundary() {
+            let default_type = default_part_type(&content_type);
+            match split_multipart(&Buffer::new(body), boundary) {
+                Ok(multipart) => {
+                    let mut parts = Vec::with_capacity(multipart.parts().len());
+                    for part in multipart.parts() {
+                        parts.push(MimePart::parse_part(part, default_type.clone(), depth + 1, max_depth)?);
+                    }
+                    Body::Multipart(parts)
+                }
+                Err(ref e) if e.is_eof() => Body::Leaf(body),
+                Err(e) => return Err(e),
+            }
+        } else if is_message(&content_type) && is_identity(transfer_encoding) {
+            let message = MimePart::parse_part(body, ContentType::default(), depth + 1, max_depth)?;
+            Body::Message(Box::new(message))
+        } else {
+            Body::Leaf(body)
+        };
+
+        Ok(MimePart {
+            raw,
+            raw_body,
+            fields,
+            content_type,
+            transfer_encoding,
+            body,
+        })
+    }
+
+    /// Return the bytes of the part, header section included.
+    pub fn raw(&self) -> &'buf [u8] {
+        self.raw
+    }
+
+    /// Return the bytes of the body, still encoded with the part's transfer encoding. For
+    /// containers, this includes the delimiters or the header section of the embedded message.
+    pub fn raw_body(&self) -> &'buf [u8] {
+        self.raw_body
+    }
+
+    pub fn fields(&self) -> &[Field<'buf>] {
+        &self.fields
+    }
+
+    /// Return the first field with the given name.
+    pub fn field(&self, name: &[u8]) -> Option<&Field<'buf>> {
+        self.fields.iter().find(|field| field.is(name))
+    }
+
+    pub fn content_type(&self) -> &ContentType {
+        &self.content_type
+    }
+
+    /// Parse the `Content-Disposition` field, if any. Invalid fields are ignored.
+    pub fn content_disposition(&self) -> Option<ContentDisposition> {
+        let field = self.field(b"Content-Disposition")?;
+        ContentDisposition::parse(&Buffer::new(&field.unfolded_value()))
+            .ok()
+            .map(|(disposition, _)| disposition)
+    }
+
+    pub fn transfer_encoding(&self) -> TransferEncoding {
+        self.transfer_encoding
+    }
+
+    pub fn body(&self) -> &Body<'buf> {
+        &self.body
+    }
+
+    /// Return the parts of a multipart body, or an empty slice for other bodies.
+    pub fn children(&self) -> &[MimePart<'buf>] {
+        match self.body {
+            Body::Multipart(ref parts) => parts,
+            _ => &[],
+        }
+    }
+
+    /// Decode a leaf body with the part's transfer encoding, and write it into the provided
+    /// writer. The number of encoded bytes read is returned. Nothing is written for containers.
+    pub fn decode_body<W: Sink>(&self, writer: &mut W) -> Result<usize, Error> {
+        match self.body {
+            Body::Leaf(body) => decode(self.transfer_encoding, &Buffer::new(body), writer),
+            _ => Ok(0),
+        }
+    }
+
+    /// Return an iterator over this part and all its descendants, in depth-first order.
+    pub fn iter<'a>(&'a self) -> Iter<'a, 'buf> {
+        Iter { stack: vec![self] }
+    }
+
+    /// Return an iterator over the attachments found in this part and all its descendants, in
+    /// depth-first order. A part is considered to be an attachment if:
+    ///
+    /// - it is not a multipart, and its disposition is `attachment`
+    /// - or its content type is not `text/*`, `multipart/*`, `message/rfc822` or
+    ///   `message/global`, its disposition is not `attachment` (usually `inline`, or missing),
+    ///   and it has a file name
+    ///
+    /// The parts of an embedded message are visited too, even if the message is itself an
+    /// attachment.
+    pub fn attachments<'a>(&'a self) -> Attachments<'a, 'buf> {
+        Attachments { iter: self.iter() }
+    }
+
+    /// Return the part designated by the given path, using IMAP part numbers (see [RFC3501
+    /// section 6.4.5]): the parts of a multipart are numbered from 1, the body of a message that
+    /// is not multipart is part 1, and the parts of an embedded message are numbered as if they
+    /// were the parts of the `message/rfc822` part. For instance:
+    ///
+    /// ```no_rust
+    /// multipart/mixed                 (empty path)
+    ///   text/plain                    1
+    ///   message/rfc822                2
+    ///     multipart/alternative
+    ///       text/plain                2.1
+    ///       text/html                 2.2
+    /// ```
+    ///
+    /// [RFC3501 section 6.4.5]: https://tools.ietf.org/html/rfc3501#section-6.4.5
+    pub fn part(&self, path: &[usize]) -> Option<&MimePart<'buf>> {
+        self.find_part(path, true)
+    }
+
+    /// Same as [`part`](#method.part), but the path is given as a string, for instance `1.2.3`.
+    pub fn part_by_number(&self, number: &str) -> Option<&MimePart<'buf>> {
+        let path: Result<Vec<usize>, _> = number.split('.').map(|n| n.parse()).collect();
+        self.part(&path.ok()?)
+    }
+
+    fn find_part(&self, path: &[usize], is_message: bool) -> Option<&MimePart<'buf>> {
+        let (&first, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return Some(self),
+        };
+        match self.body {
+            Body::Multipart(ref parts) => parts.get(first.checked_sub(1)?)?.find_part(rest, false),
+            _ if is_message => {
+                if first == 1 {
+                    self.find_part(rest, false)
+                } else {
+                    None
+                }
+            }
+            Body::Message(ref message) => message.find_part(path, true),
+            Body::Leaf(_) => None,
+        }
+    }
+}
+
+/// An attachment. See [`MimePart::attachments`].
+///
+/// [`MimePart::attachments`]: struct.MimePart.html#method.attachments
+pub struct Attachment<'a, 'buf: 'a> {
+    part: &'a MimePart<'buf>,
+    disposition: Option<ContentDisposition>,
+    filename: Option<Vec<u8>>,
+}
+
+impl<'a, 'buf> Attachment<'a, 'buf> {
+    pub fn part(&self) -> &'a MimePart<'buf> {
+        self.part
+    }
+
+    /// Return the file name, taken from the `filename` parameter of the `Content-Disposition`
+    /// field, or from the `name` parameter of the `Content-Type` field.
+    pub fn filename(&self) -> Option<&[u8]> {
+        self.filename.as_ref().map(|filename| &filename[..])
+    }
+
+    pub fn content_type(&self) -> &'a ContentType {
+        self.part.content_type()
+    }
+
+    pub fn disposition(&self) -> Option<&ContentDisposition> {
+        self.disposition.as_ref()
+    }
+
+    /// Return a reader over the content of the attachment, decoded with the part's transfer
+    /// encoding.
+    pub fn reader(&self) -> DecodingReader<&'buf [u8]> {
+        DecodingReader::new(self.part.transfer_encoding(), self.part.raw_body())
+    }
+}
+
+/// Iterator over the attachments of a MIME tree. See [`MimePart::attachments`].
+///
+/// [`MimePart::attachments`]: struct.MimePart.html#method.attachments
+pub struct Attachments<'a, 'buf: 'a> {
+    iter: Iter<'a, 'buf>,
+}
+
+impl<'a, 'buf> Iterator for Attachments<'a, 'buf> {
+    type Item = Attachment<'a, 'buf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for part in &mut self.iter {
+            if let Body::Multipart(_) = part.body {
+                continue;
+            }
+            let disposition = part.content_disposition();
+            let filename = disposition
+                .as_ref()
+                .and_then(|disposition| disposition.filename().map(|filename| filename.to_vec()))
+                .or_else(|| part.content_type().name());
+            let is_attachment = match disposition {
+                Some(ref disposition) if *disposition.kind() == DispositionKind::Attachment => true,
+                _ => {
+                    let content_type = part.content_type();
+                    filename.is_some()
+                        && content_type.mime_type() != b"text"
+                        && !content_type.is_multipart()
+                        && !is_message(content_type)
+                }
+            };
+            if is_attachment {
+                return Some(Attachment {
+                    part,
+                    disposition,
+                    filename,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Return the content type given by the `Content-Type` field, or the default type if the field is
+/// missing or invalid.
+pub fn find_content_type(fields: &[Field], default_type: ContentType) -> ContentType {
+    fields
+        .iter()
+        .find(|field| field.is(b"Content-Type"))
+        .and_then(|field| ContentType::parse(&Buffer::new(&field.unfolded_value())).ok())
+        .map(|(content_type, _)| content_type)
+        .unwrap_or(default_type)
+}
+
+/// Return the transfer encoding given by the `Content-Transfer-Encoding` field: `7bit` if the
+/// field is missing, and `binary` if it is invalid or unknown.
+pub fn find_transfer_encoding(fields: &[Field]) -> TransferEncoding {
+    match fields.iter().find(|field| field.is(b"Content-Transfer-Encoding")) {
+        Some(field) => match TransferEncoding::parse(&Buffer::new(&field.unfolded_value())) {
+            Ok((Some(encoding), _)) => encoding,
+            _ => TransferEncoding::Binary,
+        },
+        None => TransferEncoding::SevenBit,
+    }
+}
+
+/// Return the default type of the parts of a multipart: `message/rfc822` for a
+/// `multipart/digest`, and `text/plain` otherwise.
+pub fn default_part_type(content_type: &ContentType) -> ContentType {
+    if content_type.subtype() == b"digest" {
+        ContentType::new(b"message", b"rfc822")
+    } else {
+        ContentType::default()
+    }
+}
+
+/// Return `true` if the content type is one of the types of embedded messages.
+pub fn is_message(content_type: &ContentType) -> bool {
+    content_type.mime_type() == b"message"
+        && (content_type.subtype() == b"rfc822" || content_type.subtype() == b"global")
+}
+
+/// Return `true` if the transfer encoding does not change the bytes of the body.
+pub fn is_identity(encoding: TransferEncoding) -> bool {
+    match encoding {
+        TransferEncoding::SevenBit | TransferEncoding::EightBit | TransferEncoding::Binary => true,
+        TransferEncoding::QuotedPrintable | TransferEncoding::Base64 => false,
+    }
+}
+
+/// Depth-first iterator over a MIME tree. See [`MimePart::iter`].
+///
+/// [`MimePart::iter`]: struct.MimePart.html#method.iter
+pub struct Iter<'a, 'buf: 'a> {
+    stack: Vec<&'a MimePart<'buf>>,
+}
+
+impl<'a, 'buf> Iterator for Iter<'a, 'buf> {
+    type Item = &'a MimePart<'buf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let part = self.stack.pop()?;
+        match part.body {
+            Body::Multipart(ref parts) => self.stack.extend(parts.iter().rev()),
+            Body::Message(ref message) => self.stack.push(message),
+            Body::Leaf(_) => {}
+        }
+        Some(part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    static MESSAGE: &[u8] = b"From: me\r\n\
+        Content-Type: multipart/mixed; boundary=outer\r\n\
+        \r\n\
+        preamble\r\n\
+        --outer\r\n\
+        \r\n\
+        implicitly typed\r\n\
+        --outer\r\n\
+        Content-Type: message/rfc822\r\n\
+        \r\n\
+        Subject: embedded\r\n\
+        Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+        \r\n\
+        --inner\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        plain\r\n\
+        --inner\r\n\
+        Content-Type: text/html\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        PHA+aHRtbDwvcD4=\r\n\
+        --inner--\r\n\
+        --outer\r\n\
+        Content-Type: multipart/digest; boundary=digest\r\n\
+        \r\n\
+        --digest\r\n\
+        \r\n\
+        Subject: digest\r\n\
+        \r\n\
+        digested\r\n\
+        --digest--\r\n\
+        --outer--\r\n";
+
+    fn types(part: &MimePart) -> Vec<String> {
+        part.iter()
+            .map(|part| {
+                let content_type = part.content_type();
+                format!(
+                    "{}/{}",
+                    String::from_utf8_lossy(content_type.mime_type()),
+                    String::from_utf8_lossy(content_type.subtype())
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_tree() {
+        let message = MimePart::parse(b!(MESSAGE)).unwrap();
+        assert_eq!(message.raw(), MESSAGE);
+        assert_eq!(message.fields().len(), 2);
+        assert_eq!(message.children().len(), 3);
+        assert_eq!(types(&message), vec![
+            "multipart/mixed",
+            "text/plain",
+            "message/rfc822",
+            "multipart/alternative",
+            "text/plain",
+            "text/html",
+            "multipart/digest",
+            "message/rfc822",
+            "text/plain",
+        ]);
+
+        let html = &message.iter().nth(5).unwrap();
+        assert_eq!(html.transfer_encoding(), TransferEncoding::Base64);
+        assert_eq!(html.body(), &Body::Leaf(&b"PHA+aHRtbDwvcD4="[..]));
+        let mut decoded = Vec::new();
+        html.decode_body(&mut decoded).unwrap();
+        assert_eq!(decoded, b"<p>html</p>");
+    }
+
+    #[test]
+    fn test_part_numbers() {
+        let message = MimePart::parse(b!(MESSAGE)).unwrap();
+        let body = |number: &str| match *message.part_by_number(number).unwrap().body() {
+            Body::Leaf(body) => body,
+            _ => panic!("{} is not a leaf", number),
+        };
+        assert_eq!(body("1"), b"implicitly typed");
+        assert_eq!(body("2.1"), b"plain");
+        assert_eq!(body("2.2"), b"PHA+aHRtbDwvcD4=");
+        assert_eq!(body("3.1.1"), b"digested");
+        assert_eq!(message.part(&[]), Some(&message));
+        assert_eq!(message.part(&[2]).unwrap().content_type().subtype(), b"rfc822");
+        assert_eq!(message.part(&[3, 1]).unwrap().content_type().subtype(), b"rfc822");
+        assert!(message.part(&[0]).is_none());
+        assert!(message.part(&[4]).is_none());
+        assert!(message.part(&[1, 1]).is_none());
+        assert!(message.part(&[2, 3]).is_none());
+        assert!(message.part_by_number("1.a").is_none());
+
+        // the body of a message that is not multipart is part 1
+        let message = MimePart::parse(b!(b"Subject: simple\r\n\r\nbody")).unwrap();
+        assert_eq!(message.part(&[1]), Some(&message));
+        assert!(message.part(&[2]).is_none());
+        assert!(message.part(&[1, 1]).is_none());
+    }
+
+    #[test]
+    fn test_lenient_parsing() {
+        let message = MimePart::parse(b!(b"Content-Type: multipart/mixed; boundary=b\r\n\
+            Content-Transfer-Encoding: x-unknown\r\n\
+            \r\n\
+            no delimiter")).unwrap();
+        assert_eq!(message.body(), &Body::Leaf(&b"no delimiter"[..]));
+        assert_eq!(message.transfer_encoding(), TransferEncoding::Binary);
+
+        let message = MimePart::parse(b!(b"Content-Type: multipart/mixed;\n boundary=b\n\n--b\n\npart\n--b--\n")).unwrap();
+        assert_eq!(message.children().len(), 1);
+
+        let message = MimePart::parse(b!(b"Content-Type: invalid\r\n\r\nbody")).unwrap();
+        assert_eq!(message.content_type(), &ContentType::default());
+        assert_eq!(message.transfer_encoding(), TransferEncoding::SevenBit);
+
+        // a base64 encoded embedded message is not parsed
+        let message = MimePart::parse(b!(b"Content-Type: message/global\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            U3ViamVjdDogaGkNCg0KYm9keQ==")).unwrap();
+        assert_eq!(message.body(), &Body::Leaf(&b"U3ViamVjdDogaGkNCg0KYm9keQ=="[..]));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut input = Vec::new();
+        for _ in 0..5 {
+            input.extend_from_slice(b"Content-Type: message/rfc822\r\n\r\n");
+        }
+        input.extend_from_slice(b"Subject: deep\r\n\r\nbody");
+        assert_eq!(MimePart::parse(b!(&input)).unwrap().iter().count(), 6);
+        assert!(MimePart::parse_with_max_depth(b!(&input), 5).is_ok());
+        let e = MimePart::parse_with_max_depth(b!(&input), 4).unwrap_err();
+        match *e.kind() {
+            ErrorKind::TooDeep { max_depth } => assert_eq!(max_depth, 4),
+            _ => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_attachments() {
+        let input = b"Content-Type: multipart/mixed; boundary=b\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: multipart/related; boundary=r\r\n\
+            \r\n\
+            --r\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <img src=\"cid:logo\">\r\n\
+            --r\r\n\
+            Content-Type: image/png; name=logo.png\r\n\
+            Content-Disposition: inline\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            iVBORw0K\r\n\
+            --r\r\n\
+            Content-Type: image/png\r\n\
+            \r\n\
+            no name\r\n\
+            --r--\r\n\
+            --b\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Disposition: attachment;\r\n\
+            \tfilename*=utf-8''notes%20%C3%A9t%C3%A9.txt;\r\n\
+            \tsize=12\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            notes d'=C3=A9t=C3=A9\r\n\
+            --b\r\n\
+            Content-Type: text/plain; name=inline.txt\r\n\
+            \r\n\
+            inline text\r\n\
+            --b\r\n\
+            Content-Type: message/rfc822\r\n\
+            Content-Disposition: attachment\r\n\
+            \r\n\
+            Subject: forwarded\r\n\
+            \r\n\
+            forwarded body\r\n\
+            --b--\r\n";
+        let message = MimePart::parse(b!(input)).unwrap();
+        let attachments: Vec<Attachment> = message.attachments().collect();
+        assert_eq!(attachments.len(), 3);
+
+        let logo = &attachments[0];
+        assert_eq!(logo.filename(), Some(&b"logo.png"[..]));
+        assert_eq!(logo.content_type().subtype(), b"png");
+        assert_eq!(logo.disposition().unwrap().kind(), &DispositionKind::Inline);
+        let mut content = Vec::new();
+        logo.reader().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"\x89PNG\r\n");
+
+        let notes = &attachments[1];
+        assert_eq!(notes.filename(), Some("notes été.txt".as_bytes()));
+        assert_eq!(notes.disposition().unwrap().size(), Some(12));
+        let mut content = String::new();
+        notes.reader().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "notes d'été");
+
+        let forwarded = &attachments[2];
+        assert_eq!(forwarded.filename(), None);
+        assert_eq!(forwarded.content_type().subtype(), b"rfc822");
+        let mut content = Vec::new();
+        forwarded.reader().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"Subject: forwarded\r\n\r\nforwarded body");
+    }
+}
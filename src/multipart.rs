@@ -0,0 +1,245 @@
+use errors::{Error, ErrorKind};
+use prelude::*;
+use Buffer;
+
+/// The body of a `multipart/*` entity, split into its parts. See [RFC2046 section 5.1.1].
+///
+/// ```no_rust
+/// multipart-body := [preamble CRLF]
+///                   dash-boundary transport-padding CRLF
+///                   body-part *encapsulation
+///                   close-delimiter transport-padding
+///                   [CRLF epilogue]
+/// encapsulation := delimiter transport-padding
+///                  CRLF body-part
+/// delimiter := CRLF dash-boundary
+/// close-delimiter := delimiter "--"
+/// dash-boundary := "--" boundary
+/// transport-padding := *LWSP-char
+/// ```
+///
+/// Each part is a slice of the original buffer, that starts with the part's header section, and
+/// can be parsed as a message. The CRLF that precedes a delimiter is not part of the preceding
+/// part.
+///
+/// [RFC2046 section 5.1.1]: https://tools.ietf.org/html/rfc2046#section-5.1.1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Multipart<'buf> {
+    preamble: &'buf [u8],
+    parts: Vec<&'buf [u8]>,
+    epilogue: &'buf [u8],
+    closed: bool,
+}
+
+impl<'buf> Multipart<'buf> {
+    pub fn preamble(&self) -> &'buf [u8] {
+        self.preamble
+    }
+
+    pub fn parts(&self) -> &[&'buf [u8]] {
+        &self.parts
+    }
+
+    pub fn epilogue(&self) -> &'buf [u8] {
+        self.epilogue
+    }
+
+    /// Return `false` if the close-delimiter was missing, in which case the last part extends to
+    /// the end of the body.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// If the line is a delimiter line for the given boundary, return `Some(true)` for a
+/// close-delimiter and `Some(false)` for a regular delimiter. The line must not contain the line
+/// break. Lines that only start with a delimiter (for instance the delimiter of a nested multipart
+/// which boundary starts with the same characters) do not match.
+pub fn match_delimiter(line: &[u8], boundary: &[u8]) -> Option<bool> {
+    if boundary.is_empty() || !line.starts_with(b"--") || !line[2..].starts_with(boundary) {
+        return None;
+    }
+    let mut rest = &line[2 + boundary.len()..];
+    let close = rest.starts_with(b"--");
+    if close {
+        rest = &rest[2..];
+    }
+    // transport-padding
+    if rest.iter().all(|c| is_wsp(*c)) {
+        Some(close)
+    } else {
+        None
+    }
+}
+
+/// Return the index where the line break that precedes the line starting at `start` begins.
+fn line_break_start(bytes: &[u8], start: usize) -> usize {
+    let mut end = start;
+    if end > 0 && bytes[end - 1] == b'\n' {
+        end -= 1;
+        if end > 0 && bytes[end - 1] == b'\r' {
+            end -= 1;
+        }
+    }
+    end
+}
+
+/// Split the body of a `multipart/*` entity into its parts, using the given boundary (see
+/// [`ContentType::boundary`]). See [RFC2046 section 5.1.1].
+///
+/// Lines can end with CRLF or with a lone LF. Since mail in the wild is often truncated, a
+/// missing close-delimiter is tolerated: the last part then extends to the end of the body (see
+/// [`Multipart::is_closed`]). If the body does not contain any delimiter, `ErrorKind::Eof` is
+/// returned.
+///
+/// [RFC2046 section 5.1.1]: https://tools.ietf.org/html/rfc2046#section-5.1.1
+/// [`ContentType::boundary`]: ../mime/struct.ContentType.html#method.boundary
+/// [`Multipart::is_closed`]: struct.Multipart.html#method.is_closed
+pub fn split_multipart<'buf>(input: &Buffer<'buf>, boundary: &[u8]) -> Result<Multipart<'buf>, Error> {
+    let bytes = input.remaining();
+    let mut preamble: Option<&'buf [u8]> = None;
+    let mut parts = Vec::new();
+    let mut part_start: usize = 0;
+
+    let mut line_start: usize = 0;
+    while line_start < bytes.len() {
+        let (mut line_end, next_line) = match bytes[line_start..].iter().position(|c| *c == b'\n') {
+            Some(i) => (line_start + i, line_start + i + 1),
+            None => (bytes.len(), bytes.len()),
+        };
+        if line_end > line_start && bytes[line_end - 1] == b'\r' {
+            line_end -= 1;
+        }
+
+        if let Some(close) = match_delimiter(&bytes[line_start..line_end], boundary) {
+            // the line break that precedes a delimiter is part of the delimiter
+            let end = line_break_start(bytes, line_start);
+            if preamble.is_none() {
+                preamble = Some(&bytes[..end]);
+            } else {
+                parts.push(&bytes[part_start..end]);
+            }
+            if close {
+                return Ok(Multipart {
+                    preamble: preamble.unwrap(),
+                    parts,
+                    epilogue: &bytes[next_line..],
+                    closed: true,
+                });
+            }
+            part_start = next_line;
+        }
+        line_start = next_line;
+    }
+
+    match preamble {
+        Some(preamble) => {
+            parts.push(&bytes[part_start..]);
+            Ok(Multipart {
+                preamble,
+                parts,
+                epilogue: &bytes[bytes.len()..],
+                closed: false,
+            })
+        }
+        None => Err(ErrorKind::Eof.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split<'a>(input: &'a [u8], boundary: &[u8]) -> Multipart<'a> {
+        split_multipart(&Buffer::new(input), boundary).unwrap()
+    }
+
+    #[test]
+    fn test_split_multipart() {
+        let body = b"This is the preamble.\r\n\
+            --simple boundary\r\n\
+            \r\n\
+            This is implicitly typed plain US-ASCII text.\r\n\
+            --simple boundary\r\n\
+            Content-type: text/plain; charset=us-ascii\r\n\
+            \r\n\
+            This is explicitly typed plain US-ASCII text.\r\n\
+            \r\n\
+            --simple boundary--\r\n\
+            This is the epilogue.\r\n";
+        let multipart = split(body, b"simple boundary");
+        assert_eq!(multipart.preamble(), b"This is the preamble.");
+        assert_eq!(multipart.parts(), &[
+            &b"\r\nThis is implicitly typed plain US-ASCII text."[..],
+            &b"Content-type: text/plain; charset=us-ascii\r\n\r\nThis is explicitly typed plain US-ASCII text.\r\n"[..],
+        ]);
+        assert_eq!(multipart.epilogue(), b"This is the epilogue.\r\n");
+        assert!(multipart.is_closed());
+    }
+
+    #[test]
+    fn test_split_multipart_no_preamble() {
+        let multipart = split(b"--b\r\n\r\npart 1\r\n--b\r\n\r\npart 2\r\n--b--", b"b");
+        assert_eq!(multipart.preamble(), b"");
+        assert_eq!(multipart.parts(), &[&b"\r\npart 1"[..], &b"\r\npart 2"[..]]);
+        assert_eq!(multipart.epilogue(), b"");
+        assert!(multipart.is_closed());
+    }
+
+    #[test]
+    fn test_split_multipart_lf() {
+        let multipart = split(b"preamble\n--b\n\npart 1\n--b\n\npart 2\n--b--\nepilogue\n", b"b");
+        assert_eq!(multipart.preamble(), b"preamble");
+        assert_eq!(multipart.parts(), &[&b"\npart 1"[..], &b"\npart 2"[..]]);
+        assert_eq!(multipart.epilogue(), b"epilogue\n");
+    }
+
+    #[test]
+    fn test_split_multipart_transport_padding() {
+        let multipart = split(b"--b \t\r\n\r\npart 1\r\n--b-- \r\nepilogue", b"b");
+        assert_eq!(multipart.parts(), &[&b"\r\npart 1"[..]]);
+        assert_eq!(multipart.epilogue(), b"epilogue");
+    }
+
+    #[test]
+    fn test_split_multipart_boundary_prefix() {
+        let body = b"--b\r\n\
+            Content-Type: multipart/mixed; boundary=b2\r\n\
+            \r\n\
+            --b2\r\n\
+            \r\n\
+            nested\r\n\
+            --b2--\r\n\
+            --bb\r\n\
+            --b--x\r\n\
+            --b--";
+        let multipart = split(body, b"b");
+        assert_eq!(multipart.parts().len(), 1);
+        assert_eq!(
+            multipart.parts()[0],
+            &b"Content-Type: multipart/mixed; boundary=b2\r\n\r\n--b2\r\n\r\nnested\r\n--b2--\r\n--bb\r\n--b--x"[..]
+        );
+        let nested = split(multipart.parts()[0], b"b2");
+        assert_eq!(nested.parts(), &[&b"\r\nnested"[..]]);
+        assert_eq!(nested.epilogue(), b"--bb\r\n--b--x");
+    }
+
+    #[test]
+    fn test_split_multipart_unclosed() {
+        let multipart = split(b"--b\r\n\r\npart 1\r\n--b\r\n\r\npart 2\r\n", b"b");
+        assert_eq!(multipart.parts(), &[&b"\r\npart 1"[..], &b"\r\npart 2\r\n"[..]]);
+        assert_eq!(multipart.epilogue(), b"");
+        assert!(!multipart.is_closed());
+
+        let multipart = split(b"--b", b"b");
+        assert_eq!(multipart.parts(), &[&b""[..]]);
+        assert!(!multipart.is_closed());
+
+        assert!(split_multipart(b!(b"no delimiter\r\n--c\r\n"), b"b").unwrap_err().is_eof());
+        assert!(split_multipart(b!(b"--\r\n"), b"").unwrap_err().is_eof());
+    }
+}
@@ -0,0 +1,273 @@
+use core::char;
+
+use errors::{Error, ErrorKind, Token};
+use prelude::*;
+use sink::Sink;
+use Buffer;
+
+// Parameters of the bootstring encoding used by Punycode. See [RFC3492 section 5].
+//
+// [RFC3492 section 5]: https://tools.ietf.org/html/rfc3492#section-5
+static BASE: u32 = 36;
+static TMIN: u32 = 1;
+static TMAX: u32 = 26;
+static SKEW: u32 = 38;
+static DAMP: u32 = 700;
+static INITIAL_BIAS: u32 = 72;
+static INITIAL_N: u32 = 128;
+
+/// Separator between the basic code points and the encoded non-basic code points
+pub static DELIMITER: u8 = b'-';
+
+/// Return the threshold of the digit at position `k`. See [RFC3492 section 6.2].
+///
+/// [RFC3492 section 6.2]: https://tools.ietf.org/html/rfc3492#section-6.2
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        TMIN
+    } else if k >= bias + TMAX {
+        TMAX
+    } else {
+        k - bias
+    }
+}
+
+/// Adapt the bias after a delta has been encoded or decoded. See [RFC3492 section 6.1].
+///
+/// [RFC3492 section 6.1]: https://tools.ietf.org/html/rfc3492#section-6.1
+fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+fn encode_digit(digit: u32) -> u8 {
+    if digit < 26 {
+        b'a' + digit as u8
+    } else {
+        b'0' + (digit - 26) as u8
+    }
+}
+
+/// Return the value of a digit, which is case insensitive, or `None` if the byte is not a digit.
+fn decode_digit(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'a'..=b'z' => Some((c - b'a') as u32),
+        b'0'..=b'9' => Some((c - b'0') as u32 + 26),
+        _ => None,
+    }
+}
+
+/// Encode the given text in Punycode, and write it into the provided writer. The ASCII
+/// characters are written first, followed by a [`DELIMITER`] if there are any, and by the
+/// encoding of the other characters. An `Encoding` error is returned if the text is too long for
+/// the positions of its characters to be encoded. See [RFC3492 section 6.3].
+///
+/// ```rust
+/// use imf::punycode::encode_punycode;
+///
+/// let mut output = Vec::new();
+/// encode_punycode("bücher", &mut output).unwrap();
+/// assert_eq!(output, b"bcher-kva");
+/// ```
+///
+/// [`DELIMITER`]: static.DELIMITER.html
+/// [RFC3492 section 6.3]: https://tools.ietf.org/html/rfc3492#section-6.3
+pub fn encode_punycode<W: Sink>(input: &str, writer: &mut W) -> Result<(), Error> {
+    let overflow = || -> Error {
+        ErrorKind::Encoding {
+            token: Token::Label,
+            position: 0,
+        }.into()
+    };
+    let code_points: Vec<u32> = input.chars().map(|c| c as u32).collect();
+    let basic: Vec<u8> = input.bytes().filter(u8::is_ascii).collect();
+    writer.write_bytes(&basic)?;
+    if !basic.is_empty() {
+        writer.write_bytes(&[DELIMITER])?;
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    // the number of code points already encoded
+    let mut h = basic.len() as u32;
+    while (h as usize) < code_points.len() {
+        // the smallest code point that is not encoded yet
+        let m = code_points.iter().cloned().filter(|c| *c >= n).min().unwrap_or(n);
+        delta = (m - n)
+            .checked_mul(h + 1)
+            .and_then(|d| delta.checked_add(d))
+            .ok_or_else(overflow)?;
+        n = m;
+        for &c in &code_points {
+            if c < n {
+                delta = delta.checked_add(1).ok_or_else(overflow)?;
+            } else if c == n {
+                // write delta as a variable-length integer
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    writer.write_bytes(&[encode_digit(t + (q - t) % (BASE - t))])?;
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                writer.write_bytes(&[encode_digit(q)])?;
+                bias = adapt(delta, h + 1, h == basic.len() as u32);
+                delta = 0;
+                h += 1;
+            }
+        }
+        delta = delta.checked_add(1).ok_or_else(overflow)?;
+        n += 1;
+    }
+    Ok(())
+}
+
+/// Decode Punycode encoded text, and write it into the provided writer as UTF-8. The whole input
+/// is consumed, and the number of bytes read is returned. Digits are case insensitive, and the
+/// ASCII characters before the last [`DELIMITER`] are written as is. An invalid digit, a
+/// truncated or too large integer, or a code point that is not a character (such as a surrogate),
+/// result in an `Encoding` error. See [RFC3492 section 6.2].
+///
+/// ```rust
+/// use imf::punycode::decode_punycode;
+/// use imf::Buffer;
+///
+/// let mut output = Vec::new();
+/// decode_punycode(&Buffer::new(b"bcher-kva"), &mut output).unwrap();
+/// assert_eq!(output, "bücher".as_bytes());
+/// ```
+///
+/// [`DELIMITER`]: static.DELIMITER.html
+/// [RFC3492 section 6.2]: https://tools.ietf.org/html/rfc3492#section-6.2
+pub fn decode_punycode<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let pos = input.position();
+    let error = |i: usize| -> Error {
+        ErrorKind::Encoding {
+            token: Token::Label,
+            position: pos + i,
+        }.into()
+    };
+
+    // copy the basic code points, which come before the last delimiter
+    let mut output: Vec<char> = Vec::new();
+    let mut j = match bytes.iter().rposition(|c| *c == DELIMITER) {
+        Some(end) => {
+            if let Some(i) = bytes[..end].iter().position(|c| !c.is_ascii()) {
+                return Err(error(i));
+            }
+            output.extend(bytes[..end].iter().map(|c| *c as char));
+            end + 1
+        }
+        None => 0,
+    };
+
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    while j < bytes.len() {
+        // read a variable-length integer into delta
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = match bytes.get(j) {
+                Some(c) => decode_digit(*c).ok_or_else(|| error(j))?,
+                None => return Err(error(j)),
+            };
+            i = digit.checked_mul(w).and_then(|d| i.checked_add(d)).ok_or_else(|| error(j))?;
+            let t = threshold(k, bias);
+            j += 1;
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t).ok_or_else(|| error(j - 1))?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len).ok_or_else(|| error(j - 1))?;
+        i %= len;
+        match char::from_u32(n) {
+            Some(c) => output.insert(i as usize, c),
+            None => return Err(error(j - 1)),
+        }
+        i += 1;
+    }
+
+    let mut character = [0; 4];
+    for c in output {
+        writer.write_bytes(c.encode_utf8(&mut character).as_bytes())?;
+    }
+    Ok(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(input: &str) -> Vec<u8> {
+        let mut output = Vec::new();
+        encode_punycode(input, &mut output).unwrap();
+        output
+    }
+
+    fn decode(input: &Buffer) -> Result<(String, usize), Error> {
+        let mut output = Vec::new();
+        let len = decode_punycode(input, &mut output)?;
+        Ok((String::from_utf8(output).unwrap(), len))
+    }
+
+    #[test]
+    fn test_encode_punycode() {
+        assert_eq!(encode(""), b"");
+        assert_eq!(encode("abc"), b"abc-");
+        assert_eq!(encode("ü"), b"tda");
+        assert_eq!(encode("bücher"), b"bcher-kva");
+        assert_eq!(encode("München-Ost"), b"Mnchen-Ost-9db");
+        assert_eq!(encode("例子"), b"fsqu00a");
+        assert_eq!(encode("правительство"), b"80aealotwbjpid2k");
+        // RFC3492 section 7.1 (A)
+        assert_eq!(encode("ليهمابتكلموشعربي؟"), b"egbpdaj6bu4bxfgehfvwxn");
+    }
+
+    #[test]
+    fn test_decode_punycode() {
+        assert_eq!(decode(b!(b"bcher-kva")).unwrap(), ("bücher".to_string(), 9));
+        assert_eq!(decode(b!(b"Mnchen-Ost-9db")).unwrap().0, "München-Ost");
+        assert_eq!(decode(b!(b"FSQU00A")).unwrap().0, "例子");
+        assert_eq!(decode(b!(b"abc-")).unwrap().0, "abc");
+        assert_eq!(decode(b!(b"egbpdaj6bu4bxfgehfvwxn")).unwrap().0, "ليهمابتكلموشعربي؟");
+        for word in &["", "ü", "日本語", "-> $1.00 <-", "ÄÖÜ"] {
+            let encoded = encode(word);
+            assert_eq!(decode(b!(encoded)).unwrap().0, *word);
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid_punycode() {
+        let position = |input: &[u8]| decode(b!(input)).unwrap_err().position();
+        // invalid digit
+        assert_eq!(position(b"bcher-k!a"), Some(7));
+        // truncated integer
+        assert_eq!(position(b"bcher-kv"), Some(8));
+        // non-ASCII before the delimiter
+        assert_eq!(position("bü-kva".as_bytes()), Some(1));
+        // surrogate
+        assert_eq!(position(b"bb0c"), Some(3));
+        // overflow
+        assert_eq!(position(b"99999999999"), Some(7));
+    }
+}
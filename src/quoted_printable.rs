@@ -0,0 +1,562 @@
+use core::mem;
+#[cfg(feature = "std")]
+use std::io;
+
+use errors::Error;
+use prelude::*;
+use sink::Sink;
+use whitespaces::CRLF;
+use Buffer;
+
+/// Maximum length of an encoded line, line break excluded. See [RFC2045 section 6.7].
+///
+/// [RFC2045 section 6.7]: https://tools.ietf.org/html/rfc2045#section-6.7
+pub static MAX_LINE_LENGTH: usize = 76;
+
+static HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Number of bytes that are kept in the encoder until more data is written, to decide how the
+/// start of a line should be encoded (see `From `).
+static LOOKAHEAD: usize = 5;
+
+/// Return the value of an hexadecimal digit, or `None` if the byte is not an hexadecimal digit.
+/// [RFC2045 section 6.7] requires upper case digits, but lower case digits are accepted too, as
+/// recommended for robust implementations.
+///
+/// [RFC2045 section 6.7]: https://tools.ietf.org/html/rfc2045#section-6.7
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Regular text. The pending bytes are whitespaces (and possibly a CR) that are dropped if
+    /// they turn out to be at the end of a line.
+    Text,
+    /// We read a `=`. The pending bytes are the `=`, possibly followed by whitespaces and a CR,
+    /// which would make it a soft line break.
+    Equal,
+    /// We read a `=` and an hexadecimal digit.
+    Hex(u8),
+}
+
+/// A streaming quoted-printable decoder. Encoded bytes written into the decoder are decoded and
+/// written into the underlying writer. See [RFC2045 section 6.7].
+///
+/// ```no_rust
+/// quoted-printable := qp-line *(CRLF qp-line)
+/// qp-line := *(qp-segment transport-padding CRLF)
+///            qp-part transport-padding
+/// qp-part := qp-section
+/// qp-segment := qp-section *(SPACE / TAB) "="
+/// qp-section := [*(ptext / SPACE / TAB) ptext]
+/// ptext := hex-octet / safe-char
+/// hex-octet := "=" 2(DIGIT / "A" / "B" / "C" / "D" / "E" / "F")
+/// transport-padding := *LWSP-char
+/// ```
+///
+/// Soft line breaks (`=` at the end of a line) are removed, and so are the whitespaces at the end
+/// of a line, since they may have been added by a transport. The decoder is lenient: a `=` that
+/// is not followed by two hexadecimal digits or a line break is kept as is, and so is a lone LF.
+/// At most [`MAX_LINE_LENGTH`] whitespaces are held back: a longer run is written as is, since it
+/// cannot be transport padding.
+///
+/// [`finish`](#method.finish) must be called once all the data has been written.
+///
+/// [RFC2045 section 6.7]: https://tools.ietf.org/html/rfc2045#section-6.7
+/// [`MAX_LINE_LENGTH`]: static.MAX_LINE_LENGTH.html
+pub struct QuotedPrintableDecoder<W: Sink> {
+    writer: W,
+    state: State,
+    pending: Vec<u8>,
+}
+
+impl<W: Sink> QuotedPrintableDecoder<W> {
+    pub fn new(writer: W) -> Self {
+        QuotedPrintableDecoder {
+            writer,
+            state: State::Text,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Flush the bytes that are still pending, and return the underlying writer. Trailing
+    /// whitespaces and a trailing soft line break are dropped, while an incomplete escape sequence
+    /// is written as is.
+    pub fn finish(mut self) -> Result<W, Error> {
+        match self.state {
+            State::Text => {
+                while self.pending.last().is_some_and(|c| is_wsp(*c)) {
+                    self.pending.pop();
+                }
+                self.flush_pending()?;
+            }
+            State::Equal => {
+                if self.pending.iter().skip(1).any(|c| *c == b'\r') {
+                    self.flush_pending()?;
+                }
+            }
+            State::Hex(_) => self.flush_pending()?,
+        }
+        Ok(self.writer)
+    }
+
+    fn flush_pending(&mut self) -> Result<(), Error> {
+        self.writer.write_bytes(&self.pending)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn decode(&mut self, buf: &[u8]) -> Result<(), Error> {
+        for &c in buf {
+            self.decode_byte(c)?;
+        }
+        Ok(())
+    }
+
+    fn decode_byte(&mut self, c: u8) -> Result<(), Error> {
+        match self.state {
+            State::Text => match c {
+                c if is_wsp(c) || c == b'\r' => {
+                    // a CR that is not followed by a LF is not a line break, and a run of
+                    // whitespaces longer than a line cannot be transport padding
+                    if self.pending.last() == Some(&b'\r') || self.pending.len() >= MAX_LINE_LENGTH {
+                        self.flush_pending()?;
+                    }
+                    self.pending.push(c);
+                }
+                b'\n' => {
+                    // hard line break: drop the trailing whitespaces
+                    if self.pending.last() == Some(&b'\r') {
+                        self.writer.write_bytes(b"\r\n")?;
+                    } else {
+                        self.writer.write_bytes(b"\n")?;
+                    }
+                    self.pending.clear();
+                }
+                b'=' => {
+                    self.flush_pending()?;
+                    self.pending.push(c);
+                    self.state = State::Equal;
+                }
+                _ => {
+                    self.flush_pending()?;
+                    self.writer.write_bytes(&[c])?;
+                }
+            },
+            State::Equal => {
+                let seen_cr = self.pending.last() == Some(&b'\r');
+                match c {
+                    b'\n' => {
+                        // soft line break
+                        self.pending.clear();
+                        self.state = State::Text;
+                    }
+                    b'\r' | b' ' | b'\t' if !seen_cr && self.pending.len() <= MAX_LINE_LENGTH => {
+                        self.pending.push(c)
+                    }
+                    _ => match hex_value(c) {
+                        Some(value) if self.pending.len() == 1 => {
+                            self.pending.push(c);
+                            self.state = State::Hex(value);
+                        }
+                        _ => {
+                            // invalid escape sequence: keep it as is
+                            self.flush_pending()?;
+                            self.state = State::Text;
+                            self.decode_byte(c)?;
+                        }
+                    },
+                }
+            }
+            State::Hex(high) => match hex_value(c) {
+                Some(low) => {
+                    self.writer.write_bytes(&[high << 4 | low])?;
+                    self.pending.clear();
+                    self.state = State::Text;
+                }
+                None => {
+                    // invalid escape sequence: keep it as is
+                    self.flush_pending()?;
+                    self.state = State::Text;
+                    self.decode_byte(c)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Sink> io::Write for QuotedPrintableDecoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.decode(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.writer.flush_sink()?)
+    }
+}
+
+impl<W: Sink> Sink for QuotedPrintableDecoder<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.decode(bytes)
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        self.writer.flush_sink()
+    }
+}
+
+/// Decode quoted-printable encoded content and write the decoded bytes into the provided writer.
+/// The whole input is consumed, and the number of bytes read is returned. See
+/// [`QuotedPrintableDecoder`] for the details about how invalid content is handled.
+///
+/// [`QuotedPrintableDecoder`]: struct.QuotedPrintableDecoder.html
+pub fn decode_quoted_printable<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let mut decoder = QuotedPrintableDecoder::new(writer);
+    decoder.decode(bytes)?;
+    decoder.finish()?;
+    Ok(bytes.len())
+}
+
+/// A streaming quoted-printable encoder. Bytes written into the encoder are encoded and written
+/// into the underlying writer. See [RFC2045 section 6.7].
+///
+/// - line breaks (CRLF, or a lone LF) are treated as hard line breaks and written as CRLF.
+/// - lines longer than [`MAX_LINE_LENGTH`] characters are folded with soft line breaks.
+/// - `=`, non printable characters, and whitespaces at the end of a line are escaped.
+///
+/// Optionally, a `.` or `From ` at the beginning of a line can be escaped, since they are
+/// respectively mangled by some SMTP implementations and mbox writers.
+///
+/// [`finish`](#method.finish) must be called once all the data has been written.
+///
+/// [RFC2045 section 6.7]: https://tools.ietf.org/html/rfc2045#section-6.7
+/// [`MAX_LINE_LENGTH`]: static.MAX_LINE_LENGTH.html
+pub struct QuotedPrintableEncoder<W: Sink> {
+    writer: W,
+    /// current line, not encoded yet
+    line: Vec<u8>,
+    line_length: usize,
+    max_line_length: usize,
+    escape_dot: bool,
+    escape_from: bool,
+}
+
+impl<W: Sink> QuotedPrintableEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        QuotedPrintableEncoder {
+            writer,
+            line: Vec::new(),
+            line_length: 0,
+            max_line_length: MAX_LINE_LENGTH,
+            escape_dot: false,
+            escape_from: false,
+        }
+    }
+
+    /// Set the maximum length of the encoded lines. The length is at least 4, so that a line can
+    /// always contain an escaped byte and a soft line break.
+    pub fn line_length(mut self, length: usize) -> Self {
+        self.max_line_length = if length < 4 { 4 } else { length };
+        self
+    }
+
+    /// Escape the `.` characters found at the beginning of a line.
+    pub fn escape_leading_dot(mut self, escape: bool) -> Self {
+        self.escape_dot = escape;
+        self
+    }
+
+    /// Escape the `F` of the lines starting with `From `.
+    pub fn escape_from(mut self, escape: bool) -> Self {
+        self.escape_from = escape;
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Encode the last line, and return the underlying writer. No line break is added after the
+    /// last line.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let line = mem::take(&mut self.line);
+        self.encode(&line, line.len(), true)?;
+        Ok(self.writer)
+    }
+
+    /// Encode `bytes[..end]`. The remaining bytes are only used as look-ahead. `line_end` must be
+    /// `true` if `bytes` is the end of a line.
+    fn encode(&mut self, bytes: &[u8], end: usize, line_end: bool) -> Result<(), Error> {
+        for i in 0..end {
+            let c = bytes[i];
+            let last = line_end && i + 1 == bytes.len();
+            let mut literal = match c {
+                b' ' | b'\t' => !last,
+                b'=' => false,
+                c => (33..=126).contains(&c),
+            };
+
+            let len = if literal { 1 } else { 3 };
+            let max = if last { self.max_line_length } else { self.max_line_length - 1 };
+            if self.line_length + len > max {
+                self.writer.write_bytes(b"=")?;
+                self.writer.write_bytes(&CRLF)?;
+                self.line_length = 0;
+            }
+
+            if self.line_length == 0 && literal {
+                literal = !(self.escape_dot && c == b'.'
+                    || self.escape_from && bytes[i..].starts_with(b"From "));
+            }
+
+            if literal {
+                self.writer.write_bytes(&[c])?;
+                self.line_length += 1;
+            } else {
+                let escaped = [
+                    b'=',
+                    HEX_DIGITS[(c >> 4) as usize],
+                    HEX_DIGITS[(c & 0x0f) as usize],
+                ];
+                self.writer.write_bytes(&escaped)?;
+                self.line_length += 3;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the complete lines of `buf`, and what can be encoded of the current line.
+    fn encode_lines(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let mut line = mem::take(&mut self.line);
+        for &c in buf {
+            if c == b'\n' {
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                self.encode(&line, line.len(), true)?;
+                self.writer.write_bytes(&CRLF)?;
+                self.line_length = 0;
+                line.clear();
+            } else {
+                line.push(c);
+            }
+        }
+        // Encode what we can of the current line, to keep the memory usage bounded.
+        if line.len() > self.max_line_length + LOOKAHEAD {
+            let end = line.len() - LOOKAHEAD;
+            self.encode(&line, end, false)?;
+            line.drain(..end);
+        }
+        self.line = line;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Sink> io::Write for QuotedPrintableEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encode_lines(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.writer.flush_sink()?)
+    }
+}
+
+impl<W: Sink> Sink for QuotedPrintableEncoder<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.encode_lines(bytes)
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        self.writer.flush_sink()
+    }
+}
+
+/// Encode the given content in quoted-printable, and write the encoded bytes into the provided
+/// writer. The whole input is consumed, and the number of bytes read is returned. See
+/// [`QuotedPrintableEncoder`] for the details about how the content is encoded.
+///
+/// [`QuotedPrintableEncoder`]: struct.QuotedPrintableEncoder.html
+pub fn encode_quoted_printable<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let mut encoder = QuotedPrintableEncoder::new(writer);
+    encoder.encode_lines(bytes)?;
+    encoder.finish()?;
+    Ok(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        assert_eq!(decode_quoted_printable(b!(input), &mut output).unwrap(), input.len());
+        output
+    }
+
+    fn encode(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        assert_eq!(encode_quoted_printable(b!(input), &mut output).unwrap(), input.len());
+        output
+    }
+
+    #[test]
+    fn test_encode_quoted_printable() {
+        assert_eq!(encode(b""), b"");
+        assert_eq!(encode(b"abc"), b"abc");
+        assert_eq!(encode("café".as_bytes()), b"caf=C3=A9");
+        assert_eq!(encode(b"a=b"), b"a=3Db");
+        assert_eq!(encode(b"a\x00b\x7f"), b"a=00b=7F");
+        assert_eq!(encode(b"line 1\r\nline 2\nline 3"), b"line 1\r\nline 2\r\nline 3");
+        assert_eq!(encode(b"a\rb"), b"a=0Db");
+        // trailing whitespaces
+        assert_eq!(encode(b"a \r\nb\t\r\n c "), b"a=20\r\nb=09\r\n c=20");
+    }
+
+    #[test]
+    fn test_encode_line_length() {
+        let input = [b'a'; 200];
+        let output = encode(&input[..]);
+        let lines: Vec<&[u8]> = output.split(|c| *c == b'\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], &[&[b'a'; 75][..], b"=\r"].concat()[..]);
+        assert_eq!(lines[1], &[&[b'a'; 75][..], b"=\r"].concat()[..]);
+        assert_eq!(lines[2], &[b'a'; 50][..]);
+
+        // the last character of a line can use the last column
+        let input = [b'a'; 76];
+        assert_eq!(encode(&input[..]), &input[..]);
+
+        // escaped sequences are not split
+        let input = [&[b'a'; 74][..], b"\xff"].concat();
+        assert_eq!(encode(&input), [&[b'a'; 74][..], b"=\r\n=FF"].concat());
+
+        let mut encoder = QuotedPrintableEncoder::new(Vec::new()).line_length(10);
+        encoder.write_bytes(b"abcdefghijklmnop").unwrap();
+        assert_eq!(encoder.finish().unwrap(), b"abcdefghi=\r\njklmnop");
+    }
+
+    #[test]
+    fn test_encode_escape_line_start() {
+        let input = b".abc\r\nFrom me\r\nFromage\r\n.";
+        assert_eq!(encode(input), &input[..]);
+        let mut encoder = QuotedPrintableEncoder::new(Vec::new())
+            .escape_leading_dot(true)
+            .escape_from(true);
+        encoder.write_bytes(input).unwrap();
+        assert_eq!(
+            encoder.finish().unwrap(),
+            &b"=2Eabc\r\n=46rom me\r\nFromage\r\n=2E"[..]
+        );
+
+        // lines starting after a soft line break are escaped too
+        let input = [&[b'a'; 75][..], b".b"].concat();
+        let mut encoder = QuotedPrintableEncoder::new(Vec::new()).escape_leading_dot(true);
+        encoder.write_bytes(&input).unwrap();
+        assert_eq!(encoder.finish().unwrap(), [&[b'a'; 75][..], b"=\r\n=2Eb"].concat());
+    }
+
+    #[test]
+    fn test_quoted_printable_round_trip() {
+        let input: Vec<u8> = (0..2000).map(|i| (i * 7 % 256) as u8).filter(|c| *c != b'\r' && *c != b'\n').collect();
+        let mut encoder = QuotedPrintableEncoder::new(Vec::new());
+        for chunk in input.chunks(7) {
+            encoder.write_bytes(chunk).unwrap();
+        }
+        let encoded = encoder.finish().unwrap();
+        assert!(encoded.split(|c| *c == b'\n').all(|line| line.len() <= 77));
+        assert_eq!(decode(&encoded), input);
+    }
+
+    #[test]
+    fn test_decode_quoted_printable() {
+        assert_eq!(decode(b""), b"");
+        assert_eq!(decode(b"abc"), b"abc");
+        assert_eq!(decode(b"caf=C3=A9"), "café".as_bytes());
+        assert_eq!(decode(b"caf=c3=a9"), "café".as_bytes());
+        assert_eq!(decode(b"a=3Db"), b"a=b");
+        assert_eq!(decode(b"line 1\r\nline 2\r\n"), b"line 1\r\nline 2\r\n");
+        // trailing whitespaces are removed
+        assert_eq!(decode(b"line 1 \t\r\nline 2  "), b"line 1\r\nline 2");
+        assert_eq!(decode(b"keep=20\r\n"), b"keep \r\n");
+    }
+
+    #[test]
+    fn test_decode_soft_line_breaks() {
+        assert_eq!(decode(b"soft=\r\nbreak"), b"softbreak");
+        assert_eq!(decode(b"soft=\nbreak"), b"softbreak");
+        assert_eq!(decode(b"soft= \t\r\nbreak"), b"softbreak");
+        assert_eq!(decode(b"soft =\r\n break"), b"soft  break");
+        assert_eq!(decode(b"end="), b"end");
+        assert_eq!(decode(b"end=  "), b"end");
+    }
+
+    #[test]
+    fn test_decode_invalid_sequences() {
+        assert_eq!(decode(b"a=b"), b"a=b");
+        assert_eq!(decode(b"a=Gb"), b"a=Gb");
+        assert_eq!(decode(b"a=4"), b"a=4");
+        assert_eq!(decode(b"a=4x"), b"a=4x");
+        assert_eq!(decode(b"a==41"), b"a=A");
+        assert_eq!(decode(b"a= b"), b"a= b");
+        assert_eq!(decode(b"a\rb"), b"a\rb");
+        assert_eq!(decode(b"a=\rb"), b"a=\rb");
+    }
+
+    #[test]
+    fn test_decode_quoted_printable_streaming() {
+        let input = b"caf=C3=A9 =\r\nau lait  \r\nend";
+        let mut decoder = QuotedPrintableDecoder::new(Vec::new());
+        for chunk in input.chunks(1) {
+            decoder.write_bytes(chunk).unwrap();
+        }
+        assert_eq!(decoder.finish().unwrap(), "café au lait\r\nend".as_bytes());
+    }
+
+    #[test]
+    fn test_decode_long_whitespace_run() {
+        // a run longer than a line is not held back
+        let mut decoder = QuotedPrintableDecoder::new(Vec::new());
+        decoder.write_bytes(&[b' '; 1000]).unwrap();
+        assert!(decoder.pending.len() <= MAX_LINE_LENGTH);
+        assert!(decoder.get_ref().len() >= 1000 - MAX_LINE_LENGTH);
+        decoder.write_bytes(b"a").unwrap();
+        assert_eq!(decoder.finish().unwrap(), [&[b' '; 1000][..], b"a"].concat());
+
+        let mut decoder = QuotedPrintableDecoder::new(Vec::new());
+        decoder.write_bytes(b"a=").unwrap();
+        decoder.write_bytes(&[b'\t'; 1000]).unwrap();
+        assert!(decoder.pending.len() <= MAX_LINE_LENGTH + 1);
+        decoder.write_bytes(b"b").unwrap();
+        assert_eq!(decoder.finish().unwrap(), [&b"a="[..], &[b'\t'; 1000][..], b"b"].concat());
+    }
+}
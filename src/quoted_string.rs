@@ -1,7 +1,10 @@
-use std::io::Write;
+use alloc::borrow::Cow;
 use Buffer;
 
-use errors::{ErrorKind, Error, Token};
+use common::utf8_non_ascii_len;
+use cow::parse_cow;
+use errors::{ErrorCategory, ErrorKind, Error, Token};
+use sink::Sink;
 use whitespaces::{skip_cfws, replace_fws};
 
 /// NULL character
@@ -41,7 +44,7 @@ fn is_valid_qtext(c: u8) -> bool {
 ///                     %d127              ;  white space characters
 /// qcontent        =   qtext / quoted-pair
 /// ```
-pub fn parse_qcontent<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+pub fn parse_qcontent<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
     let bytes = input.remaining();
     let pos = input.position();
     if bytes.is_empty() {
@@ -55,9 +58,12 @@ pub fn parse_qcontent<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize,
 
         if is_valid_qtext(c) {
             i += 1;
+        } else if let Some(len) = utf8_non_ascii_len(input, i, Token::QuotedText)? {
+            // qtext =/ UTF8-non-ascii
+            i += len;
         } else if c == b'\\' {
             // write whatever we parsed up to here
-            writer.write_all(&bytes[last_write..i])?;
+            writer.write_bytes(&bytes[last_write..i])?;
             // TODO: this may be optimisable by first checking the positive case, ie:
             //
             // if i + 1 < bytes.len() && bytes[i + 1] <= 127 {
@@ -76,10 +82,15 @@ pub fn parse_qcontent<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize,
             if i + 1 == bytes.len() {
                 // if there nothing after the \ whereas we're expecting an escaped character
                 return Err(ErrorKind::Eof.into());
+            } else if let Some(len) = utf8_non_ascii_len(input, i + 1, Token::QuotedPair)? {
+                // an escaped non-ASCII character, since VCHAR =/ UTF8-non-ascii
+                last_write = i + 1;
+                i += 1 + len;
+                continue;
             } else if bytes[i + 1] > 127 {
                 // this is not a valid escaped character
                 return Err(ErrorKind::Token {
-                    token: Token::QuotedString,
+                    token: Token::QuotedPair,
                     byte: bytes[i + 1],
                     position: pos + i + 1,
                 }.into());
@@ -96,17 +107,26 @@ pub fn parse_qcontent<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize,
 
     if i == 0 {
         // we expect the quoted content to be at least one valid character
-        return Err(ErrorKind::Token {
-            token: Token::QuotedString,
+        let error: Error = ErrorKind::Token {
+            token: Token::QuotedText,
             byte: bytes[0],
             position: pos,
-        }.into());
+        }.into();
+        return Err(error.expecting(&[Token::QuotedText, Token::QuotedPair]));
     }
 
-    writer.write_all(&bytes[last_write..i])?;
+    writer.write_bytes(&bytes[last_write..i])?;
     Ok(i)
 }
 
+/// Parse the content of a quoted string, and return it, borrowed from the input if it contains no
+/// quoted-pair. See [`parse_qcontent`].
+///
+/// [`parse_qcontent`]: fn.parse_qcontent.html
+pub fn parse_qcontent_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_qcontent)
+}
+
 /// Skip quoted content.
 ///
 ///
@@ -139,15 +159,22 @@ pub fn skip_qcontent(input: &Buffer) -> Result<usize, Error> {
         match bytes[i] {
             // read a normal character
             c if is_valid_qtext(c) => i += 1,
+            // qtext =/ UTF8-non-ascii
+            c if c > 127 && input.accepts_utf8() => {
+                i += utf8_non_ascii_len(input, i, Token::QuotedText)?.unwrap_or(1);
+            }
             b'\\' => {
-                // we expect a quoted character between 0 and 127
+                // we expect a quoted character between 0 and 127, or a non-ASCII character if the
+                // buffer accepts UTF-8
                 if i + 1 == bytes.len() {
                     // if there nothing after the \ whereas we're expecting an escaped character
                     return Err(ErrorKind::Eof.into());
+                } else if let Some(len) = utf8_non_ascii_len(input, i + 1, Token::QuotedPair)? {
+                    i += 1 + len;
                 } else if bytes[i + 1] > 127 {
                     // this is not a valid escaped character
                     return Err(ErrorKind::Token {
-                        token: Token::QuotedString,
+                        token: Token::QuotedPair,
                         byte: bytes[i + 1],
                         position: pos + i + 1,
                     }.into());
@@ -156,11 +183,14 @@ pub fn skip_qcontent(input: &Buffer) -> Result<usize, Error> {
                 }
             }
             // we expect the quoted content to be at least one valid character.
-            _ if i == 0 => return Err(ErrorKind::Token {
-                token: Token::QuotedString,
-                byte: bytes[0],
-                position: pos,
-            }.into()),
+            _ if i == 0 => {
+                let error: Error = ErrorKind::Token {
+                    token: Token::QuotedText,
+                    byte: bytes[0],
+                    position: pos,
+                }.into();
+                return Err(error.expecting(&[Token::QuotedText, Token::QuotedPair]));
+            }
             _ => break,
         }
     }
@@ -201,7 +231,7 @@ pub fn skip_qcontent(input: &Buffer) -> Result<usize, Error> {
 /// between the two quote characters. As stated earlier, the "\\" in any quoted-pair and the CRLF in
 /// any FWS/CFWS that appears within the quoted-string are semantically "invisible" and therefore
 /// not part of the quoted-string either.
-pub fn parse_quoted_string<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+pub fn parse_quoted_string<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
 
     let bytes = input.remaining();
     let pos = input.position();
@@ -232,7 +262,7 @@ pub fn parse_quoted_string<W: Write>(input: &Buffer, writer: &mut W) -> Result<u
                 i += len;
                 buffer.set_position(pos + i);
             }
-            Err(e) => if e.is_io() {
+            Err(e) => if e.category() == ErrorCategory::Io {
                 return Err(e);
             }
         }
@@ -242,7 +272,9 @@ pub fn parse_quoted_string<W: Write>(input: &Buffer, writer: &mut W) -> Result<u
                 buffer.set_position(pos + i);
             }
             Err(e) => {
-                if e.is_io() {
+                // the content ends at the first byte that is not qcontent, but an invalid
+                // quoted-pair or malformed UTF-8 is an error
+                if e.category() == ErrorCategory::Io || e.position() != Some(pos + i) || e.category() == ErrorCategory::InvalidEncoding {
                     return Err(e);
                 } else {
                     break;
@@ -273,6 +305,39 @@ pub fn parse_quoted_string<W: Write>(input: &Buffer, writer: &mut W) -> Result<u
     }
 }
 
+/// Parse a quoted string, and return its content, borrowed from the input if it contains no
+/// quoted-pair nor folding. See [`parse_quoted_string`].
+///
+/// [`parse_quoted_string`]: fn.parse_quoted_string.html
+pub fn parse_quoted_string_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, parse_quoted_string)
+}
+
+/// Write the given text as a quoted string, escaping `"` and `\` with a quoted-pair, so that
+/// [`parse_quoted_string`] returns the same text.
+///
+/// CR, LF and NUL cannot be written in a quoted string, not even as quoted-pairs: an
+/// `ErrorKind::Token` error is returned for them, and nothing is written.
+///
+/// [`parse_quoted_string`]: fn.parse_quoted_string.html
+pub fn write_quoted_string<W: Sink>(text: &[u8], writer: &mut W) -> Result<(), Error> {
+    if let Some(i) = text.iter().position(|c| *c == b'\r' || *c == b'\n' || *c == 0) {
+        return Err(ErrorKind::Token {
+            token: Token::QuotedString,
+            byte: text[i],
+            position: i,
+        }.into());
+    }
+    writer.write_bytes(b"\"")?;
+    for &c in text {
+        if c == b'"' || c == b'\\' {
+            writer.write_bytes(b"\\")?;
+        }
+        writer.write_bytes(&[c])?;
+    }
+    writer.write_bytes(b"\"")
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -0,0 +1,324 @@
+use core::fmt;
+use core::str;
+#[cfg(feature = "std")]
+use std::io;
+
+use errors::{Error, ErrorKind};
+use prelude::*;
+use Buffer;
+
+/// An output for the text read by the parsers, such as [`parse_phrase`] or [`unfold_fws`].
+///
+/// `Vec<u8>`, [`Counter`] to only count the bytes, and a mutable reference to a sink are sinks.
+/// Text can be written into a `fmt::Write`, such as a `String`, with a [`FmtSink`], and into an
+/// `io::Write`, such as a `File`, with an [`IoSink`]. The same types are sinks with or without
+/// the `std` feature.
+///
+/// [`parse_phrase`]: ../common/fn.parse_phrase.html
+/// [`unfold_fws`]: ../whitespaces/fn.unfold_fws.html
+/// [`Counter`]: struct.Counter.html
+/// [`FmtSink`]: struct.FmtSink.html
+/// [`IoSink`]: struct.IoSink.html
+pub trait Sink {
+    /// Write all the given bytes.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Write formatted text, to use the `write!` macro.
+    fn write_fmt(&mut self, args: fmt::Arguments) -> Result<(), Error> {
+        let mut adapter = Adapter { sink: self, error: None };
+        match fmt::write(&mut adapter, args) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(adapter.error.unwrap_or_else(|| ErrorKind::Fmt.into())),
+        }
+    }
+
+    /// Flush the bytes written into the sink, if it buffers them.
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A `fmt::Write` writing into a sink, to implement `Sink::write_fmt`.
+struct Adapter<'a, S: Sink + ?Sized + 'a> {
+    sink: &'a mut S,
+    error: Option<Error>,
+}
+
+impl<'a, S: Sink + ?Sized> fmt::Write for Adapter<'a, S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.sink.write_bytes(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+impl Sink for Vec<u8> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        (**self).write_bytes(bytes)
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        (**self).flush_sink()
+    }
+}
+
+/// A sink writing into a `fmt::Write`. Bytes that are not valid UTF-8 result in an
+/// `ErrorKind::Utf8` error, and an error of the underlying writer in an `ErrorKind::Fmt` error.
+///
+/// ```rust
+/// use imf::common::parse_phrase;
+/// use imf::sink::FmtSink;
+/// use imf::Buffer;
+///
+/// let mut sink = FmtSink::new(String::new());
+/// parse_phrase(&Buffer::new(b"John (comment) \"Q.\" Doe <"), &mut sink).unwrap();
+/// assert_eq!(sink.into_inner().unwrap(), "John Q. Doe");
+/// ```
+#[derive(Debug)]
+pub struct FmtSink<W: fmt::Write> {
+    writer: W,
+    /// The beginning of a character whose end has not been written yet
+    pending: Vec<u8>,
+}
+
+impl<W: fmt::Write> FmtSink<W> {
+    pub fn new(writer: W) -> Self {
+        FmtSink {
+            writer,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Return the underlying writer, or an error if the bytes written end in the middle of a
+    /// character.
+    pub fn into_inner(self) -> Result<W, Error> {
+        if self.pending.is_empty() {
+            Ok(self.writer)
+        } else {
+            Err(ErrorKind::Utf8.into())
+        }
+    }
+}
+
+fn write_str<W: fmt::Write>(writer: &mut W, s: &str) -> Result<(), Error> {
+    writer.write_str(s).map_err(|_| ErrorKind::Fmt.into())
+}
+
+impl<W: fmt::Write> Sink for FmtSink<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut bytes = bytes;
+        // complete the pending character first
+        while !self.pending.is_empty() && !bytes.is_empty() {
+            self.pending.push(bytes[0]);
+            bytes = &bytes[1..];
+            match str::from_utf8(&self.pending) {
+                Ok(s) => {
+                    write_str(&mut self.writer, s)?;
+                    self.pending.clear();
+                }
+                Err(e) if e.error_len().is_some() => return Err(ErrorKind::Utf8.into()),
+                Err(_) => {}
+            }
+        }
+        match str::from_utf8(bytes) {
+            Ok(s) => write_str(&mut self.writer, s),
+            Err(e) => match e.error_len() {
+                Some(_) => Err(ErrorKind::Utf8.into()),
+                None => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    write_str(&mut self.writer, str::from_utf8(valid).unwrap_or_default())?;
+                    self.pending.extend_from_slice(rest);
+                    Ok(())
+                }
+            },
+        }
+    }
+}
+
+/// A sink writing into an `io::Write`. Errors of the underlying writer result in an
+/// `ErrorKind::Io` error.
+///
+/// ```rust
+/// use imf::common::parse_phrase;
+/// use imf::sink::IoSink;
+/// use imf::Buffer;
+///
+/// let mut sink = IoSink::new(std::io::Cursor::new(Vec::new()));
+/// parse_phrase(&Buffer::new(b"John (comment) \"Q.\" Doe <"), &mut sink).unwrap();
+/// assert_eq!(sink.into_inner().into_inner(), b"John Q. Doe");
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct IoSink<W: io::Write> {
+    writer: W,
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> IoSink<W> {
+    pub fn new(writer: W) -> Self {
+        IoSink { writer }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> Sink for IoSink<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A sink that discards the bytes written into it, and counts them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counter {
+    count: usize,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Counter::default()
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sink for Counter {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.count += bytes.len();
+        Ok(())
+    }
+}
+
+/// Run a parser that writes into a sink, and return what it wrote as a `String`, along with the
+/// number of bytes read. An `ErrorKind::Utf8` error is returned if the text is not valid UTF-8.
+pub fn parse_to_string<'buf, F>(input: &Buffer<'buf>, parse: F) -> Result<(String, usize), Error>
+where
+    F: FnOnce(&Buffer<'buf>, &mut FmtSink<String>) -> Result<usize, Error>,
+{
+    let mut sink = FmtSink::new(String::new());
+    let len = parse(input, &mut sink)?;
+    Ok((sink.into_inner()?, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::parse_phrase;
+    use errors::ErrorCategory;
+    use quoted_string::parse_qcontent;
+    use whitespaces::unfold_fws;
+
+    #[test]
+    fn test_fmt_sink() {
+        let mut sink = FmtSink::new(String::new());
+        sink.write_bytes(b"caf\xc3").unwrap();
+        assert_eq!(sink.get_ref(), "caf");
+        sink.write_bytes(b"\xa9 \xe2\x82").unwrap();
+        sink.write_bytes(b"\xac").unwrap();
+        assert_eq!(sink.into_inner().unwrap(), "café €");
+
+        let mut sink = FmtSink::new(String::new());
+        assert_eq!(sink.write_bytes(b"a\xff").unwrap_err().category(), ErrorCategory::InvalidEncoding);
+        let mut sink = FmtSink::new(String::new());
+        sink.write_bytes(b"\xc3").unwrap();
+        assert_eq!(sink.write_bytes(b"a").unwrap_err().category(), ErrorCategory::InvalidEncoding);
+        let mut sink = FmtSink::new(String::new());
+        sink.write_bytes(b"\xc3").unwrap();
+        assert_eq!(sink.into_inner().unwrap_err().category(), ErrorCategory::InvalidEncoding);
+    }
+
+    #[test]
+    fn test_sinks() {
+        let (text, len) = parse_to_string(b!(b"a\\\"b\" c"), parse_qcontent).unwrap();
+        assert_eq!((text, len), ("a\"b".to_string(), 4));
+        let (text, _) = parse_to_string(b!(b" \r\n\tx"), unfold_fws).unwrap();
+        assert_eq!(text, " \t");
+
+        let mut counter = Counter::new();
+        assert_eq!(parse_phrase(b!(b"John  Doe <"), &mut counter).unwrap(), 10);
+        assert_eq!(counter.count(), 8);
+        let mut output = Vec::new();
+        parse_phrase(b!(b"John  Doe <"), &mut output).unwrap();
+        assert_eq!(output, b"John Doe");
+
+        // a reference to any sink is a sink, with or without the `std` feature
+        let mut sink = FmtSink::new(String::new());
+        parse_phrase(b!(b"John  Doe <"), &mut &mut sink).unwrap();
+        assert_eq!(sink.into_inner().unwrap(), "John Doe");
+    }
+
+    struct Failing;
+
+    impl fmt::Write for Failing {
+        fn write_str(&mut self, _: &str) -> fmt::Result {
+            Err(fmt::Error)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl io::Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::Other.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_errors() {
+        // both are in the I/O category, but only the errors of an `io::Write` are I/O errors
+        let e = FmtSink::new(Failing).write_bytes(b"a").unwrap_err();
+        assert_eq!(e.category(), ErrorCategory::Io);
+        assert!(!e.is_io());
+        #[cfg(feature = "std")]
+        {
+            let e = IoSink::new(Failing).write_bytes(b"a").unwrap_err();
+            assert_eq!(e.category(), ErrorCategory::Io);
+            assert!(e.is_io());
+        }
+    }
+}
@@ -0,0 +1,997 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use futures_core::Stream;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, ReadBuf};
+
+use errors::{Error, ErrorKind};
+use folding::MAX_LINE_LENGTH;
+use message::{parse_field, parse_header_section, Field};
+use mime::ContentType;
+use mime_part::{default_part_type, find_content_type, find_transfer_encoding, is_identity, is_message, DEFAULT_MAX_DEPTH};
+use multipart::match_delimiter;
+use transfer_encoding::{Decoder, TransferEncoding};
+use Buffer;
+
+/// Default maximum size of a header section. See [`MessageReader::with_limits`].
+///
+/// [`MessageReader::with_limits`]: struct.MessageReader.html#method.with_limits
+pub static DEFAULT_MAX_HEADER_SIZE: usize = 256 * 1024;
+
+/// Size of the chunks read from the underlying reader.
+const CHUNK_SIZE: usize = 8192;
+
+/// The header section of a message or of a MIME part, read by a [`MessageReader`].
+///
+/// [`MessageReader`]: struct.MessageReader.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartHeader {
+    raw: Vec<u8>,
+    depth: usize,
+    content_type: ContentType,
+    transfer_encoding: TransferEncoding,
+}
+
+impl PartHeader {
+    fn parse(raw: Vec<u8>, depth: usize, default_type: ContentType) -> Result<Self, Error> {
+        let (content_type, transfer_encoding) = {
+            let (fields, _) = parse_header_section(&Buffer::new(&raw))?;
+            (find_content_type(&fields, default_type), find_transfer_encoding(&fields))
+        };
+        Ok(PartHeader {
+            raw,
+            depth,
+            content_type,
+            transfer_encoding,
+        })
+    }
+
+    /// Return the bytes of the header section, including the empty line that terminates it.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn fields(&self) -> Vec<Field<'_>> {
+        // the header section was parsed when it was read
+        parse_header_section(&Buffer::new(&self.raw))
+            .map(|(fields, _)| fields)
+            .unwrap_or_default()
+    }
+
+    /// Return the first field with the given name.
+    pub fn field(&self, name: &[u8]) -> Option<Field<'_>> {
+        self.fields().into_iter().find(|field| field.is(name))
+    }
+
+    /// Return the depth of the part: the message is at depth 0, its parts (or the message it
+    /// embeds) at depth 1, and so on.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn content_type(&self) -> &ContentType {
+        &self.content_type
+    }
+
+    pub fn transfer_encoding(&self) -> TransferEncoding {
+        self.transfer_encoding
+    }
+}
+
+/// An event produced by a [`PushParser`].
+///
+/// [`PushParser`]: struct.PushParser.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A field of the header section being read. The value is raw, as with [`Field::value`].
+    ///
+    /// [`Field::value`]: ../message/struct.Field.html#method.value
+    Field { name: Vec<u8>, value: Vec<u8> },
+    /// The header section of a part has been read. It is followed by the body of the part if it
+    /// is a leaf, by the header section of the embedded message if it is a `message/rfc822`
+    /// part, and by the boundaries of its parts if it is a multipart.
+    HeaderEnd(PartHeader),
+    /// A delimiter of the multipart at the given depth has been read. A close-delimiter ends the
+    /// multipart, while a delimiter is followed by the header section of the next part.
+    Boundary { depth: usize, close: bool },
+    /// Decoded bytes of the body of the current part
+    Body(Vec<u8>),
+}
+
+/// What the parser is reading.
+enum State {
+    /// The header section of a part
+    Header { depth: usize, default_type: ContentType },
+    /// A leaf body
+    Body(Decoder<Vec<u8>>),
+    /// The preamble of the innermost multipart
+    Preamble,
+    /// The epilogue of a multipart, up to the next delimiter of an enclosing multipart
+    Epilogue,
+}
+
+/// A multipart being read.
+struct Frame {
+    boundary: Vec<u8>,
+    depth: usize,
+    default_type: ContentType,
+}
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// Return the line break at the end of a line.
+fn line_break(line: &[u8]) -> &'static [u8] {
+    if line.ends_with(b"\r\n") {
+        b"\r\n"
+    } else if line.ends_with(b"\n") {
+        b"\n"
+    } else {
+        b""
+    }
+}
+
+/// A push parser, for messages received in chunks of arbitrary sizes: the bytes of a message are
+/// fed to the parser as they come, and events are returned as soon as they are complete. Data
+/// that ends in the middle of a field or of a line is not an error: it is kept until the next
+/// chunk is fed to the parser, or until the end of the message.
+///
+/// ```rust
+/// use imf::stream::{Event, PushParser};
+///
+/// let mut parser = PushParser::new();
+/// let mut events = parser.feed(b"Subject: a\r\n b\r\nContent-Transfer-Enc").unwrap();
+/// events.extend(parser.feed(b"oding: base64\r\n\r\nAA").unwrap());
+/// events.extend(parser.feed(b"EC\r\n").unwrap());
+/// events.extend(parser.finish().unwrap());
+///
+/// assert_eq!(events[0], Event::Field { name: b"Subject".to_vec(), value: b" a\r\n b".to_vec() });
+/// assert!(matches!(events[2], Event::HeaderEnd(_)));
+/// let body: Vec<u8> = events[3..]
+///     .iter()
+///     .flat_map(|event| match *event {
+///         Event::Body(ref bytes) => bytes.clone(),
+///         _ => Vec::new(),
+///     })
+///     .collect();
+/// assert_eq!(body, vec![0, 1, 2]);
+/// ```
+///
+/// Only the bytes that may still be part of an incomplete header section or delimiter line are
+/// kept in memory, and the limits are the same as with a [`MessageReader`]. After an error, the
+/// parser cannot be used anymore.
+///
+/// [`MessageReader`]: struct.MessageReader.html
+pub struct PushParser {
+    state: State,
+    /// The multiparts being read, the outermost first
+    frames: Vec<Frame>,
+    /// The bytes given to the parser, and not processed yet
+    buffer: Vec<u8>,
+    /// The header section being read
+    header: Vec<u8>,
+    /// The position in the header section of the field being read
+    field_start: usize,
+    /// The line break at the end of the last line of a body, which is not part of the body if
+    /// the next line is a delimiter
+    pending_line_break: &'static [u8],
+    /// `false` if the buffer starts in the middle of a line
+    line_start: bool,
+    max_header_size: usize,
+    max_depth: usize,
+}
+
+impl Default for PushParser {
+    fn default() -> Self {
+        PushParser::new()
+    }
+}
+
+impl PushParser {
+    pub fn new() -> Self {
+        PushParser::with_limits(DEFAULT_MAX_HEADER_SIZE, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Create a parser that returns `ErrorKind::TooLarge` when a header section is larger than
+    /// `max_header_size` bytes, and `ErrorKind::TooDeep` when parts are nested deeper than
+    /// `max_depth`.
+    pub fn with_limits(max_header_size: usize, max_depth: usize) -> Self {
+        PushParser {
+            state: State::Header {
+                depth: 0,
+                default_type: ContentType::default(),
+            },
+            frames: Vec::new(),
+            buffer: Vec::new(),
+            header: Vec::new(),
+            field_start: 0,
+            pending_line_break: b"",
+            line_start: true,
+            max_header_size,
+            max_depth,
+        }
+    }
+
+    /// Feed the next bytes of the message to the parser, and return the events they complete.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Event>, Error> {
+        let mut events = Vec::new();
+        self.push(chunk, &mut events)?;
+        Ok(events)
+    }
+
+    /// Signal the end of the message, and return the last events.
+    pub fn finish(&mut self) -> Result<Vec<Event>, Error> {
+        let mut events = Vec::new();
+        self.push_end(&mut events)?;
+        Ok(events)
+    }
+
+    /// Process the given bytes, and push the events they complete. The events that precede an
+    /// error are pushed.
+    fn push(&mut self, bytes: &[u8], events: &mut Vec<Event>) -> Result<(), Error> {
+        self.buffer.extend_from_slice(bytes);
+        self.process(false, events)
+    }
+
+    /// Process the bytes that are left at the end of the message, and push the last events.
+    fn push_end(&mut self, events: &mut Vec<Event>) -> Result<(), Error> {
+        self.process(true, events)?;
+        // a header section that is not terminated by an empty line ends with the message
+        while let State::Header { depth, ref default_type } = self.state {
+            let default_type = default_type.clone();
+            self.start_part(depth, default_type, events)?;
+        }
+        if let State::Body(mut decoder) = mem::replace(&mut self.state, State::Epilogue) {
+            decoder.write_all(self.pending_line_break)?;
+            events.push(Event::Body(decoder.finish()?));
+        }
+        self.frames.clear();
+        Ok(())
+    }
+
+    fn process(&mut self, end: bool, events: &mut Vec<Event>) -> Result<(), Error> {
+        let buffer = mem::take(&mut self.buffer);
+        let mut i: usize = 0;
+        while i < buffer.len() {
+            match self.process_line(&buffer[i..], end, events)? {
+                Some(len) => i += len,
+                None => break,
+            }
+        }
+        self.flush_body(events);
+        self.buffer = buffer[i..].to_vec();
+        Ok(())
+    }
+
+    /// Process the line at the beginning of the bytes, or a part of it. The number of bytes
+    /// processed is returned, or `None` if more bytes are needed.
+    fn process_line(&mut self, bytes: &[u8], end: bool, events: &mut Vec<Event>) -> Result<Option<usize>, Error> {
+        let line = match bytes.iter().position(|c| *c == b'\n') {
+            Some(i) => &bytes[..i + 1],
+            None if end => bytes,
+            None => return self.process_partial_line(bytes, events),
+        };
+        let line_break = line_break(line);
+        let content = &line[..line.len() - line_break.len()];
+
+        if let State::Header { depth, ref default_type } = self.state {
+            let default_type = default_type.clone();
+            let delimiter = self.find_delimiter(content).is_some();
+            // a line that does not start with a whitespace ends the previous field
+            if !line.first().is_some_and(|c| is_wsp(*c)) {
+                self.end_field(events)?;
+            }
+            if !delimiter {
+                self.header.extend_from_slice(line);
+                if self.header.len() > self.max_header_size {
+                    return Err(ErrorKind::TooLarge { max_size: self.max_header_size }.into());
+                }
+            }
+            if delimiter || content.is_empty() || content == b"\r" {
+                self.field_start = self.header.len();
+                // a delimiter ends the part: it is processed in the new state
+                self.start_part(depth, default_type, events)?;
+                return Ok(Some(if delimiter { 0 } else { line.len() }));
+            }
+            return Ok(Some(line.len()));
+        }
+
+        if !self.line_start {
+            self.line_start = !line_break.is_empty();
+            self.write_body(line, line_break)?;
+            return Ok(Some(line.len()));
+        }
+        match self.find_delimiter(content) {
+            Some((index, close)) => self.end_part(index, close, events)?,
+            None => self.write_body(line, line_break)?,
+        }
+        Ok(Some(line.len()))
+    }
+
+    /// Process the beginning of a line whose end has not been received yet.
+    fn process_partial_line(&mut self, bytes: &[u8], events: &mut Vec<Event>) -> Result<Option<usize>, Error> {
+        match self.state {
+            State::Header { .. } => {
+                // the first byte of the line is enough to end the previous field
+                if !is_wsp(bytes[0]) {
+                    self.end_field(events)?;
+                }
+                if self.header.len() + bytes.len() > self.max_header_size {
+                    return Err(ErrorKind::TooLarge { max_size: self.max_header_size }.into());
+                }
+                Ok(None)
+            }
+            // a line that may be a delimiter is kept until it is complete, unless it is too long
+            // to be a delimiter
+            _ if !self.frames.is_empty()
+                && self.line_start
+                && b"--".starts_with(&bytes[..bytes.len().min(2)])
+                && bytes.len() <= MAX_LINE_LENGTH =>
+            {
+                Ok(None)
+            }
+            _ => {
+                // a CR may be the beginning of the line break
+                let len = bytes.len() - bytes.ends_with(b"\r") as usize;
+                if len == 0 {
+                    return Ok(None);
+                }
+                self.line_start = false;
+                self.write_body(&bytes[..len], b"")?;
+                Ok(Some(len))
+            }
+        }
+    }
+
+    /// Return the index of the multipart of which the line is a delimiter, and whether it is a
+    /// close-delimiter. The innermost multipart is tried first, and the delimiters of enclosing
+    /// multiparts end the multiparts they contain.
+    fn find_delimiter(&self, line: &[u8]) -> Option<(usize, bool)> {
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, frame)| match_delimiter(line, &frame.boundary).map(|close| (index, close)))
+    }
+
+    /// Write a line of a body, keeping its line break until the next line is known.
+    fn write_body(&mut self, line: &[u8], line_break: &'static [u8]) -> Result<(), Error> {
+        if let State::Body(ref mut decoder) = self.state {
+            decoder.write_all(self.pending_line_break)?;
+            decoder.write_all(&line[..line.len() - line_break.len()])?;
+        }
+        self.pending_line_break = line_break;
+        Ok(())
+    }
+
+    /// Push the bytes decoded so far.
+    fn flush_body(&mut self, events: &mut Vec<Event>) {
+        if let State::Body(ref mut decoder) = self.state {
+            if !decoder.get_ref().is_empty() {
+                events.push(Event::Body(mem::take(decoder.get_mut())));
+            }
+        }
+    }
+
+    /// Push the field being read, once all its lines have been read.
+    fn end_field(&mut self, events: &mut Vec<Event>) -> Result<(), Error> {
+        if self.field_start < self.header.len() {
+            let (field, _) = parse_field(&Buffer::with_offset(&self.header, self.field_start))?;
+            events.push(Event::Field {
+                name: field.name().to_vec(),
+                value: field.value().to_vec(),
+            });
+            self.field_start = self.header.len();
+        }
+        Ok(())
+    }
+
+    /// Start a part, once its header section has been read.
+    fn start_part(&mut self, depth: usize, default_type: ContentType, events: &mut Vec<Event>) -> Result<(), Error> {
+        self.end_field(events)?;
+        if depth > self.max_depth {
+            return Err(ErrorKind::TooDeep { max_depth: self.max_depth }.into());
+        }
+        self.field_start = 0;
+        let header = PartHeader::parse(mem::take(&mut self.header), depth, default_type)?;
+        let content_type = header.content_type();
+        self.state = if let Some(boundary) = content_type.boundary() {
+            self.frames.push(Frame {
+                boundary: boundary.to_vec(),
+                depth,
+                default_type: default_part_type(content_type),
+            });
+            State::Preamble
+        } else if is_message(content_type) && is_identity(header.transfer_encoding()) {
+            State::Header {
+                depth: depth + 1,
+                default_type: ContentType::default(),
+            }
+        } else {
+            State::Body(header.transfer_encoding().decoder(Vec::new()))
+        };
+        self.pending_line_break = b"";
+        events.push(Event::HeaderEnd(header));
+        Ok(())
+    }
+
+    /// End the current part, and the parts that contain it, on a delimiter of the multipart at
+    /// the given index.
+    fn end_part(&mut self, index: usize, close: bool, events: &mut Vec<Event>) -> Result<(), Error> {
+        if let State::Body(decoder) = mem::replace(&mut self.state, State::Epilogue) {
+            // the line break that precedes the delimiter is part of the delimiter
+            let decoded = decoder.finish()?;
+            if !decoded.is_empty() {
+                events.push(Event::Body(decoded));
+            }
+        }
+        self.pending_line_break = b"";
+        events.push(Event::Boundary {
+            depth: self.frames[index].depth,
+            close,
+        });
+        self.frames.truncate(index + 1);
+        if close {
+            self.frames.pop();
+        } else {
+            let frame = &self.frames[index];
+            self.state = State::Header {
+                depth: frame.depth + 1,
+                default_type: frame.default_type.clone(),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// The events produced by a push parser, and not consumed yet.
+struct EventQueue {
+    parser: PushParser,
+    events: VecDeque<Event>,
+    /// The error that follows the pending events
+    error: Option<Error>,
+    /// `true` once the end of the message has been fed to the parser, or after an error
+    finished: bool,
+}
+
+impl EventQueue {
+    fn new(parser: PushParser) -> Self {
+        EventQueue {
+            parser,
+            events: VecDeque::new(),
+            error: None,
+            finished: false,
+        }
+    }
+
+    /// Feed a chunk to the parser, an empty chunk marking the end of the message.
+    fn push(&mut self, chunk: &[u8]) {
+        let mut events = Vec::new();
+        let result = if chunk.is_empty() {
+            self.finished = true;
+            self.parser.push_end(&mut events)
+        } else {
+            self.parser.push(chunk, &mut events)
+        };
+        // the events that precede an error are not lost, and the parser cannot go on
+        self.events.extend(events);
+        if let Err(error) = result {
+            self.error = Some(error);
+            self.finished = true;
+        }
+    }
+
+    /// Return the next event, `Ok(None)` at the end of the message, or `None` if the parser
+    /// needs more bytes.
+    fn pop(&mut self) -> Option<Result<Option<Event>, Error>> {
+        if let Some(event) = self.events.pop_front() {
+            Some(Ok(Some(event)))
+        } else if let Some(error) = self.error.take() {
+            Some(Err(error))
+        } else if self.finished {
+            Some(Ok(None))
+        } else {
+            None
+        }
+    }
+}
+
+/// A reader for messages too large to be kept in memory. The message is read from an underlying
+/// reader, one part after the other: the header section of each part is kept in memory, while
+/// its body is decoded with its transfer encoding as it is read.
+///
+/// Parts are visited in the same order as with [`MimePart::iter`]: the message first, then its
+/// parts and the messages it embeds, depth first. `MessageReader` implements `Read`, to read the
+/// decoded body of the current part. Containers (multiparts and embedded messages) have an empty
+/// body.
+///
+/// ```rust
+/// use std::io::Read;
+/// use imf::stream::MessageReader;
+///
+/// let input = &b"Content-Type: multipart/mixed; boundary=b\r\n\
+/// \r\n\
+/// --b\r\n\
+/// \r\n\
+/// text\r\n\
+/// --b\r\n\
+/// Content-Type: application/octet-stream\r\n\
+/// Content-Transfer-Encoding: base64\r\n\
+/// \r\n\
+/// AAEC\r\n\
+/// --b--\r\n"[..];
+/// let mut reader = MessageReader::new(input).unwrap();
+/// assert!(reader.header().content_type().is_multipart());
+///
+/// let mut bodies = Vec::new();
+/// while reader.next_part().unwrap().is_some() {
+///     let mut body = Vec::new();
+///     reader.read_to_end(&mut body).unwrap();
+///     bodies.push(body);
+/// }
+/// assert_eq!(bodies, vec![b"text".to_vec(), vec![0, 1, 2]]);
+/// ```
+///
+/// Memory usage is bounded: header sections cannot be larger than [`DEFAULT_MAX_HEADER_SIZE`],
+/// and parts cannot be nested deeper than [`DEFAULT_MAX_DEPTH`] (see [`with_limits`]). Unlike
+/// [`MimePart`], a multipart body without delimiter has no parts, instead of being a leaf.
+///
+/// [`MimePart::iter`]: ../mime_part/struct.MimePart.html#method.iter
+/// [`MimePart`]: ../mime_part/struct.MimePart.html
+/// [`DEFAULT_MAX_HEADER_SIZE`]: static.DEFAULT_MAX_HEADER_SIZE.html
+/// [`DEFAULT_MAX_DEPTH`]: ../mime_part/static.DEFAULT_MAX_DEPTH.html
+/// [`with_limits`]: #method.with_limits
+pub struct MessageReader<R: Read> {
+    reader: R,
+    events: EventQueue,
+    header: PartHeader,
+    /// The decoded bytes of the current part that have not been read yet
+    body: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> MessageReader<R> {
+    /// Read the header section of the message.
+    pub fn new(reader: R) -> Result<Self, Error> {
+        MessageReader::with_limits(reader, DEFAULT_MAX_HEADER_SIZE, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Read the header section of the message. If a header section is larger than
+    /// `max_header_size` bytes, `ErrorKind::TooLarge` is returned, and if the parts are nested
+    /// deeper than `max_depth`, `ErrorKind::TooDeep` is returned.
+    pub fn with_limits(reader: R, max_header_size: usize, max_depth: usize) -> Result<Self, Error> {
+        let mut message = MessageReader {
+            reader,
+            events: EventQueue::new(PushParser::with_limits(max_header_size, max_depth)),
+            header: PartHeader {
+                raw: Vec::new(),
+                depth: 0,
+                content_type: ContentType::default(),
+                transfer_encoding: TransferEncoding::SevenBit,
+            },
+            body: Vec::new(),
+            position: 0,
+        };
+        // the first event is always the header section of the message
+        message.next_part()?;
+        Ok(message)
+    }
+
+    /// Return the header section of the current part.
+    pub fn header(&self) -> &PartHeader {
+        &self.header
+    }
+
+    /// Skip the rest of the current part, and read the header section of the next part. `None`
+    /// is returned at the end of the message.
+    pub fn next_part(&mut self) -> Result<Option<&PartHeader>, Error> {
+        self.body.clear();
+        self.position = 0;
+        while let Some(event) = self.next_event()? {
+            if let Event::HeaderEnd(header) = event {
+                self.header = header;
+                return Ok(Some(&self.header));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            if let Some(result) = self.events.pop() {
+                return result;
+            }
+            let mut chunk = [0; CHUNK_SIZE];
+            let len = self.reader.read(&mut chunk)?;
+            self.events.push(&chunk[..len]);
+        }
+    }
+}
+
+impl<R: Read> Read for MessageReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.body.len() {
+            match self.next_event().map_err(io::Error::other)? {
+                Some(Event::Body(body)) => {
+                    self.body = body;
+                    self.position = 0;
+                }
+                Some(event) => {
+                    // the current part is over
+                    self.events.events.push_front(event);
+                    return Ok(0);
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.body.len() - self.position);
+        buf[..len].copy_from_slice(&self.body[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// The asynchronous counterpart of [`MessageReader`], available with the `async` feature: a
+/// stream of the events of a message read from a `tokio::io::AsyncRead`. Events are produced by
+/// a [`PushParser`], with the same limits: the header section of each part is
+/// [`Event::HeaderEnd`], and the decoded body of a part is made of the following
+/// [`Event::Body`] chunks.
+///
+/// After an error, the stream ends.
+///
+/// [`MessageReader`]: struct.MessageReader.html
+/// [`PushParser`]: struct.PushParser.html
+/// [`Event::HeaderEnd`]: enum.Event.html#variant.HeaderEnd
+/// [`Event::Body`]: enum.Event.html#variant.Body
+#[cfg(feature = "async")]
+pub struct EventStream<R: AsyncRead + Unpin> {
+    reader: R,
+    events: EventQueue,
+    chunk: Box<[u8]>,
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> EventStream<R> {
+    pub fn new(reader: R) -> Self {
+        EventStream::with_parser(reader, PushParser::new())
+    }
+
+    /// Create a stream whose events are produced by the given parser, to set its limits.
+    pub fn with_parser(reader: R, parser: PushParser) -> Self {
+        EventStream {
+            reader,
+            events: EventQueue::new(parser),
+            chunk: vec![0; CHUNK_SIZE].into_boxed_slice(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> Stream for EventStream<R> {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Event, Error>>> {
+        let stream = self.get_mut();
+        loop {
+            if let Some(result) = stream.events.pop() {
+                return Poll::Ready(result.transpose());
+            }
+            let mut buf = ReadBuf::new(&mut stream.chunk);
+            match Pin::new(&mut stream.reader).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(())) => stream.events.push(buf.filled()),
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mime_part::MimePart;
+
+    static MESSAGE: &[u8] = b"From: me\r\n\
+        Content-Type: multipart/mixed; boundary=outer\r\n\
+        \r\n\
+        preamble\r\n\
+        --outer\r\n\
+        \r\n\
+        implicitly typed\r\n\
+        --outer\r\n\
+        Content-Type: message/rfc822\r\n\
+        \r\n\
+        Subject: embedded\r\n\
+        Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+        \r\n\
+        --inner\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Transfer-Encoding: quoted-printable\r\n\
+        \r\n\
+        a long=\r\n line\r\n\
+        --inner\r\n\
+        Content-Type: text/html\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        PHA+aHRtbDwvcD4=\r\n\
+        --inner--\r\n\
+        --outer\r\n\
+        Content-Type: multipart/digest; boundary=digest\r\n\
+        \r\n\
+        --digest\r\n\
+        \r\n\
+        Subject: digest\r\n\
+        \r\n\
+        --not a delimiter\r\n\
+        --digest--\r\n\
+        --outer\r\n\
+        Content-Type: application/octet-stream\r\n\
+        \r\n\
+        \r\n\
+        last\r\n\
+        \r\n\
+        --outer--\r\n\
+        epilogue\r\n";
+
+    /// A reader that returns the bytes a few at a time.
+    struct Chunks<'a> {
+        bytes: &'a [u8],
+        size: usize,
+    }
+
+    impl<'a> Read for Chunks<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.size.min(buf.len()).min(self.bytes.len());
+            buf[..len].copy_from_slice(&self.bytes[..len]);
+            self.bytes = &self.bytes[len..];
+            Ok(len)
+        }
+    }
+
+    /// Read all the parts, and return their depth, content type and decoded body.
+    fn read_parts<R: Read>(reader: &mut MessageReader<R>) -> Vec<(usize, Vec<u8>, Vec<u8>)> {
+        let mut parts = Vec::new();
+        loop {
+            let header = reader.header();
+            let content_type = [header.content_type().mime_type(), header.content_type().subtype()].join(&b'/');
+            let depth = header.depth();
+            let mut body = Vec::new();
+            reader.read_to_end(&mut body).unwrap();
+            parts.push((depth, content_type, body));
+            if reader.next_part().unwrap().is_none() {
+                return parts;
+            }
+        }
+    }
+
+    #[test]
+    fn test_push_parser() {
+        let input = b"Content-Type: multipart/mixed;\r\n boundary=b\r\n\r\n\
+            --b\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            a=3Db\r\n\
+            --b--\r\n";
+        let expected = vec![
+            Event::Field {
+                name: b"Content-Type".to_vec(),
+                value: b" multipart/mixed;\r\n boundary=b".to_vec(),
+            },
+            Event::Boundary { depth: 0, close: false },
+            Event::Field {
+                name: b"Content-Transfer-Encoding".to_vec(),
+                value: b" quoted-printable".to_vec(),
+            },
+            Event::Body(b"a=b".to_vec()),
+            Event::Boundary { depth: 0, close: true },
+        ];
+
+        // events are the same, whatever the size of the chunks, except for the body
+        for size in [1, 5, input.len()].iter() {
+            let mut parser = PushParser::new();
+            let mut events = Vec::new();
+            let mut headers = Vec::new();
+            let mut body = Vec::new();
+            let chunks = input.chunks(*size).map(|chunk| parser.feed(chunk).unwrap()).collect::<Vec<_>>();
+            for event in chunks.into_iter().flatten().chain(parser.finish().unwrap()) {
+                match event {
+                    Event::HeaderEnd(header) => headers.push(header),
+                    Event::Body(bytes) => body.extend(bytes),
+                    event => events.push(event),
+                }
+            }
+            assert_eq!(headers.iter().map(|header| header.depth()).collect::<Vec<_>>(), vec![0, 1]);
+            assert!(headers[0].content_type().is_multipart());
+            events.insert(3, Event::Body(body));
+            assert_eq!(events, expected);
+        }
+
+        // a field is parsed once its last line is known
+        let mut parser = PushParser::new();
+        assert_eq!(parser.feed(b"Subject: a\r\n").unwrap(), vec![]);
+        assert_eq!(parser.feed(b" b\r\nX").unwrap().len(), 1);
+        let e = parser.feed(b" y\r\nTo: c\r\n").unwrap_err();
+        match *e.kind() {
+            ErrorKind::Token { byte, position, .. } => assert_eq!((byte, position), (b'y', 18)),
+            _ => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_event_stream() {
+        use std::task::Waker;
+
+        /// An asynchronous reader that is not ready every other time it is polled.
+        struct Pending<'a> {
+            bytes: &'a [u8],
+            ready: bool,
+        }
+
+        impl<'a> AsyncRead for Pending<'a> {
+            fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+                self.ready = !self.ready;
+                if !self.ready {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                let len = buf.remaining().min(self.bytes.len()).min(5);
+                buf.put_slice(&self.bytes[..len]);
+                self.bytes = &self.bytes[len..];
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let mut parser = PushParser::new();
+        let mut expected: Vec<Event> = MESSAGE.chunks(5).flat_map(|chunk| parser.feed(chunk).unwrap()).collect();
+        expected.extend(parser.finish().unwrap());
+
+        let mut stream = EventStream::new(Pending { bytes: MESSAGE, ready: false });
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut events = Vec::new();
+        let mut pending = 0;
+        loop {
+            match Pin::new(&mut stream).poll_next(&mut cx) {
+                Poll::Ready(Some(event)) => events.push(event.unwrap()),
+                Poll::Ready(None) => break,
+                Poll::Pending => pending += 1,
+            }
+        }
+        assert_eq!(events, expected);
+        assert!(pending > MESSAGE.len() / 5);
+
+        let parser = PushParser::with_limits(DEFAULT_MAX_HEADER_SIZE, 1);
+        let mut stream = EventStream::with_parser(MESSAGE, parser);
+        let mut errors = 0;
+        while let Poll::Ready(Some(event)) = Pin::new(&mut stream).poll_next(&mut cx) {
+            errors += event.is_err() as usize;
+        }
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn test_read_parts() {
+        // the parts are the same as with the parser of complete messages
+        let message = MimePart::parse(b!(MESSAGE)).unwrap();
+        let expected: Vec<(usize, Vec<u8>, Vec<u8>)> = message
+            .iter()
+            .map(|part| {
+                let mut body = Vec::new();
+                part.decode_body(&mut body).unwrap();
+                let depth = MessageReader::new(part.raw()).unwrap().header().depth();
+                let content_type = [part.content_type().mime_type(), part.content_type().subtype()].join(&b'/');
+                (depth, content_type, body)
+            })
+            .collect();
+        assert_eq!(expected.len(), 10);
+
+        for size in [1, 2, 7, 100, MESSAGE.len()].iter() {
+            let mut reader = MessageReader::new(Chunks { bytes: MESSAGE, size: *size }).unwrap();
+            let parts = read_parts(&mut reader);
+            let depths: Vec<usize> = parts.iter().map(|part| part.0).collect();
+            assert_eq!(depths, vec![0, 1, 1, 2, 3, 3, 1, 2, 3, 1]);
+            let parts: Vec<(Vec<u8>, Vec<u8>)> = parts.into_iter().map(|part| (part.1, part.2)).collect();
+            let expected: Vec<(Vec<u8>, Vec<u8>)> = expected.iter().map(|part| (part.1.clone(), part.2.clone())).collect();
+            assert_eq!(parts, expected);
+        }
+    }
+
+    #[test]
+    fn test_read_header() {
+        let mut reader = MessageReader::new(&b"Subject: a\r\n b\r\nTo: c\r\n\r\nbody\r\n"[..]).unwrap();
+        assert_eq!(reader.header().raw(), b"Subject: a\r\n b\r\nTo: c\r\n\r\n");
+        assert_eq!(reader.header().fields().len(), 2);
+        assert_eq!(reader.header().field(b"to").unwrap().value(), b" c");
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "body\r\n");
+        assert!(reader.next_part().unwrap().is_none());
+
+        // no body
+        let mut reader = MessageReader::new(&b"Subject: a"[..]).unwrap();
+        assert_eq!(reader.header().fields().len(), 1);
+        assert_eq!(reader.read(&mut [0; 10]).unwrap(), 0);
+
+        let e = MessageReader::new(&b"Subject: a\r\nnot a field\r\n\r\n"[..]).err().unwrap();
+        assert!(e.is_token());
+    }
+
+    #[test]
+    fn test_truncated_multipart() {
+        // the inner multipart is ended by a delimiter of the outer one, and the last part of
+        // the outer multipart by the end of the message
+        let input = b"Content-Type: multipart/mixed; boundary=outer\n\
+            \n\
+            --outer\n\
+            Content-Type: multipart/mixed; boundary=inner\n\
+            \n\
+            --inner\n\
+            \n\
+            inner\n\
+            --outer\n\
+            Content-Type: text/plain\n\
+            --outer\n\
+            \n\
+            last\n";
+        let mut reader = MessageReader::new(Chunks { bytes: input, size: 3 }).unwrap();
+        let bodies: Vec<(usize, Vec<u8>)> = read_parts(&mut reader).into_iter().map(|part| (part.0, part.2)).collect();
+        assert_eq!(bodies, vec![
+            (0, b"".to_vec()),
+            (1, b"".to_vec()),
+            (2, b"inner".to_vec()),
+            (1, b"".to_vec()),
+            (1, b"last\n".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_long_lines() {
+        // lines are not kept in memory, unless they may be delimiters
+        let mut input = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\n\r\n".to_vec();
+        let line = vec![b'-'; 3 * MAX_LINE_LENGTH];
+        input.extend_from_slice(&line);
+        input.extend_from_slice(b"\r\n--b--");
+        let mut reader = MessageReader::new(Chunks { bytes: &input, size: 100 }).unwrap();
+        reader.next_part().unwrap();
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body, line);
+    }
+
+    #[test]
+    fn test_limits() {
+        let e = MessageReader::with_limits(&b"Subject: a\r\nTo: b\r\n\r\n"[..], 15, 0).err().unwrap();
+        match *e.kind() {
+            ErrorKind::TooLarge { max_size } => assert_eq!(max_size, 15),
+            _ => panic!("unexpected error {:?}", e),
+        }
+        assert!(MessageReader::with_limits(&b"Subject: a\r\nTo: b\r\n\r\n"[..], 21, 0).is_ok());
+
+        let mut reader = MessageReader::with_limits(MESSAGE, DEFAULT_MAX_HEADER_SIZE, 2).unwrap();
+        let e = loop {
+            if let Err(e) = reader.next_part() {
+                break e;
+            }
+        };
+        match *e.kind() {
+            ErrorKind::TooDeep { max_depth } => assert_eq!(max_depth, 2),
+            _ => panic!("unexpected error {:?}", e),
+        }
+    }
+}
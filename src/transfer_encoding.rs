@@ -0,0 +1,494 @@
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "std")]
+use std::mem;
+
+use errors::Error;
+use whitespaces::skip_cfws;
+use mime::skip_token;
+use base64::{Base64Decoder, Base64Encoder};
+use quoted_printable::{QuotedPrintableDecoder, QuotedPrintableEncoder};
+use sink::Sink;
+use Buffer;
+
+/// A content transfer encoding mechanism. See [RFC2045 section 6.1].
+///
+/// ```no_rust
+/// encoding := "Content-Transfer-Encoding" ":" mechanism
+/// mechanism := "7bit" / "8bit" / "binary" /
+///              "quoted-printable" / "base64" /
+///              ietf-token / x-token
+/// ```
+///
+/// [RFC2045 section 6.1]: https://tools.ietf.org/html/rfc2045#section-6.1
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum TransferEncoding {
+    SevenBit,
+    EightBit,
+    Binary,
+    QuotedPrintable,
+    Base64,
+}
+
+impl TransferEncoding {
+    /// Return the encoding corresponding to the given mechanism name. Mechanism names are case
+    /// insensitive. `None` is returned for unknown mechanisms.
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        let encodings = [
+            TransferEncoding::SevenBit,
+            TransferEncoding::EightBit,
+            TransferEncoding::Binary,
+            TransferEncoding::QuotedPrintable,
+            TransferEncoding::Base64,
+        ];
+        encodings
+            .iter()
+            .find(|encoding| encoding.name().as_bytes().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// Parse the value of a `Content-Transfer-Encoding` field. On success, the encoding and the
+    /// number of bytes read are returned. The encoding is `None` if the mechanism is not known.
+    pub fn parse(input: &Buffer) -> Result<(Option<Self>, usize), Error> {
+        let bytes = input.remaining();
+        let pos = input.position();
+        let mut buffer = input.clone();
+
+        // read [CFWS] mechanism [CFWS]
+        let mut i = skip_cfws(&buffer).unwrap_or(0);
+        buffer.set_position(pos + i);
+        let len = skip_token(&buffer)?;
+        let encoding = TransferEncoding::from_name(&bytes[i..i + len]);
+        i += len;
+        buffer.set_position(pos + i);
+        i += skip_cfws(&buffer).unwrap_or(0);
+        Ok((encoding, i))
+    }
+
+    /// Return the canonical name of the mechanism.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TransferEncoding::SevenBit => "7bit",
+            TransferEncoding::EightBit => "8bit",
+            TransferEncoding::Binary => "binary",
+            TransferEncoding::QuotedPrintable => "quoted-printable",
+            TransferEncoding::Base64 => "base64",
+        }
+    }
+
+    /// Return a streaming decoder for this encoding, that writes the decoded bytes into the given
+    /// writer.
+    pub fn decoder<W: Sink>(&self, writer: W) -> Decoder<W> {
+        match *self {
+            TransferEncoding::SevenBit | TransferEncoding::EightBit | TransferEncoding::Binary => {
+                Decoder::Identity(writer)
+            }
+            TransferEncoding::QuotedPrintable => {
+                Decoder::QuotedPrintable(QuotedPrintableDecoder::new(writer))
+            }
+            TransferEncoding::Base64 => Decoder::Base64(Base64Decoder::new(writer)),
+        }
+    }
+
+    /// Return a streaming encoder for this encoding, that writes the encoded bytes into the given
+    /// writer. The encoders use their default settings.
+    pub fn encoder<W: Sink>(&self, writer: W) -> Encoder<W> {
+        match *self {
+            TransferEncoding::SevenBit | TransferEncoding::EightBit | TransferEncoding::Binary => {
+                Encoder::Identity(writer)
+            }
+            TransferEncoding::QuotedPrintable => {
+                Encoder::QuotedPrintable(QuotedPrintableEncoder::new(writer))
+            }
+            TransferEncoding::Base64 => Encoder::Base64(Base64Encoder::new(writer)),
+        }
+    }
+}
+
+/// A streaming decoder for any of the transfer encodings. `7bit`, `8bit` and `binary` are identity
+/// encodings: the bytes are written as is into the underlying writer.
+pub enum Decoder<W: Sink> {
+    Identity(W),
+    QuotedPrintable(QuotedPrintableDecoder<W>),
+    Base64(Base64Decoder<W>),
+}
+
+impl<W: Sink> Decoder<W> {
+    pub fn get_ref(&self) -> &W {
+        match *self {
+            Decoder::Identity(ref writer) => writer,
+            Decoder::QuotedPrintable(ref decoder) => decoder.get_ref(),
+            Decoder::Base64(ref decoder) => decoder.get_ref(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        match *self {
+            Decoder::Identity(ref mut writer) => writer,
+            Decoder::QuotedPrintable(ref mut decoder) => decoder.get_mut(),
+            Decoder::Base64(ref mut decoder) => decoder.get_mut(),
+        }
+    }
+
+    /// Flush the bytes that the decoder may still be holding, and return the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        match self {
+            Decoder::Identity(writer) => Ok(writer),
+            Decoder::QuotedPrintable(decoder) => decoder.finish(),
+            Decoder::Base64(decoder) => decoder.finish(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Sink> Write for Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Decoder::Identity(ref mut writer) => {
+                writer.write_bytes(buf)?;
+                Ok(buf.len())
+            }
+            Decoder::QuotedPrintable(ref mut decoder) => decoder.write(buf),
+            Decoder::Base64(ref mut decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Decoder::Identity(ref mut writer) => Ok(writer.flush_sink()?),
+            Decoder::QuotedPrintable(ref mut decoder) => decoder.flush(),
+            Decoder::Base64(ref mut decoder) => decoder.flush(),
+        }
+    }
+}
+
+impl<W: Sink> Sink for Decoder<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match *self {
+            Decoder::Identity(ref mut writer) => writer.write_bytes(bytes),
+            Decoder::QuotedPrintable(ref mut decoder) => decoder.write_bytes(bytes),
+            Decoder::Base64(ref mut decoder) => decoder.write_bytes(bytes),
+        }
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        match *self {
+            Decoder::Identity(ref mut writer) => writer.flush_sink(),
+            Decoder::QuotedPrintable(ref mut decoder) => decoder.flush_sink(),
+            Decoder::Base64(ref mut decoder) => decoder.flush_sink(),
+        }
+    }
+}
+
+/// A reader that decodes the content read from another reader.
+#[cfg(feature = "std")]
+pub struct DecodingReader<R: Read> {
+    reader: R,
+    /// `None` once the underlying reader is exhausted
+    decoder: Option<Decoder<Vec<u8>>>,
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> DecodingReader<R> {
+    pub fn new(encoding: TransferEncoding, reader: R) -> Self {
+        DecodingReader {
+            reader,
+            decoder: Some(encoding.decoder(Vec::new())),
+            decoded: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decoded.len() {
+            self.decoded.clear();
+            self.position = 0;
+            let mut chunk = [0; 4096];
+            let len = match self.decoder {
+                Some(_) => self.reader.read(&mut chunk)?,
+                None => return Ok(0),
+            };
+            if len == 0 {
+                let decoder = self.decoder.take().unwrap();
+                self.decoded = decoder.finish().map_err(io::Error::other)?;
+            } else {
+                let decoder = self.decoder.as_mut().unwrap();
+                decoder.write_all(&chunk[..len])?;
+                mem::swap(&mut self.decoded, decoder.get_mut());
+            }
+        }
+        let len = buf.len().min(self.decoded.len() - self.position);
+        buf[..len].copy_from_slice(&self.decoded[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// A streaming encoder for any of the transfer encodings. `7bit`, `8bit` and `binary` are identity
+/// encodings: the bytes are written as is into the underlying writer.
+pub enum Encoder<W: Sink> {
+    Identity(W),
+    QuotedPrintable(QuotedPrintableEncoder<W>),
+    Base64(Base64Encoder<W>),
+}
+
+impl<W: Sink> Encoder<W> {
+    pub fn get_ref(&self) -> &W {
+        match *self {
+            Encoder::Identity(ref writer) => writer,
+            Encoder::QuotedPrintable(ref encoder) => encoder.get_ref(),
+            Encoder::Base64(ref encoder) => encoder.get_ref(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        match *self {
+            Encoder::Identity(ref mut writer) => writer,
+            Encoder::QuotedPrintable(ref mut encoder) => encoder.get_mut(),
+            Encoder::Base64(ref mut encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Flush the bytes that the encoder may still be holding, and return the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        match self {
+            Encoder::Identity(writer) => Ok(writer),
+            Encoder::QuotedPrintable(encoder) => encoder.finish(),
+            Encoder::Base64(encoder) => encoder.finish(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Sink> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Encoder::Identity(ref mut writer) => {
+                writer.write_bytes(buf)?;
+                Ok(buf.len())
+            }
+            Encoder::QuotedPrintable(ref mut encoder) => encoder.write(buf),
+            Encoder::Base64(ref mut encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Encoder::Identity(ref mut writer) => Ok(writer.flush_sink()?),
+            Encoder::QuotedPrintable(ref mut encoder) => encoder.flush(),
+            Encoder::Base64(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl<W: Sink> Sink for Encoder<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match *self {
+            Encoder::Identity(ref mut writer) => writer.write_bytes(bytes),
+            Encoder::QuotedPrintable(ref mut encoder) => encoder.write_bytes(bytes),
+            Encoder::Base64(ref mut encoder) => encoder.write_bytes(bytes),
+        }
+    }
+
+    fn flush_sink(&mut self) -> Result<(), Error> {
+        match *self {
+            Encoder::Identity(ref mut writer) => writer.flush_sink(),
+            Encoder::QuotedPrintable(ref mut encoder) => encoder.flush_sink(),
+            Encoder::Base64(ref mut encoder) => encoder.flush_sink(),
+        }
+    }
+}
+
+/// Return the cheapest encoding that can be used to transport the given content over a 7bit
+/// channel.
+///
+/// `7bit` is picked if the content is made of lines of at most 998 US-ASCII characters, other than
+/// NUL, separated by CRLF (see [RFC2045 section 2.7]). Otherwise, quoted-printable is picked if
+/// its output would not be larger than the base64 output, which is the case when less than about
+/// one byte out of six has to be escaped.
+///
+/// Base64 is always picked for content with a CR or a LF that is not part of a CRLF: the
+/// quoted-printable encoder writes line breaks as CRLF, so the decoded content would differ.
+///
+/// [RFC2045 section 2.7]: https://tools.ietf.org/html/rfc2045#section-2.7
+pub fn choose_encoding(body: &[u8]) -> TransferEncoding {
+    let mut escaped: usize = 0;
+    let mut line_length: usize = 0;
+    let mut seven_bit = true;
+    for (i, &c) in body.iter().enumerate() {
+        match c {
+            b'\n' => {
+                if i == 0 || body[i - 1] != b'\r' {
+                    return TransferEncoding::Base64;
+                }
+                line_length = 0;
+                continue;
+            }
+            b'\r' if body.get(i + 1) != Some(&b'\n') => return TransferEncoding::Base64,
+            // the CR of a CRLF is part of the line break, not of the line
+            b'\r' => continue,
+            b'=' => escaped += 1,
+            c if c == 0 || c > 127 => {
+                seven_bit = false;
+                escaped += 1;
+            }
+            c if c < 32 && c != b'\t' => escaped += 1,
+            _ => {}
+        }
+        line_length += 1;
+        if line_length > 998 {
+            seven_bit = false;
+        }
+    }
+    if seven_bit {
+        return TransferEncoding::SevenBit;
+    }
+    // Each escaped byte takes two more characters in quoted-printable, whereas base64 makes the
+    // content grow by a third.
+    if escaped * 2 * 3 <= body.len() {
+        TransferEncoding::QuotedPrintable
+    } else {
+        TransferEncoding::Base64
+    }
+}
+
+/// Encode content with the given transfer encoding, and write the encoded bytes into the provided
+/// writer. The whole input is consumed, and the number of bytes read is returned.
+pub fn encode<W: Sink>(
+    encoding: TransferEncoding,
+    input: &Buffer,
+    writer: &mut W,
+) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let mut encoder = encoding.encoder(writer);
+    encoder.write_bytes(bytes)?;
+    encoder.finish()?;
+    Ok(bytes.len())
+}
+
+/// Decode content encoded with the given transfer encoding, and write the decoded bytes into the
+/// provided writer. The whole input is consumed, and the number of bytes read is returned.
+pub fn decode<W: Sink>(
+    encoding: TransferEncoding,
+    input: &Buffer,
+    writer: &mut W,
+) -> Result<usize, Error> {
+    let bytes = input.remaining();
+    let mut decoder = encoding.decoder(writer);
+    decoder.write_bytes(bytes)?;
+    decoder.finish()?;
+    Ok(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prelude::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(TransferEncoding::from_name(b"7bit"), Some(TransferEncoding::SevenBit));
+        assert_eq!(TransferEncoding::from_name(b"8BIT"), Some(TransferEncoding::EightBit));
+        assert_eq!(TransferEncoding::from_name(b"Binary"), Some(TransferEncoding::Binary));
+        assert_eq!(
+            TransferEncoding::from_name(b"Quoted-Printable"),
+            Some(TransferEncoding::QuotedPrintable)
+        );
+        assert_eq!(TransferEncoding::from_name(b"BASE64"), Some(TransferEncoding::Base64));
+        assert_eq!(TransferEncoding::from_name(b"x-uuencode"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            TransferEncoding::parse(b!(b" Base64 (comment)\r\n ")).unwrap(),
+            (Some(TransferEncoding::Base64), 20)
+        );
+        assert_eq!(TransferEncoding::parse(b!(b"x-uuencode")).unwrap(), (None, 10));
+        assert!(TransferEncoding::parse(b!(b" ")).unwrap_err().is_eof());
+        assert!(TransferEncoding::parse(b!(b"\"7bit\"")).unwrap_err().is_token());
+    }
+
+    #[test]
+    fn test_choose_encoding() {
+        assert_eq!(choose_encoding(b""), TransferEncoding::SevenBit);
+        assert_eq!(choose_encoding(b"Hello,\r\n\tworld!\r\n"), TransferEncoding::SevenBit);
+        let long_line = [b'a'; 999];
+        assert_eq!(choose_encoding(&long_line[..998]), TransferEncoding::SevenBit);
+        assert_eq!(choose_encoding(&long_line[..]), TransferEncoding::QuotedPrintable);
+        let crlf_line = [&long_line[..998], b"\r\n", &long_line[..998], b"\r\n"].concat();
+        assert_eq!(choose_encoding(&crlf_line), TransferEncoding::SevenBit);
+        assert_eq!(choose_encoding(b"bare\nLF"), TransferEncoding::Base64);
+        assert_eq!(choose_encoding(b"bare\rCR"), TransferEncoding::Base64);
+        assert_eq!(choose_encoding(b"\nLF"), TransferEncoding::Base64);
+        assert_eq!(choose_encoding("caf\u{e9}\r\nbare\nLF".as_bytes()), TransferEncoding::Base64);
+        assert_eq!(
+            choose_encoding("Le café est prêt, venez le boire tant qu'il est chaud.".as_bytes()),
+            TransferEncoding::QuotedPrintable
+        );
+        assert_eq!(choose_encoding("日本語のテキスト".as_bytes()), TransferEncoding::Base64);
+        assert_eq!(choose_encoding(b"\x00\x01\x02\xff"), TransferEncoding::Base64);
+    }
+
+    #[test]
+    fn test_encode() {
+        let cases: [(TransferEncoding, &[u8], &[u8]); 5] = [
+            (TransferEncoding::SevenBit, b"a=41\r\n", b"a=41\r\n"),
+            (TransferEncoding::EightBit, b"\xc3\xa9 \r\n", b"\xc3\xa9 \r\n"),
+            (TransferEncoding::Binary, b"\x00\xff", b"\x00\xff"),
+            (TransferEncoding::QuotedPrintable, b"a=41 \r\n", b"a=3D41=20\r\n"),
+            (TransferEncoding::Base64, b"aA", b"YUE="),
+        ];
+        for &(encoding, input, expected) in cases.iter() {
+            let mut output = Vec::new();
+            assert_eq!(encode(encoding, b!(input), &mut output).unwrap(), input.len());
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decoding_reader() {
+        let encoded: Vec<u8> = (0..3000).flat_map(|_| b"Zm9vYmFy\r\n".iter().cloned()).collect();
+        let mut reader = DecodingReader::new(TransferEncoding::Base64, &encoded[..]);
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded.len(), 18000);
+        assert!(decoded.chunks(6).all(|chunk| chunk == b"foobar"));
+
+        let mut reader = DecodingReader::new(TransferEncoding::QuotedPrintable, &b"caf=C3=A9="[..]);
+        let mut decoded = String::new();
+        reader.read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "café");
+        assert_eq!(reader.read(&mut [0; 10]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_decode() {
+        let cases: [(TransferEncoding, &[u8], &[u8]); 5] = [
+            (TransferEncoding::SevenBit, b"a=41\r\n", b"a=41\r\n"),
+            (TransferEncoding::EightBit, b"\xc3\xa9 \r\n", b"\xc3\xa9 \r\n"),
+            (TransferEncoding::Binary, b"\x00\xff", b"\x00\xff"),
+            (TransferEncoding::QuotedPrintable, b"a=41 \r\n", b"aA\r\n"),
+            (TransferEncoding::Base64, b"YUE=\r\n", b"aA"),
+        ];
+        for &(encoding, input, expected) in cases.iter() {
+            let mut output = Vec::new();
+            assert_eq!(decode(encoding, b!(input), &mut output).unwrap(), input.len());
+            assert_eq!(output, expected);
+        }
+    }
+}
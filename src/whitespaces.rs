@@ -1,6 +1,9 @@
+use common::utf8_non_ascii_len;
+use cow::parse_cow;
 use errors::{ErrorKind, Error, Token};
+use sink::Sink;
 use Buffer;
-use std::io::Write;
+use alloc::borrow::Cow;
 
 /// CRLF sequence (`\r\n`)
 pub static CRLF: [u8; 2] = *b"\r\n";
@@ -71,7 +74,7 @@ pub fn skip_fws(input: &Buffer) -> Result<usize, Error> {
 /// ```
 ///
 /// [RFC5322 section 2.2.3]: https://tools.ietf.org/html/rfc5322#section-2.2.3
-pub fn unfold_fws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+pub fn unfold_fws<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
     let bytes = input.remaining();
     if bytes.is_empty() {
         return Err(ErrorKind::Eof.into());
@@ -84,7 +87,7 @@ pub fn unfold_fws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Err
             c if is_wsp(c) => i += 1,
             // CRLF
             b'\r' => {
-                writer.write_all(&bytes[next_write..i])?;
+                writer.write_bytes(&bytes[next_write..i])?;
                 // we need to match LF and then a space
                 if i + 2 < bytes.len() && bytes[i + 1] == b'\n' && is_wsp(bytes[i + 2]) {
                     next_write = i + 2;
@@ -103,10 +106,18 @@ pub fn unfold_fws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Err
             position: input.position(),
         }));
     }
-    writer.write_all(&bytes[next_write..i])?;
+    writer.write_bytes(&bytes[next_write..i])?;
     Ok(i)
 }
 
+/// Parse a folding whitespace, and return it unfolded, borrowed from the input if it is not
+/// folded. See [`unfold_fws`].
+///
+/// [`unfold_fws`]: fn.unfold_fws.html
+pub fn unfold_fws_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, unfold_fws)
+}
+
 /// Read comments. See [RFC5322 section 3.2.3]
 ///
 /// ```no_rust
@@ -124,7 +135,10 @@ pub fn unfold_fws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Err
 /// comment         =       "(" *([FWS] ccontent) [FWS] ")"
 /// ```
 ///
+/// To read the content of the comment, use [`Comment::parse`].
+///
 /// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
+/// [`Comment::parse`]: ../comment/struct.Comment.html#method.parse
 pub fn skip_comment(input: &Buffer) -> Result<usize, Error> {
     let bytes = input.remaining();
     if bytes.is_empty() {
@@ -145,7 +159,7 @@ pub fn skip_comment(input: &Buffer) -> Result<usize, Error> {
         match bytes[i] {
             b'\\' => {
                 // we want to ignore the next character, since it's escaped
-                i += 2;
+                i += 1 + utf8_non_ascii_len(input, i + 1, Token::Comment)?.unwrap_or(1);
                 continue;
             }
             b')' => {
@@ -155,6 +169,11 @@ pub fn skip_comment(input: &Buffer) -> Result<usize, Error> {
                 }
             }
             b'(' => nested_level += 1,
+            // ctext =/ UTF8-non-ascii
+            c if c > 127 && input.accepts_utf8() => {
+                i += utf8_non_ascii_len(input, i, Token::Comment)?.unwrap_or(1);
+                continue;
+            }
             // ignore any other character
             _ => {}
         }
@@ -208,21 +227,22 @@ pub fn skip_cfws(input: &Buffer) -> Result<usize, Error> {
         }
     }
 
+    // the CFWS ends with a comment, at the end of the buffer
     assert!(i == bytes.len());
-    Err(ErrorKind::Eof.into())
+    Ok(i)
 }
 
-pub fn replace_cfws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+pub fn replace_cfws<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
     let len = skip_cfws(input)?;
     // If we're here, then we read a CFWS. Let's replace it by a single space.
-    writer.write_all(&b" "[..])?;
+    writer.write_bytes(&b" "[..])?;
     Ok(len)
 }
 
-pub fn replace_fws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+pub fn replace_fws<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
     let len = skip_fws(input)?;
     // If we're here, then we read a CFWS. Let's replace it by a single space.
-    writer.write_all(&b" "[..])?;
+    writer.write_bytes(&b" "[..])?;
     Ok(len)
 }
 
@@ -233,7 +253,7 @@ pub fn replace_fws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Er
 /// ```
 ///
 /// [RFC5322 section 3.2.3]: https://tools.ietf.org/html/rfc5322#section-3.2.3
-pub fn unfold_cfws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
+pub fn unfold_cfws<W: Sink>(input: &Buffer, writer: &mut W) -> Result<usize, Error> {
     let bytes = input.remaining();
     if bytes.is_empty() {
         return Err(ErrorKind::Eof.into());
@@ -280,6 +300,14 @@ pub fn unfold_cfws<W: Write>(input: &Buffer, writer: &mut W) -> Result<usize, Er
     Ok(i)
 }
 
+/// Parse comments and folding whitespaces, and return them unfolded, borrowed from the input if
+/// possible. See [`unfold_cfws`].
+///
+/// [`unfold_cfws`]: fn.unfold_cfws.html
+pub fn unfold_cfws_cow<'buf>(input: &Buffer<'buf>) -> Result<(Cow<'buf, [u8]>, usize), Error> {
+    parse_cow(input, unfold_cfws)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -297,6 +325,9 @@ mod tests {
         eof!(skip_comment, b"(comment()comment");
         tok!(skip_comment, b"fail", Token::Comment, b'f', 0);
         tok!(skip_comment, b"\\(", Token::Comment, b'\\', 0);
+        // UTF-8
+        assert_eq!(skip_comment(u!("(€)")).unwrap(), 5);
+        enc!(skip_comment, b"(\xe2\x82)", Token::Comment, 1);
     }
 
     #[test]
@@ -343,5 +374,6 @@ mod tests {
         ok!(skip_cfws, b"  (a comment)abc", 13);
         ok!(skip_cfws, b"  (  a comment ( ) ()\r\n)  abc", 26);
         ok!(skip_cfws, b"(a comment)  () ()abc", 18);
+        ok!(skip_cfws, b" (a comment)", 12);
     }
 }